@@ -0,0 +1,102 @@
+// Wrap a flat M0 binary in an m0image header.
+//
+//   m0pack <input.bin> <output.img> <load address> [vector offset] [build id]
+//
+// Addresses take a 0x prefix for hex; the build ID is up to 40 hex digits
+// (eg. `git rev-parse HEAD`).
+
+extern crate m0image;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+
+use m0image::{Header, BUILD_ID_SIZE, HEADER_SIZE};
+
+fn parse_u32(arg: &str) -> Option<u32> {
+    if arg.starts_with("0x") || arg.starts_with("0X") {
+        u32::from_str_radix(&arg[2..], 16).ok()
+    } else {
+        arg.parse().ok()
+    }
+}
+
+fn parse_build_id(arg: &str) -> Option<[u8; BUILD_ID_SIZE]> {
+    let mut id = [0u8; BUILD_ID_SIZE];
+
+    // the slicing below goes by bytes, so anything else could split a
+    // character
+    if !arg.is_ascii() || arg.len() % 2 != 0 || arg.len() > BUILD_ID_SIZE * 2 {
+        return None;
+    }
+
+    for (i, byte) in id.iter_mut().enumerate().take(arg.len() / 2) {
+        match u8::from_str_radix(&arg[i * 2..i * 2 + 2], 16) {
+            Ok(b) => *byte = b,
+            Err(_) => return None,
+        }
+    }
+
+    Some(id)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: m0pack <input.bin> <output.img> <load address> [vector offset] [build id]");
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 || args.len() > 6 {
+        usage();
+    }
+
+    let load_address = parse_u32(&args[3]).unwrap_or_else(|| usage());
+    let vector_offset = args.get(4).map(|a| parse_u32(a).unwrap_or_else(|| usage())).unwrap_or(0);
+    let build_id = args.get(5).map(|a| parse_build_id(a).unwrap_or_else(|| usage()))
+        .unwrap_or([0; BUILD_ID_SIZE]);
+
+    let mut payload = Vec::new();
+    File::open(&args[1]).and_then(|mut f| f.read_to_end(&mut payload)).unwrap_or_else(|e| {
+        eprintln!("m0pack: can't read {}: {}", args[1], e);
+        process::exit(1);
+    });
+
+    let header = Header::new(&payload, load_address, vector_offset, build_id);
+    if let Err(e) = header.check_payload(&payload) {
+        eprintln!("m0pack: bad image: {:?}", e);
+        process::exit(1);
+    }
+
+    let mut buf = [0u8; HEADER_SIZE];
+    header.write(&mut buf);
+
+    File::create(&args[2])
+        .and_then(|mut f| f.write_all(&buf).and_then(|_| f.write_all(&payload)))
+        .unwrap_or_else(|e| {
+            eprintln!("m0pack: can't write {}: {}", args[2], e);
+            process::exit(1);
+        });
+
+    println!("m0pack: {} bytes, load 0x{:08x}, entry 0x{:08x}, crc32 0x{:08x}",
+        header.size, header.load_address, header.entry(), header.crc32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_id() {
+        let id = parse_build_id("0123abCD").unwrap();
+        assert_eq!(&id[..5], &[0x01, 0x23, 0xab, 0xcd, 0]);
+
+        assert_eq!(parse_build_id("012"), None);
+        assert_eq!(parse_build_id("zz"), None);
+        assert_eq!(parse_build_id(&"00".repeat(BUILD_ID_SIZE + 1)), None);
+
+        // four bytes, but the second pair would split the 'é'
+        assert_eq!(parse_build_id("a\u{e9}0"), None);
+    }
+}
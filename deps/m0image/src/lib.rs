@@ -0,0 +1,278 @@
+//! Container format for M0 firmware images.
+//!
+//! A fixed 64 byte header, all little endian, followed by the raw binary:
+//!
+//! ```text
+//!  0  magic          "M0IM"
+//!  4  version        u16, HEADER_VERSION
+//!  6  header_size    u16, HEADER_SIZE
+//!  8  load_address   u32, where the payload has to be copied to
+//! 12  vector_offset  u32, offset of the vector table in the payload
+//! 16  size           u32, payload size in bytes
+//! 20  crc32          u32, of the payload (IEEE 802.3)
+//! 24  build_id       20 bytes, usually a git commit hash
+//! 44  reserved       zero
+//! ```
+//!
+//! Shared between feo, which checks images before starting the M0 on them,
+//! and the `m0pack` host tool which builds them.
+
+#![no_std]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+pub const MAGIC: [u8; 4] = [b'M', b'0', b'I', b'M'];
+pub const HEADER_VERSION: u16 = 1;
+pub const HEADER_SIZE: usize = 64;
+pub const BUILD_ID_SIZE: usize = 20;
+
+/// An error from checking an image
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Shorter than its header says it is
+    Truncated,
+
+    /// Doesn't start with `MAGIC`
+    BadMagic,
+
+    /// Header is from a version of the format we don't know
+    UnsupportedVersion(u16),
+
+    /// Payload doesn't match its checksum
+    BadCrc { expected: u32, actual: u32 },
+
+    /// Vector table isn't inside the payload, or isn't word aligned
+    BadVectorOffset,
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub version: u16,
+    pub load_address: u32,
+    pub vector_offset: u32,
+    pub size: u32,
+    pub crc32: u32,
+    pub build_id: [u8; BUILD_ID_SIZE],
+}
+
+impl Header {
+    /// Header for `payload`, with its size and checksum filled in.
+    pub fn new(payload: &[u8], load_address: u32, vector_offset: u32,
+        build_id: [u8; BUILD_ID_SIZE]) -> Header {
+        Header {
+            version: HEADER_VERSION,
+            load_address,
+            vector_offset,
+            size: payload.len() as u32,
+            crc32: crc32(payload),
+            build_id,
+        }
+    }
+
+    /// Where the core starts: the vector table, once loaded.
+    pub fn entry(&self) -> u32 {
+        self.load_address.wrapping_add(self.vector_offset)
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Header, Error> {
+        if buf.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        if buf[0..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let version = read_u16(&buf[4..]);
+        if version != HEADER_VERSION || read_u16(&buf[6..]) as usize != HEADER_SIZE {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let mut build_id = [0u8; BUILD_ID_SIZE];
+        build_id.copy_from_slice(&buf[24..24 + BUILD_ID_SIZE]);
+
+        Ok(Header {
+            version,
+            load_address: read_u32(&buf[8..]),
+            vector_offset: read_u32(&buf[12..]),
+            size: read_u32(&buf[16..]),
+            crc32: read_u32(&buf[20..]),
+            build_id,
+        })
+    }
+
+    pub fn write(&self, buf: &mut [u8; HEADER_SIZE]) {
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+
+        buf[0..4].copy_from_slice(&MAGIC);
+        write_u16(&mut buf[4..], self.version);
+        write_u16(&mut buf[6..], HEADER_SIZE as u16);
+        write_u32(&mut buf[8..], self.load_address);
+        write_u32(&mut buf[12..], self.vector_offset);
+        write_u32(&mut buf[16..], self.size);
+        write_u32(&mut buf[20..], self.crc32);
+        buf[24..24 + BUILD_ID_SIZE].copy_from_slice(&self.build_id);
+    }
+
+    /// Check `payload` is what this header describes.
+    pub fn check_payload(&self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() != self.size as usize {
+            return Err(Error::Truncated);
+        }
+
+        if self.vector_offset % 4 != 0 || self.vector_offset >= self.size {
+            return Err(Error::BadVectorOffset);
+        }
+
+        let actual = crc32(payload);
+        if actual != self.crc32 {
+            return Err(Error::BadCrc { expected: self.crc32, actual });
+        }
+
+        Ok(())
+    }
+}
+
+/// Check an image end to end, returning its header and payload.
+pub fn verify(image: &[u8]) -> Result<(Header, &[u8]), Error> {
+    let header = Header::parse(image)?;
+
+    let end = HEADER_SIZE.checked_add(header.size as usize).ok_or(Error::Truncated)?;
+    if image.len() < end {
+        return Err(Error::Truncated);
+    }
+
+    let payload = &image[HEADER_SIZE..end];
+    header.check_payload(payload)?;
+
+    Ok((header, payload))
+}
+
+/// CRC-32 (IEEE 802.3, as used by zlib), a bit at a time; images are small
+/// and this only runs once per load.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    buf[0] as u16 | (buf[1] as u16) << 8
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+fn write_u16(buf: &mut [u8], value: u16) {
+    buf[0] = value as u8;
+    buf[1] = (value >> 8) as u8;
+}
+
+fn write_u32(buf: &mut [u8], value: u32) {
+    buf[0] = value as u8;
+    buf[1] = (value >> 8) as u8;
+    buf[2] = (value >> 16) as u8;
+    buf[3] = (value >> 24) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const LOAD_ADDRESS: u32 = 0x250000;
+
+    fn payload() -> Vec<u8> {
+        (0..64).collect()
+    }
+
+    // as m0pack writes it
+    fn pack(payload: &[u8], vector_offset: u32) -> Vec<u8> {
+        let header = Header::new(payload, LOAD_ADDRESS, vector_offset, [0xab; BUILD_ID_SIZE]);
+
+        let mut buf = [0u8; HEADER_SIZE];
+        header.write(&mut buf);
+
+        let mut image = buf.to_vec();
+        image.extend_from_slice(payload);
+        image
+    }
+
+    #[test]
+    fn crc() {
+        // the standard check value
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let payload = payload();
+        let image = pack(&payload, 0x10);
+
+        let (header, loaded) = verify(&image).unwrap();
+        assert_eq!(header, Header::new(&payload, LOAD_ADDRESS, 0x10, [0xab; BUILD_ID_SIZE]));
+        assert_eq!(loaded, &payload[..]);
+        assert_eq!(header.entry(), LOAD_ADDRESS + 0x10);
+
+        // anything after the payload isn't part of it
+        let mut padded = image.clone();
+        padded.extend_from_slice(&[0xff; 8]);
+        assert_eq!(verify(&padded).unwrap().1, &payload[..]);
+    }
+
+    #[test]
+    fn truncated() {
+        let image = pack(&payload(), 0);
+
+        assert_eq!(Header::parse(&image[..HEADER_SIZE - 1]), Err(Error::Truncated));
+        assert_eq!(verify(&image[..HEADER_SIZE - 1]), Err(Error::Truncated));
+        assert_eq!(verify(&image[..image.len() - 1]), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut image = pack(&payload(), 0);
+        image[0] = b'X';
+        assert_eq!(verify(&image), Err(Error::BadMagic));
+
+        let mut image = pack(&payload(), 0);
+        image[4] = 2;
+        assert_eq!(verify(&image), Err(Error::UnsupportedVersion(2)));
+    }
+
+    #[test]
+    fn bad_payload() {
+        let payload = payload();
+
+        let mut image = pack(&payload, 0);
+        image[HEADER_SIZE + 3] ^= 1;
+        let mut corrupt = payload.clone();
+        corrupt[3] ^= 1;
+        assert_eq!(verify(&image), Err(Error::BadCrc {
+            expected: crc32(&payload),
+            actual: crc32(&corrupt),
+        }));
+
+        assert_eq!(verify(&pack(&payload, 2)), Err(Error::BadVectorOffset));
+        assert_eq!(verify(&pack(&payload, 64)), Err(Error::BadVectorOffset));
+    }
+}
@@ -0,0 +1,417 @@
+use nb;
+
+use delay::{self, Timeout};
+use i2c::{I2CError, I2CTrait};
+
+/// An error from an EEPROM
+#[derive(Debug)]
+pub enum Error {
+    /// Access runs past the end of the part
+    OutOfRange,
+
+    /// Part never came back after a write cycle
+    WriteTimeout,
+
+    /// Talking to the EEPROM over I2C failed
+    Bus(I2CError),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<nb::Error<I2CError>> for Error {
+    fn from(e: nb::Error<I2CError>) -> Error {
+        match e {
+            nb::Error::Other(e) => Error::Bus(e),
+            nb::Error::WouldBlock => Error::Bus(I2CError::Timeout),
+        }
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// Usual base address; A0-A2 straps add to this.
+pub const AT24_ADDRESS: u8 = 0x50;
+
+// largest page of any part we know about
+const MAX_PAGE_SIZE: usize = 128;
+
+// write cycles are 5ms max on most parts
+const WRITE_CYCLE_TIMEOUT_US: u32 = 10_000;
+
+/// Geometry of a 24C-series part.
+#[derive(Clone, Copy, Debug)]
+pub struct Variant {
+    /// total size, in bytes
+    pub size: usize,
+
+    /// writes can't cross a boundary of this many bytes
+    pub page_size: usize,
+
+    /// number of word address bytes; small parts with one byte put the
+    /// high address bits into the low bits of the device address instead
+    pub address_bytes: u8,
+}
+
+pub const AT24C01: Variant = Variant { size: 128, page_size: 8, address_bytes: 1 };
+pub const AT24C02: Variant = Variant { size: 256, page_size: 8, address_bytes: 1 };
+pub const AT24C04: Variant = Variant { size: 512, page_size: 16, address_bytes: 1 };
+pub const AT24C08: Variant = Variant { size: 1024, page_size: 16, address_bytes: 1 };
+pub const AT24C16: Variant = Variant { size: 2048, page_size: 16, address_bytes: 1 };
+pub const AT24C32: Variant = Variant { size: 4096, page_size: 32, address_bytes: 2 };
+pub const AT24C64: Variant = Variant { size: 8192, page_size: 32, address_bytes: 2 };
+pub const AT24C128: Variant = Variant { size: 16384, page_size: 64, address_bytes: 2 };
+pub const AT24C256: Variant = Variant { size: 32768, page_size: 64, address_bytes: 2 };
+pub const AT24C512: Variant = Variant { size: 65536, page_size: 128, address_bytes: 2 };
+
+/// A 24C-series I2C EEPROM.
+pub struct AT24<T>
+where
+    T: I2CTrait,
+{
+    bus: T,
+    address: u8,
+    variant: Variant,
+}
+
+impl<T> AT24<T>
+where
+    T: I2CTrait,
+{
+    pub fn new(bus: T, address: u8, variant: Variant) -> AT24<T> {
+        AT24 {
+            bus: bus,
+            address: address,
+            variant: variant,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.variant.size
+    }
+
+    /// Give back the underlying bus.
+    pub fn free(self) -> T {
+        self.bus
+    }
+
+    /// Read `buf.len()` bytes starting at `offset`.
+    ///
+    /// Reads aren't bound by pages, but single-byte-address parts only see
+    /// 256 bytes per device address, so split at those boundaries.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.check_range(offset, buf.len())?;
+
+        let block = if self.variant.address_bytes == 1 { 256 } else { self.variant.size };
+
+        let mut offset = offset;
+        let mut remaining = buf;
+
+        while !remaining.is_empty() {
+            let len = block - offset % block;
+            let len = if len < remaining.len() { len } else { remaining.len() };
+
+            let (chunk, rest) = { remaining }.split_at_mut(len);
+
+            let (address, word) = self.word_address(offset);
+            self.bus.write_read(address, &word[..self.variant.address_bytes as usize], chunk)?;
+
+            offset += len;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Write `data` starting at `offset`, one page at a time, waiting out
+    /// each write cycle before starting the next.
+    pub fn write(&self, offset: usize, data: &[u8]) -> Result<()> {
+        self.check_range(offset, data.len())?;
+
+        let page_size = self.variant.page_size;
+        let mut offset = offset;
+        let mut remaining = data;
+
+        while !remaining.is_empty() {
+            let len = page_size - offset % page_size;
+            let len = if len < remaining.len() { len } else { remaining.len() };
+
+            let (chunk, rest) = remaining.split_at(len);
+            self.write_page(offset, chunk)?;
+
+            offset += len;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+
+    fn write_page(&self, offset: usize, data: &[u8]) -> Result<()> {
+        let (address, word) = self.word_address(offset);
+
+        if self.variant.address_bytes == 1 {
+            self.bus.write_to(address, Some(word[0]), data)?;
+        } else {
+            // the controller only knows about one register byte, so the
+            // low address byte goes out as the first byte of data
+            let mut buf = [0u8; MAX_PAGE_SIZE + 1];
+            buf[0] = word[1];
+            buf[1..data.len() + 1].copy_from_slice(data);
+
+            self.bus.write_to(address, Some(word[0]), &buf[..data.len() + 1])?;
+        }
+
+        self.wait_write_cycle(address)
+    }
+
+    // the part ignores its address (NAKs) until the internal write cycle
+    // is done; keep addressing it until it answers
+    fn wait_write_cycle(&self, address: u8) -> Result<()> {
+        let timeout = Timeout::new(delay::system_timer(), WRITE_CYCLE_TIMEOUT_US);
+        while !timeout.expired() {
+            match self.bus.write_to(address, None, &[]) {
+                Ok(_) => return Ok(()),
+                Err(nb::Error::Other(I2CError::SlaveNak)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(Error::WriteTimeout)
+    }
+
+    // device address and word address bytes (most significant first) for
+    // a byte offset
+    fn word_address(&self, offset: usize) -> (u8, [u8; 2]) {
+        if self.variant.address_bytes == 1 {
+            (self.address | (offset >> 8) as u8, [offset as u8, 0])
+        } else {
+            (self.address, [(offset >> 8) as u8, offset as u8])
+        }
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> Result<()> {
+        if offset > self.variant.size || len > self.variant.size - offset {
+            return Err(Error::OutOfRange);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::vec::Vec;
+
+    use super::*;
+    use i2c;
+
+    // an EEPROM on the end of a bus, which remembers every transfer
+    struct FakeEeprom {
+        variant: Variant,
+        memory: RefCell<Vec<u8>>,
+
+        // NAKs to give after each page write, for the write cycle
+        cycle: u32,
+        busy: Cell<u32>,
+        polls: Cell<u32>,
+
+        // (device address, offset, length) of each page write and read
+        writes: RefCell<Vec<(u8, usize, usize)>>,
+        reads: RefCell<Vec<(u8, usize, usize)>>,
+    }
+
+    impl FakeEeprom {
+        fn new(variant: Variant, cycle: u32) -> FakeEeprom {
+            FakeEeprom {
+                variant: variant,
+                memory: RefCell::new((0..variant.size).map(|n| n as u8).collect()),
+                cycle: cycle,
+                busy: Cell::new(0),
+                polls: Cell::new(0),
+                writes: RefCell::new(Vec::new()),
+                reads: RefCell::new(Vec::new()),
+            }
+        }
+
+        // byte offset from a device address and the word address bytes
+        fn offset(&self, address: u8, word: &[u8]) -> usize {
+            assert_eq!(word.len(), self.variant.address_bytes as usize);
+
+            if self.variant.address_bytes == 1 {
+                assert_eq!(address & !7, AT24_ADDRESS);
+                ((address & 7) as usize) << 8 | word[0] as usize
+            } else {
+                assert_eq!(address, AT24_ADDRESS);
+                (word[0] as usize) << 8 | word[1] as usize
+            }
+        }
+
+        fn check_busy(&self) -> i2c::Result<()> {
+            if self.busy.get() > 0 {
+                self.busy.set(self.busy.get() - 1);
+                return Err(nb::Error::Other(I2CError::SlaveNak));
+            }
+
+            Ok(())
+        }
+    }
+
+    impl<'a> I2CTrait for &'a FakeEeprom {
+        fn read_from(&self, address: u8, register: Option<u8>, buf: &mut [u8]) -> i2c::Result<usize> {
+            match register {
+                Some(register) => self.write_read(address, &[register], buf),
+                None => self.write_read(address, &[], buf),
+            }
+        }
+
+        fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> i2c::Result<usize> {
+            self.check_busy()?;
+
+            let register = match register {
+                Some(register) => register,
+                None => {
+                    // just the address: a write cycle poll
+                    assert!(data.is_empty());
+                    self.polls.set(self.polls.get() + 1);
+                    return Ok(1);
+                },
+            };
+
+            let (offset, data) = if self.variant.address_bytes == 1 {
+                (self.offset(address, &[register]), data)
+            } else {
+                (self.offset(address, &[register, data[0]]), &data[1..])
+            };
+
+            // the part wraps within a page rather than crossing it, which
+            // would corrupt the start of the page
+            let page_size = self.variant.page_size;
+            assert!(offset % page_size + data.len() <= page_size,
+                "write of {} at {} crosses a page", data.len(), offset);
+
+            self.memory.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+            self.writes.borrow_mut().push((address, offset, data.len()));
+            self.busy.set(self.cycle);
+
+            Ok(data.len() + 2)
+        }
+
+        fn write_read(&self, address: u8, word: &[u8], buf: &mut [u8]) -> i2c::Result<usize> {
+            self.check_busy()?;
+
+            let offset = self.offset(address, word);
+            let memory = self.memory.borrow();
+
+            // the address counter rolls over at the end of the part
+            for (n, byte) in buf.iter_mut().enumerate() {
+                *byte = memory[(offset + n) % self.variant.size];
+            }
+
+            self.reads.borrow_mut().push((address, offset, buf.len()));
+
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn write_splits_pages() {
+        let eeprom = FakeEeprom::new(AT24C32, 0);
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C32);
+        let data: Vec<u8> = (0..70).map(|n| 0x80 | n as u8).collect();
+
+        at24.write(20, &data).unwrap();
+
+        // 32-byte pages: up to 32, up to 64, then the rest
+        assert_eq!(*eeprom.writes.borrow(),
+            [(AT24_ADDRESS, 20, 12), (AT24_ADDRESS, 32, 32), (AT24_ADDRESS, 64, 26)]);
+        assert_eq!(&eeprom.memory.borrow()[20..90], &data[..]);
+        assert_eq!(eeprom.memory.borrow()[19], 19);
+        assert_eq!(eeprom.memory.borrow()[90], 90);
+    }
+
+    #[test]
+    fn write_small_part() {
+        let eeprom = FakeEeprom::new(AT24C04, 0);
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C04);
+
+        // across the 256-byte boundary, so the second page goes to the next
+        // device address
+        at24.write(250, &[1; 12]).unwrap();
+
+        assert_eq!(*eeprom.writes.borrow(),
+            [(AT24_ADDRESS, 250, 6), (AT24_ADDRESS + 1, 256, 6)]);
+        assert_eq!(&eeprom.memory.borrow()[250..262], &[1; 12]);
+    }
+
+    #[test]
+    fn read_wraps_device_address() {
+        let eeprom = FakeEeprom::new(AT24C04, 0);
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C04);
+        let mut buf = [0u8; 300];
+
+        at24.read(200, &mut buf).unwrap();
+
+        assert_eq!(*eeprom.reads.borrow(), [(AT24_ADDRESS, 200, 56), (AT24_ADDRESS + 1, 256, 244)]);
+        for (n, byte) in buf.iter().enumerate() {
+            assert_eq!(*byte, (200 + n) as u8);
+        }
+    }
+
+    #[test]
+    fn read_large() {
+        let eeprom = FakeEeprom::new(AT24C256, 0);
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C256);
+        let mut buf = [0u8; 100];
+
+        // two-byte parts read the lot in one go; the bus splits it up
+        at24.read(0x1234, &mut buf).unwrap();
+
+        assert_eq!(*eeprom.reads.borrow(), [(AT24_ADDRESS, 0x1234, 100)]);
+        for (n, byte) in buf.iter().enumerate() {
+            assert_eq!(*byte, (0x34 + n) as u8);
+        }
+    }
+
+    #[test]
+    fn range() {
+        let eeprom = FakeEeprom::new(AT24C02, 0);
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C02);
+        let mut buf = [0u8; 2];
+
+        match at24.read(255, &mut buf) {
+            Err(Error::OutOfRange) => (),
+            r => panic!("{:?}", r),
+        }
+
+        match at24.write(257, &[]) {
+            Err(Error::OutOfRange) => (),
+            r => panic!("{:?}", r),
+        }
+
+        at24.read(254, &mut buf).unwrap();
+        at24.write(256, &[]).unwrap();
+        assert!(eeprom.writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn write_cycle() {
+        let eeprom = FakeEeprom::new(AT24C02, 5);
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C02);
+
+        // each page waits out its write cycle: five NAKs, then an ACK
+        at24.write(0, &[0xaa; 16]).unwrap();
+        assert_eq!(eeprom.writes.borrow().len(), 2);
+        assert_eq!(eeprom.polls.get(), 2);
+        assert_eq!(eeprom.busy.get(), 0);
+
+        // one that never comes back
+        let eeprom = FakeEeprom::new(AT24C02, u32::max_value());
+        let at24 = AT24::new(&eeprom, AT24_ADDRESS, AT24C02);
+
+        match at24.write(0, &[0xaa]) {
+            Err(Error::WriteTimeout) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+}
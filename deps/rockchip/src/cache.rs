@@ -0,0 +1,60 @@
+// Data cache maintenance, for memory the AP shares with something that
+// doesn't snoop its caches (the M0s).
+//
+// The M0s have no data cache, so there these do nothing, and code shared by
+// both sides can call them unconditionally.
+//
+// Cleaning a line writes back all of it, not just the words we changed, so
+// anything both sides write has to keep each side's words on lines of their
+// own; `SHARED_LINE` is how far apart to put them.
+
+/// Largest data cache line on either cluster, in bytes.
+pub const SHARED_LINE: usize = 64;
+
+/// Write `[start, start + len)` back to memory, keeping it cached.
+pub fn clean(start: usize, len: usize) {
+    maintain(start, len, false);
+}
+
+/// Write `[start, start + len)` back to memory and drop it from the cache,
+/// so the next read sees what the other side has written since.
+pub fn clean_invalidate(start: usize, len: usize) {
+    maintain(start, len, true);
+}
+
+#[cfg(target_arch = "aarch64")]
+fn maintain(start: usize, len: usize, invalidate: bool) {
+    if len == 0 {
+        return;
+    }
+
+    let line = line_size();
+    let mut addr = start & !(line - 1);
+
+    while addr < start + len {
+        unsafe {
+            if invalidate {
+                asm!("dc civac, $0" :: "r"(addr) : "memory" : "volatile");
+            } else {
+                asm!("dc cvac, $0" :: "r"(addr) : "memory" : "volatile");
+            }
+        }
+
+        addr += line;
+    }
+
+    unsafe { asm!("dsb sy" ::: "memory" : "volatile"); }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn maintain(_start: usize, _len: usize, _invalidate: bool) {
+}
+
+// smallest data cache line, in bytes
+#[cfg(target_arch = "aarch64")]
+fn line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs $0, ctr_el0" : "=r"(ctr) ::: "volatile"); }
+
+    4 << ((ctr >> 16) & 0xf)
+}
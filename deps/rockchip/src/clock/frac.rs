@@ -0,0 +1,72 @@
+// Fractional dividers.
+//
+// The UART and I2S clocks each have one after their integer divider:
+//
+//   Fout = Fin * NUMERATOR / DENOMINATOR
+//
+// with both halves 16 bits wide in a single CLKSEL register. The output
+// jitters between the two nearest integer divisions, so the TRM only
+// guarantees a usable clock with Fin at least 20 times Fout.
+
+use super::ClockError;
+
+/// Input must be at least this many times the output.
+pub const MIN_RATIO: u32 = 20;
+
+const MAX_TERM: u64 = 0xffff;
+
+/// Register value for a numerator and denominator.
+pub fn pack(num: u16, den: u16) -> u32 {
+    (num as u32) << 16 | den as u32
+}
+
+/// Numerator and denominator from a register value.
+pub fn unpack(value: u32) -> (u16, u16) {
+    ((value >> 16) as u16, value as u16)
+}
+
+/// Output rate for `parent_rate` in; 0 if the divider isn't set up.
+pub fn rate(parent_rate: u32, num: u16, den: u16) -> u32 {
+    if den == 0 {
+        return 0;
+    }
+
+    (parent_rate as u64 * num as u64 / den as u64) as u32
+}
+
+/// Numerator and denominator getting as close to `rate` as 16 bits allow,
+/// by walking the continued fraction of `rate / parent_rate` until the
+/// next convergent won't fit.
+pub fn solve(parent_rate: u32, rate: u32) -> Result<(u16, u16), ClockError> {
+    if rate == 0 || (parent_rate as u64) < rate as u64 * MIN_RATIO as u64 {
+        return Err(ClockError::InvalidRate);
+    }
+
+    let (mut n, mut d) = (rate as u64, parent_rate as u64);
+    let (mut n0, mut d0) = (0u64, 1u64);
+    let (mut n1, mut d1) = (1u64, 0u64);
+
+    while d != 0 {
+        let a = n / d;
+        let r = n % d;
+        n = d;
+        d = r;
+
+        let n2 = n0 + a * n1;
+        let d2 = d0 + a * d1;
+        if n2 > MAX_TERM || d2 > MAX_TERM {
+            break;
+        }
+
+        n0 = n1;
+        d0 = d1;
+        n1 = n2;
+        d1 = d2;
+    }
+
+    if n1 == 0 || d1 == 0 {
+        return Err(ClockError::InvalidRate);
+    }
+
+    Ok((n1 as u16, d1 as u16))
+}
@@ -0,0 +1,1207 @@
+use hal;
+use core::any::{Any, TypeId};
+use core::ops::Deref;
+use core::ptr;
+
+use spin::Mutex;
+
+#[cfg(target_arch = "aarch64")]
+use rk3399_tools::{CRU, PMUCRU};
+
+#[cfg(not(target_arch = "aarch64"))]
+use rk3399_m0::{CRU, PMUCRU};
+
+pub mod tree;
+pub use self::tree::{Clock, ClockNode, Kind, CLOCKS, CLOCK_COUNT};
+
+pub mod pll;
+pub use self::pll::RkPLL;
+
+pub mod notify;
+pub use self::notify::{ClockConsumer, Veto, MAX_CONSUMERS};
+
+pub mod frac;
+
+pub mod summary;
+pub use self::summary::{Issue, Summary};
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PLLSource {
+    Slow_24MHz_28MHz,
+    Normal,
+    DeepSlow_32_768MHz
+}
+
+/// PLL dividers, as programmed into the hardware (ie. not minus one).
+/// Only used in `Normal` mode; the slow modes bypass the PLL.
+#[derive(Clone, Copy, Debug)]
+pub struct PLLConfiguration {
+    pub source:PLLSource,
+    pub refdiv: u32,
+    pub fbdiv: u32,
+    pub postdiv1: u32,
+    pub postdiv2: u32,
+
+    /// fractional part of the feedback divider, in 2^-24ths; `None` runs
+    /// the PLL in integer mode
+    pub frac: Option<u32>,
+}
+
+/// An error from reconfiguring a PLL
+#[derive(Debug)]
+pub enum PLLError {
+    /// Rate is outside what any PLL configuration can produce
+    OutOfRange,
+
+    /// No divider combination gives the requested rate
+    NoSolution,
+
+    /// Dividers break the PLL's VCO or divider limits
+    InvalidConfig,
+
+    /// PLL didn't report lock in time; it's been left in slow mode
+    LockTimeout,
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+pub trait PLL {
+    fn get_config(&self) -> PLLConfiguration;
+
+    /// update clock speed, may fail if any of the dependent peripherals
+    /// cannot use the new clock configuration
+    fn set_config(&self, PLLConfiguration) -> Result<(), PLLError>;
+}
+
+/// An error from the clock tree
+#[derive(Debug)]
+pub enum ClockError {
+    /// No clock by that name
+    NotFound,
+
+    /// Clock can't be made to run at (or near) the requested rate
+    InvalidRate,
+
+    /// Mux is set to an input we don't model, or isn't one of its inputs
+    InvalidParent,
+
+    /// Clock can't be reconfigured this way
+    Unsupported,
+
+    /// Reprogramming a PLL failed
+    Pll(PLLError),
+
+    /// A consumer of the clock, or of one below it, refused the change
+    Vetoed,
+
+    /// No room to register another consumer
+    TooManyConsumers,
+
+    /// Clock was disabled more times than it was enabled
+    NotEnabled,
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<PLLError> for ClockError {
+    fn from(e: PLLError) -> ClockError {
+        ClockError::Pll(e)
+    }
+}
+
+/// Which register block a clock register lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bank {
+    Cru,
+    PmuCru,
+}
+
+/// A clock control register, as an offset into its bank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reg {
+    pub bank: Bank,
+    pub offset: u16,
+}
+
+impl Reg {
+    /// The register `n` words after this one.
+    pub fn next(&self, n: u16) -> Reg {
+        Reg { bank: self.bank, offset: self.offset + n * 4 }
+    }
+}
+
+/// Access to the clock registers, by bank and offset.
+///
+/// Everything in the clock model goes through this rather than touching the
+/// CRU and PMUCRU directly.
+pub trait ClockRegisters {
+    fn read(&self, reg: Reg) -> u32;
+    fn write(&self, reg: Reg, value: u32);
+
+    /// Update a field in a register with a write mask in its top 16 bits,
+    /// as nearly all CRU registers have.
+    fn write_field(&self, reg: Reg, shift: u8, width: u8, value: u32) {
+        let mask = ((1 << width) - 1) << shift;
+        self.write(reg, mask << 16 | (value << shift) & mask);
+    }
+
+    fn read_field(&self, reg: Reg, shift: u8, width: u8) -> u32 {
+        (self.read(reg) >> shift) & ((1 << width) - 1)
+    }
+}
+
+/// The real CRU and PMUCRU.
+pub struct Mmio;
+
+impl Mmio {
+    fn address(&self, reg: Reg) -> *mut u32 {
+        let base = match reg.bank {
+            Bank::Cru => CRU.get() as *mut u8,
+            Bank::PmuCru => PMUCRU.get() as *mut u8,
+        };
+
+        unsafe { base.offset(reg.offset as isize) as *mut u32 }
+    }
+}
+
+impl ClockRegisters for Mmio {
+    fn read(&self, reg: Reg) -> u32 {
+        unsafe { ptr::read_volatile(self.address(reg)) }
+    }
+
+    fn write(&self, reg: Reg, value: u32) {
+        unsafe { ptr::write_volatile(self.address(reg), value) }
+    }
+}
+
+// a register change worked out by `plan_rate`, not yet applied
+#[derive(Clone, Copy)]
+enum Change {
+    None,
+    Pll { clk: Clock, config: PLLConfiguration },
+    Parent { mux: Clock, parent: Clock },
+    Div { clk: Clock, div: u32, input: Option<(Clock, Clock)> },
+    Frac { clk: Clock, num: u16, den: u16 },
+}
+
+#[derive(Clone, Copy)]
+struct Plan {
+    change: Change,
+
+    /// highest clock in the tree whose rate changes; everything under it
+    /// is affected
+    root: Clock,
+    root_rate: u32,
+
+    /// rate the requested clock ends up at
+    achieved: u32,
+}
+
+type Consumers<'c> = [Option<(Clock, &'c ClockConsumer)>; MAX_CONSUMERS];
+
+pub struct ClockManager<'c, R>
+where
+    R: ClockRegisters,
+{
+    regs: R,
+    consumers: Mutex<Consumers<'c>>,
+
+    // users of each clock, indexed by `Clock`; a clock's parent holds one
+    // reference for as long as the clock itself has any
+    counts: Mutex<[u16; CLOCK_COUNT]>,
+}
+
+impl<'c, R> ClockManager<'c, R>
+where
+    R: ClockRegisters,
+{
+    // each clock has a parent, except the top level PLLs
+    // each clock has some rate determined by its configuration (ie divider/multipler rate, and
+    // selected input mux if any), as well as the ability to be gated on or off.
+    // each clock may be active, depending on its gating state, or one of its parents'.
+    //
+    // a lower level clock in the tree might set some restriction on its rate
+    //
+    // any clock can be reconfigured, and any devices using the clock must be notified
+    // before and after any clock rate change, in case they need to reconfigure themselves
+    // (eg if the UART's parent clock changes, then the UART needs to change its own divider
+    // to keep itself outputting at the configured baud rate). to take the UART example,
+    // it needs to pause transmission, ACK the clock change, let the clock change happen,
+    // then reconfigure its clock, then resume transmission.
+    //
+    // some devices may not permit changing the clock rate after they've been activated,
+    // or the new clockrate may place the peripheral in an invalid state. as such, active
+    // peripherals that share that clock may decline a clock rate change.
+    //
+    // clock dependency tree worst-case can be determined statically, but if dynamically
+    // adjustable, can only be known at runtime.
+    //
+    // the tree itself lives in `tree::CLOCKS`; everything here walks it and reads (or
+    // writes) the registers each node points at, so there's no cached state to go stale
+    // if something else (uboot, the M0) has been fiddling with the CRU.
+    //
+    // the exception is enable counts: a clock nobody has asked for is left however
+    // uboot left it, and is only turned off once everyone who enabled it has let go.
+
+    pub fn new(regs: R) -> ClockManager<'c, R> {
+        ClockManager {
+            regs: regs,
+            consumers: Mutex::new([None; MAX_CONSUMERS]),
+            counts: Mutex::new([0; CLOCK_COUNT]),
+        }
+    }
+
+    /// Have `consumer` told about any rate change affecting `clk`.
+    pub fn register_consumer(&self, clk: Clock, consumer: &'c ClockConsumer) -> Result<(), ClockError> {
+        let mut consumers = self.consumers.lock();
+        let slot = consumers.iter_mut().find(|c| c.is_none()).ok_or(ClockError::TooManyConsumers)?;
+        *slot = Some((clk, consumer));
+        Ok(())
+    }
+
+    /// Stop telling `consumer` about changes to `clk`.
+    pub fn unregister_consumer(&self, clk: Clock, consumer: &'c ClockConsumer) {
+        for slot in self.consumers.lock().iter_mut() {
+            let matches = match *slot {
+                Some((c, registered)) => c == clk && same_consumer(registered, consumer),
+                None => false,
+            };
+
+            if matches {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn registers(&self) -> &R {
+        &self.regs
+    }
+
+    /// The PLL behind `clk`, if it is one.
+    pub fn pll(&self, clk: Clock) -> Option<RkPLL<R>> {
+        RkPLL::new(&self.regs, clk)
+    }
+
+    /// Look up a clock by name.
+    pub fn get(&self, name: &str) -> Result<Clock, ClockError> {
+        Clock::by_name(name).ok_or(ClockError::NotFound)
+    }
+
+    /// A handle on the clock called `name`, for a driver to enable and
+    /// disable as it needs. The clock isn't touched until then.
+    pub fn request<'m>(&'m self, name: &str) -> Result<ClockHandle<'m, 'c, R>, ClockError> {
+        Ok(ClockHandle {
+            manager: self,
+            clk: self.get(name)?,
+            enabled: false,
+        })
+    }
+
+    /// Take a reference on a clock, turning it (and everything it runs
+    /// from) on if it's the first.
+    pub fn enable(&self, clk: Clock) -> Result<(), ClockError> {
+        let mut counts = self.counts.lock();
+        self.enable_locked(&mut counts, clk)
+    }
+
+    /// Drop a reference on a clock; the last one turns it off, and releases
+    /// its parent in turn. Critical clocks are never actually turned off.
+    pub fn disable(&self, clk: Clock) -> Result<(), ClockError> {
+        let mut counts = self.counts.lock();
+        self.disable_locked(&mut counts, clk)
+    }
+
+    /// Number of references held on a clock.
+    pub fn enable_count(&self, clk: Clock) -> u16 {
+        self.counts.lock()[clk as usize]
+    }
+
+    fn enable_locked(&self, counts: &mut [u16; CLOCK_COUNT], clk: Clock) -> Result<(), ClockError> {
+        if counts[clk as usize] == 0 {
+            if let Some(parent) = self.parent(clk) {
+                self.enable_locked(counts, parent)?;
+            }
+
+            if let Err(e) = self.ungate(clk) {
+                if let Some(parent) = self.parent(clk) {
+                    let _ = self.disable_locked(counts, parent);
+                }
+                return Err(e);
+            }
+        }
+
+        counts[clk as usize] += 1;
+        Ok(())
+    }
+
+    fn disable_locked(&self, counts: &mut [u16; CLOCK_COUNT], clk: Clock) -> Result<(), ClockError> {
+        match counts[clk as usize] {
+            0 => return Err(ClockError::NotEnabled),
+            1 => (),
+            _ => {
+                counts[clk as usize] -= 1;
+                return Ok(());
+            },
+        }
+
+        counts[clk as usize] = 0;
+
+        if !clk.is_critical() {
+            self.gate(clk);
+        }
+
+        match self.parent(clk) {
+            Some(parent) => self.disable_locked(counts, parent),
+            None => Ok(()),
+        }
+    }
+
+    // turn a single node on; anything that isn't a gate or a PLL is always on
+    fn ungate(&self, clk: Clock) -> Result<(), ClockError> {
+        match clk.node().kind {
+            Kind::Gate { reg, bit, .. } => {
+                self.regs.write_field(reg, bit, 1, 0);
+                Ok(())
+            },
+            Kind::Pll { .. } => match self.pll(clk) {
+                Some(pll) => Ok(pll.power_up()?),
+                None => Err(ClockError::Unsupported),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn gate(&self, clk: Clock) {
+        match clk.node().kind {
+            Kind::Gate { reg, bit, .. } => self.regs.write_field(reg, bit, 1, 1),
+            Kind::Pll { .. } => {
+                if let Some(pll) = self.pll(clk) {
+                    pll.power_down();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Current parent of a clock; `None` for oscillators and PLLs, or if a
+    /// mux is set to an input we don't model.
+    pub fn parent(&self, clk: Clock) -> Option<Clock> {
+        match clk.node().kind {
+            Kind::Fixed { .. } | Kind::Pll { .. } => None,
+            Kind::Mux { parents, reg, shift, width } => {
+                let idx = self.regs.read_field(reg, shift, width) as usize;
+                parents.get(idx).map(|p| *p)
+            },
+            Kind::Div { parent, .. } | Kind::Gate { parent, .. } | Kind::Frac { parent, .. } => Some(parent),
+        }
+    }
+
+    /// Current output rate of a clock in Hz, worked out by walking up
+    /// through its parents. Gating doesn't count; a gated clock reports
+    /// the rate it would run at.
+    pub fn rate(&self, clk: Clock) -> u32 {
+        match clk.node().kind {
+            Kind::Fixed { rate } => rate,
+            Kind::Pll { .. } => self.pll(clk).map(|pll| pll.rate()).unwrap_or(0),
+            Kind::Mux { .. } | Kind::Gate { .. } => {
+                self.parent(clk).map(|p| self.rate(p)).unwrap_or(0)
+            },
+            Kind::Div { parent, reg, shift, width } => {
+                self.rate(parent) / (self.regs.read_field(reg, shift, width) + 1)
+            },
+            Kind::Frac { parent, reg } => {
+                let (num, den) = frac::unpack(self.regs.read(reg));
+                frac::rate(self.rate(parent), num, den)
+            },
+        }
+    }
+
+    pub fn is_gated(&self, clk: Clock) -> bool {
+        match clk.node().kind {
+            Kind::Gate { reg, bit, .. } => self.regs.read_field(reg, bit, 1) != 0,
+            _ => false,
+        }
+    }
+
+    /// Point a mux at one of its inputs.
+    pub fn set_parent(&self, clk: Clock, parent: Clock) -> Result<(), ClockError> {
+        mux_index(clk, parent)?;
+
+        let new = self.rate(parent);
+        self.change(Plan {
+            change: Change::Parent { mux: clk, parent: parent },
+            root: clk,
+            root_rate: new,
+            achieved: new,
+        })
+    }
+
+    /// Set a divider directly, rather than letting `set_rate` pick one.
+    pub fn set_div(&self, clk: Clock, div: u32) -> Result<(), ClockError> {
+        let width = match clk.node().kind {
+            Kind::Div { width, .. } => width,
+            _ => return Err(ClockError::Unsupported),
+        };
+
+        if div < 1 || div > 1 << width {
+            return Err(ClockError::InvalidRate);
+        }
+
+        let new = self.parent(clk).map(|p| self.rate(p)).unwrap_or(0) / div;
+        self.change(Plan {
+            change: Change::Div { clk: clk, div: div, input: None },
+            root: clk,
+            root_rate: new,
+            achieved: new,
+        })
+    }
+
+    /// Get a clock as close as possible to `rate` without going over it,
+    /// picking the best input and divider on the way. Returns the rate
+    /// actually set.
+    ///
+    /// Gates pass the request on to their parent. PLLs are relocked at the
+    /// new rate; oscillators can't be changed.
+    ///
+    /// Consumers of any clock whose rate changes as a result are asked
+    /// first, and can veto the change.
+    pub fn set_rate(&self, clk: Clock, rate: u32) -> Result<u32, ClockError> {
+        if rate == 0 {
+            return Err(ClockError::InvalidRate);
+        }
+
+        let plan = self.plan_rate(clk, rate)?;
+        self.change(plan)?;
+
+        Ok(plan.achieved)
+    }
+
+    // work out what to change to get `clk` to `rate`, without touching
+    // anything
+    fn plan_rate(&self, clk: Clock, rate: u32) -> Result<Plan, ClockError> {
+        match clk.node().kind {
+            Kind::Pll { .. } => {
+                let config = pll::solve(rate)?;
+                let achieved = config.rate();
+
+                Ok(Plan {
+                    change: Change::Pll { clk: clk, config: config },
+                    root: clk,
+                    root_rate: achieved,
+                    achieved: achieved,
+                })
+            },
+
+            Kind::Fixed { .. } => {
+                if self.rate(clk) == rate {
+                    Ok(Plan { change: Change::None, root: clk, root_rate: rate, achieved: rate })
+                } else {
+                    Err(ClockError::Unsupported)
+                }
+            },
+
+            Kind::Gate { parent, .. } => self.plan_rate(parent, rate),
+
+            // always from whatever the parent runs at now; getting the
+            // parent fast enough is up to the caller
+            Kind::Frac { parent, .. } => {
+                let parent_rate = self.rate(parent);
+                let (num, den) = frac::solve(parent_rate, rate)?;
+                let achieved = frac::rate(parent_rate, num, den);
+
+                Ok(Plan {
+                    change: Change::Frac { clk: clk, num: num, den: den },
+                    root: clk,
+                    root_rate: achieved,
+                    achieved: achieved,
+                })
+            },
+
+            Kind::Mux { parents, .. } => {
+                let mut best = None;
+                for parent in parents {
+                    let candidate = self.rate(*parent);
+                    if is_better(candidate, best.map(|(_, r)| r), rate) {
+                        best = Some((*parent, candidate));
+                    }
+                }
+
+                let (parent, achieved) = best.ok_or(ClockError::InvalidRate)?;
+
+                Ok(Plan {
+                    change: Change::Parent { mux: clk, parent: parent },
+                    root: clk,
+                    root_rate: achieved,
+                    achieved: achieved,
+                })
+            },
+
+            Kind::Div { parent, width, .. } => {
+                // if we sit right under a mux, we get to pick its input too
+                let only_parent = [parent];
+                let inputs: &[Clock] = match parent.node().kind {
+                    Kind::Mux { parents, .. } => parents,
+                    _ => &only_parent,
+                };
+
+                let max_div = 1 << width;
+                let mut best = None;
+
+                for input in inputs {
+                    let input_rate = self.rate(*input);
+                    let div = div_for(input_rate, rate, max_div);
+                    let candidate = input_rate / div;
+
+                    if is_better(candidate, best.map(|(_, _, r)| r), rate) {
+                        best = Some((*input, div, candidate));
+                    }
+                }
+
+                let (input, div, achieved) = best.ok_or(ClockError::InvalidRate)?;
+                if achieved == 0 {
+                    return Err(ClockError::InvalidRate);
+                }
+
+                // switching the mux changes the rate of everything else
+                // hanging off it too
+                if input != parent && self.parent(parent) != Some(input) {
+                    Ok(Plan {
+                        change: Change::Div { clk: clk, div: div, input: Some((parent, input)) },
+                        root: parent,
+                        root_rate: self.rate(input),
+                        achieved: achieved,
+                    })
+                } else {
+                    Ok(Plan {
+                        change: Change::Div { clk: clk, div: div, input: None },
+                        root: clk,
+                        root_rate: achieved,
+                        achieved: achieved,
+                    })
+                }
+            },
+        }
+    }
+
+    // ask, apply, then tell
+    fn change(&self, plan: Plan) -> Result<(), ClockError> {
+        if let Change::None = plan.change {
+            return Ok(());
+        }
+
+        // work from a copy, so consumers are free to use the manager from
+        // their callbacks
+        let consumers = *self.consumers.lock();
+
+        // pre-change; on a veto, tell everyone who already said yes
+        let mut old_rates = [0u32; MAX_CONSUMERS];
+
+        for (idx, slot) in consumers.iter().enumerate() {
+            if let Some((clk, consumer)) = *slot {
+                if !self.is_below(clk, plan.root) {
+                    continue;
+                }
+
+                old_rates[idx] = self.rate(clk);
+                let new = self.planned_rate(clk, &plan);
+
+                if consumer.pre_rate_change(clk, old_rates[idx], new).is_err() {
+                    self.notify_abort(&consumers[..idx], &plan);
+                    return Err(ClockError::Vetoed);
+                }
+            }
+        }
+
+        if let Err(e) = self.apply(plan.change) {
+            self.notify_abort(&consumers, &plan);
+            return Err(e);
+        }
+
+        for (idx, slot) in consumers.iter().enumerate() {
+            if let Some((clk, consumer)) = *slot {
+                if self.is_below(clk, plan.root) {
+                    consumer.post_rate_change(clk, old_rates[idx], self.rate(clk));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // only called once the registers are back as they were (or were never
+    // touched), so the current rate is the old one
+    fn notify_abort(&self, consumers: &[Option<(Clock, &'c ClockConsumer)>], plan: &Plan) {
+        for slot in consumers {
+            if let Some((clk, consumer)) = *slot {
+                if self.is_below(clk, plan.root) {
+                    consumer.abort_rate_change(clk, self.rate(clk), self.planned_rate(clk, plan));
+                }
+            }
+        }
+    }
+
+    fn apply(&self, change: Change) -> Result<(), ClockError> {
+        match change {
+            Change::None => Ok(()),
+
+            Change::Pll { clk, config } => {
+                let pll = self.pll(clk).ok_or(ClockError::Unsupported)?;
+                let old = pll.get_config();
+
+                if let Err(e) = pll.set_config(config) {
+                    // put it back how we found it
+                    let _ = pll.set_config(old);
+                    return Err(e.into());
+                }
+
+                Ok(())
+            },
+
+            Change::Parent { mux, parent } => {
+                self.write_parent(mux, parent)
+            },
+
+            Change::Div { clk, div, input } => {
+                let (reg, shift, width) = match clk.node().kind {
+                    Kind::Div { reg, shift, width, .. } => (reg, shift, width),
+                    _ => return Err(ClockError::Unsupported),
+                };
+
+                // when the divider is going up, change it before the mux
+                // so we never overshoot; otherwise switch input first
+                let old_div = self.regs.read_field(reg, shift, width) + 1;
+
+                if div > old_div {
+                    self.regs.write_field(reg, shift, width, div - 1);
+                    if let Some((mux, parent)) = input {
+                        if let Err(e) = self.write_parent(mux, parent) {
+                            // still on the old input, so the old divider
+                            self.regs.write_field(reg, shift, width, old_div - 1);
+                            return Err(e);
+                        }
+                    }
+                } else {
+                    if let Some((mux, parent)) = input { self.write_parent(mux, parent)?; }
+                    self.regs.write_field(reg, shift, width, div - 1);
+                }
+
+                Ok(())
+            },
+
+            Change::Frac { clk, num, den } => {
+                match clk.node().kind {
+                    Kind::Frac { reg, .. } => {
+                        self.regs.write(reg, frac::pack(num, den));
+                        Ok(())
+                    },
+                    _ => Err(ClockError::Unsupported),
+                }
+            },
+        }
+    }
+
+    // if the mux is in use, its reference moves over to the new input,
+    // which has to be running before we switch to it
+    fn write_parent(&self, mux: Clock, parent: Clock) -> Result<(), ClockError> {
+        let idx = mux_index(mux, parent)?;
+
+        let (reg, shift, width) = match mux.node().kind {
+            Kind::Mux { reg, shift, width, .. } => (reg, shift, width),
+            _ => return Err(ClockError::Unsupported),
+        };
+
+        let mut counts = self.counts.lock();
+        let old = self.parent(mux);
+        let in_use = counts[mux as usize] > 0 && old != Some(parent);
+
+        if in_use {
+            self.enable_locked(&mut counts, parent)?;
+        }
+
+        self.regs.write_field(reg, shift, width, idx as u32);
+
+        if in_use {
+            if let Some(old) = old {
+                self.disable_locked(&mut counts, old)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `clk` is `root`, or currently fed from it.
+    pub fn is_below(&self, clk: Clock, root: Clock) -> bool {
+        let mut current = Some(clk);
+
+        while let Some(c) = current {
+            if c == root {
+                return true;
+            }
+
+            current = self.parent(c);
+        }
+
+        false
+    }
+
+    // rate `clk` will run at once `plan` has been applied
+    fn planned_rate(&self, clk: Clock, plan: &Plan) -> u32 {
+        if clk == plan.root {
+            return plan.root_rate;
+        }
+
+        match clk.node().kind {
+            Kind::Fixed { .. } | Kind::Pll { .. } => self.rate(clk),
+            Kind::Mux { .. } | Kind::Gate { .. } => {
+                self.parent(clk).map(|p| self.planned_rate(p, plan)).unwrap_or(0)
+            },
+            Kind::Div { parent, reg, shift, width } => {
+                let div = match plan.change {
+                    Change::Div { clk: changed, div, .. } if changed == clk => div,
+                    _ => self.regs.read_field(reg, shift, width) + 1,
+                };
+
+                self.planned_rate(parent, plan) / div
+            },
+            Kind::Frac { parent, reg } => {
+                let (num, den) = match plan.change {
+                    Change::Frac { clk: changed, num, den } if changed == clk => (num, den),
+                    _ => frac::unpack(self.regs.read(reg)),
+                };
+
+                frac::rate(self.planned_rate(parent, plan), num, den)
+            },
+        }
+    }
+}
+
+/// A driver's hold on one clock.
+///
+/// The handle counts as at most one user: enabling it twice only takes one
+/// reference, and disabling it gives that reference back. So does dropping
+/// it, so keep it for as long as the clock's needed.
+pub struct ClockHandle<'m, 'c: 'm, R>
+where
+    R: 'm + ClockRegisters,
+{
+    manager: &'m ClockManager<'c, R>,
+    clk: Clock,
+    enabled: bool,
+}
+
+impl<'m, 'c, R> ClockHandle<'m, 'c, R>
+where
+    R: 'm + ClockRegisters,
+{
+    pub fn clock(&self) -> Clock {
+        self.clk
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn enable(&mut self) -> Result<(), ClockError> {
+        if !self.enabled {
+            self.manager.enable(self.clk)?;
+            self.enabled = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn disable(&mut self) -> Result<(), ClockError> {
+        if self.enabled {
+            self.manager.disable(self.clk)?;
+            self.enabled = false;
+        }
+
+        Ok(())
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.manager.rate(self.clk)
+    }
+
+    pub fn set_rate(&self, rate: u32) -> Result<u32, ClockError> {
+        self.manager.set_rate(self.clk, rate)
+    }
+}
+
+impl<'m, 'c, R> Drop for ClockHandle<'m, 'c, R>
+where
+    R: 'm + ClockRegisters,
+{
+    // nowhere to report a failure to; `disable` first to see it
+    fn drop(&mut self) {
+        let _ = self.disable();
+    }
+}
+
+// position of `parent` among a mux's inputs
+fn mux_index(mux: Clock, parent: Clock) -> Result<usize, ClockError> {
+    match mux.node().kind {
+        Kind::Mux { parents, .. } => {
+            parents.iter().position(|p| *p == parent).ok_or(ClockError::InvalidParent)
+        },
+        _ => Err(ClockError::Unsupported),
+    }
+}
+
+// trait objects are the same consumer if they point at the same thing
+fn same_consumer(a: &ClockConsumer, b: &ClockConsumer) -> bool {
+    a as *const ClockConsumer as *const u8 == b as *const ClockConsumer as *const u8
+}
+
+// smallest divider that doesn't take us over `rate`; any `rate` will do,
+// up to u32::max_value() for as fast as the input allows
+fn div_for(input_rate: u32, rate: u32, max_div: u32) -> u32 {
+    let div = input_rate / rate + if input_rate % rate != 0 { 1 } else { 0 };
+
+    if div < 1 {
+        1
+    } else if div > max_div {
+        max_div
+    } else {
+        div
+    }
+}
+
+// prefer rates at or under the target, closest first; failing that, the
+// smallest rate over it
+fn is_better(candidate: u32, best: Option<u32>, target: u32) -> bool {
+    match best {
+        None => true,
+        Some(best) => {
+            if candidate <= target && best <= target {
+                candidate > best
+            } else if candidate <= target {
+                true
+            } else if best <= target {
+                false
+            } else {
+                candidate < best
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::FakeRegisters;
+    use super::*;
+
+    use std::cell::Cell;
+
+    // PLL CON2 and the fractional dividers are written whole
+    fn has_mask(reg: Reg) -> bool {
+        !CLOCKS.iter().any(|node| match node.kind {
+            Kind::Pll { con } => con.next(2) == reg,
+            Kind::Frac { reg: frac, .. } => frac == reg,
+            _ => false,
+        })
+    }
+
+    fn is_pll_con2(reg: Reg) -> bool {
+        CLOCKS.iter().any(|node| match node.kind {
+            Kind::Pll { con } => con.next(2) == reg,
+            _ => false,
+        })
+    }
+
+    thread_local! {
+        // CON2 of a PLL that never locks
+        static STUCK: Cell<Option<Reg>> = Cell::new(None);
+    }
+
+    // PLLs lock straight away, unless they're stuck
+    impl<'a> ClockRegisters for &'a FakeRegisters<Reg> {
+        fn read(&self, reg: Reg) -> u32 {
+            if is_pll_con2(reg) && STUCK.with(|stuck| stuck.get()) != Some(reg) {
+                self.value(reg) | 1 << 31
+            } else {
+                self.value(reg)
+            }
+        }
+
+        fn write(&self, reg: Reg, value: u32) {
+            if has_mask(reg) {
+                self.store_masked(reg, value);
+            } else {
+                self.store(reg, value);
+            }
+        }
+    }
+
+    fn clksel(n: u16) -> Reg {
+        Reg { bank: Bank::Cru, offset: 0x100 + n * 4 }
+    }
+
+    fn clkgate(n: u16) -> Reg {
+        Reg { bank: Bank::Cru, offset: 0x300 + n * 4 }
+    }
+
+    // CPLL at 1GHz, GPLL at 800MHz, PPLL at 676MHz; the rest in slow mode
+    fn manager(regs: &FakeRegisters<Reg>) -> ClockManager<'static, &FakeRegisters<Reg>> {
+        let clocks = ClockManager::new(regs);
+
+        for &(clk, rate) in [(Clock::Cpll, 1_000_000_000), (Clock::Gpll, 800_000_000),
+                (Clock::Ppll, 676_000_000)].iter() {
+            assert_eq!(clocks.pll(clk).unwrap().set_rate(rate).unwrap(), rate);
+        }
+
+        clocks
+    }
+
+    #[test]
+    fn tables() {
+        for (n, node) in CLOCKS.iter().enumerate() {
+            assert_eq!(node.id as usize, n, "{} is out of order", node.name);
+            assert_eq!(Clock::by_name(node.name), Some(node.id));
+
+            // every mux input is reachable, and nothing loops
+            if let Kind::Mux { parents, width, .. } = node.kind {
+                assert!(parents.len() <= 1 << width, "{} has too many parents", node.name);
+            }
+
+            let regs = FakeRegisters::new();
+            let clocks = ClockManager::new(&regs);
+            let mut current = Some(node.id);
+            for _ in 0..CLOCK_COUNT {
+                current = current.and_then(|c| clocks.parent(c));
+            }
+            assert_eq!(current, None, "{} loops", node.name);
+        }
+
+        assert_eq!(Clock::by_name("nope"), None);
+    }
+
+    #[test]
+    fn pll_solve() {
+        // integer solutions, lowest post dividers first
+        let config = pll::solve(800_000_000).unwrap();
+        assert_eq!((config.refdiv, config.fbdiv, config.postdiv1, config.postdiv2, config.frac),
+            (3, 100, 1, 1, None));
+        assert_eq!(config.rate(), 800_000_000);
+
+        for rate in [24_000_000, 32_000_000, 594_000_000, 676_000_000, 1_416_000_000,
+                3_200_000_000].iter() {
+            let config = pll::solve(*rate).unwrap();
+            assert!(config.validate().is_ok());
+            assert_eq!(config.frac, None);
+            assert_eq!(config.rate(), *rate);
+        }
+
+        // needs the fractional divider; close, but not over
+        let config = pll::solve(100_000_007).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.refdiv, 1);
+        assert!(config.frac.is_some());
+        assert!(config.rate() <= 100_000_007 && config.rate() > 99_999_000);
+
+        // in range, but the VCO can't get that low
+        match pll::solve(16_000_000) {
+            Err(PLLError::NoSolution) => (),
+            r => panic!("{:?}", r),
+        }
+
+        match pll::solve(15_999_999) {
+            Err(PLLError::OutOfRange) => (),
+            r => panic!("{:?}", r),
+        }
+
+        match pll::solve(3_200_000_001) {
+            Err(PLLError::OutOfRange) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn pll_registers() {
+        let regs = FakeRegisters::new();
+        let clocks = ClockManager::new(&regs);
+        let gpll = clocks.pll(Clock::Gpll).unwrap();
+
+        // slow mode out of reset
+        assert_eq!(gpll.rate(), 24_000_000);
+
+        gpll.set_rate(800_000_000).unwrap();
+        let con = Reg { bank: Bank::Cru, offset: 0x080 };
+        assert_eq!((&regs).read(con), 100);
+        assert_eq!((&regs).read(con.next(1)), 1 << 12 | 1 << 8 | 3);
+        assert_eq!((&regs).read(con.next(3)), 1 << 8 | 1 << 3);
+        assert_eq!(gpll.rate(), 800_000_000);
+        assert_eq!(clocks.rate(Clock::Gpll), 800_000_000);
+
+        gpll.power_down();
+        assert_eq!(gpll.rate(), 24_000_000);
+    }
+
+    #[test]
+    fn mux_and_div() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        // clk_i2c1_src picks CPLL with 0
+        assert_eq!(clocks.parent(Clock::ClkI2c1Src), Some(Clock::Cpll));
+        assert_eq!(clocks.rate(Clock::ClkI2c1), 1_000_000_000);
+
+        clocks.set_parent(Clock::ClkI2c1Src, Clock::Gpll).unwrap();
+        assert_eq!((&regs).read(clksel(61)), 1 << 7);
+        assert_eq!(clocks.rate(Clock::ClkI2c1), 800_000_000);
+
+        match clocks.set_parent(Clock::ClkI2c1Src, Clock::Ppll) {
+            Err(ClockError::InvalidParent) => (),
+            r => panic!("{:?}", r),
+        }
+
+        clocks.set_div(Clock::ClkI2c1Div, 4).unwrap();
+        assert_eq!((&regs).read(clksel(61)), 1 << 7 | 3);
+        assert_eq!(clocks.rate(Clock::ClkI2c1), 200_000_000);
+
+        match clocks.set_div(Clock::ClkI2c1Div, 129) {
+            Err(ClockError::InvalidRate) => (),
+            r => panic!("{:?}", r),
+        }
+
+        // a mux at 2 in a 2-bit field, among 3 parents
+        assert_eq!(clocks.parent(Clock::ClkUart0), Some(Clock::ClkUart0Div));
+        clocks.set_parent(Clock::ClkUart0, Clock::Xin24m).unwrap();
+        assert_eq!((&regs).read(clksel(33)), 2 << 8);
+        assert_eq!(clocks.rate(Clock::ClkUart0), 24_000_000);
+    }
+
+    #[test]
+    fn set_rate() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        // GPLL / 5 is exact; CPLL / 7 would be under
+        assert_eq!(clocks.set_rate(Clock::ClkI2c1, 160_000_000).unwrap(), 160_000_000);
+        assert_eq!((&regs).read(clksel(61)), 1 << 7 | 4);
+        assert_eq!(clocks.rate(Clock::ClkI2c1), 160_000_000);
+
+        // never over, if under is possible
+        assert_eq!(clocks.set_rate(Clock::ClkI2c1, 150_000_000).unwrap(), 142_857_142);
+        assert_eq!((&regs).read(clksel(61)), 6);
+
+        // fractional divider, from whatever the integer one gives it
+        clocks.set_div(Clock::ClkUart0Divider, 10).unwrap();
+        assert_eq!(clocks.rate(Clock::ClkUart0Div), 100_000_000);
+        assert_eq!(clocks.set_rate(Clock::ClkUart0Frac, 1_500_000).unwrap(), 1_500_000);
+        assert_eq!(frac::unpack((&regs).read(clksel(100))), (3, 200));
+
+        // PLLs relock; oscillators stay put
+        assert_eq!(clocks.set_rate(Clock::Vpll, 594_000_000).unwrap(), 594_000_000);
+        assert_eq!(clocks.rate(Clock::Vpll), 594_000_000);
+        assert_eq!(clocks.set_rate(Clock::Xin24m, 24_000_000).unwrap(), 24_000_000);
+
+        match clocks.set_rate(Clock::Xin24m, 25_000_000) {
+            Err(ClockError::Unsupported) => (),
+            r => panic!("{:?}", r),
+        }
+
+        match clocks.set_rate(Clock::ClkI2c1, 0) {
+            Err(ClockError::InvalidRate) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn failed_parent_switch() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        clocks.enable(Clock::ClkI2c1).unwrap();
+
+        // GPLL powered down still shows 24MHz, slow mode being its input
+        // passed straight through, so it's the best way to 12MHz: half of
+        // it, where CPLL would need /84 and fall short. But it won't lock.
+        clocks.pll(Clock::Gpll).unwrap().power_down();
+        STUCK.with(|stuck| stuck.set(match Clock::Gpll.node().kind {
+            Kind::Pll { con } => Some(con.next(2)),
+            _ => None,
+        }));
+
+        // the divider goes up first, so has to come back down
+        match clocks.set_rate(Clock::ClkI2c1, 12_000_000) {
+            Err(ClockError::Pll(PLLError::LockTimeout)) => (),
+            r => panic!("{:?}", r),
+        }
+        assert_eq!((&regs).read(clksel(61)), 0);
+        assert_eq!(clocks.rate(Clock::ClkI2c1), 1_000_000_000);
+        assert_eq!(clocks.enable_count(Clock::Cpll), 1);
+        assert_eq!(clocks.enable_count(Clock::Gpll), 0);
+    }
+
+    #[test]
+    fn refcounts() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        // gated to start with
+        (&regs).write_field(clkgate(10), 0, 1, 1);
+        assert!(clocks.is_gated(Clock::ClkI2c1));
+
+        clocks.enable(Clock::ClkI2c1).unwrap();
+        clocks.enable(Clock::ClkI2c1).unwrap();
+        assert!(!clocks.is_gated(Clock::ClkI2c1));
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1), 2);
+
+        // each parent holds one reference, however many its child has
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1Div), 1);
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1Src), 1);
+        assert_eq!(clocks.enable_count(Clock::Cpll), 1);
+
+        clocks.disable(Clock::ClkI2c1).unwrap();
+        assert!(!clocks.is_gated(Clock::ClkI2c1));
+
+        // a busy mux moves its reference to the new input
+        clocks.set_parent(Clock::ClkI2c1Src, Clock::Gpll).unwrap();
+        assert_eq!(clocks.enable_count(Clock::Cpll), 0);
+        assert_eq!(clocks.enable_count(Clock::Gpll), 1);
+
+        clocks.disable(Clock::ClkI2c1).unwrap();
+        assert!(clocks.is_gated(Clock::ClkI2c1));
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1Src), 0);
+        assert_eq!(clocks.enable_count(Clock::Gpll), 0);
+
+        // critical, so the last reference going doesn't power it down
+        assert_eq!(clocks.rate(Clock::Gpll), 800_000_000);
+
+        match clocks.disable(Clock::ClkI2c1) {
+            Err(ClockError::NotEnabled) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn handles() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        let mut handle = clocks.request("clk_i2c1").unwrap();
+        assert!(clocks.request("clk_nope").is_err());
+
+        handle.enable().unwrap();
+        handle.enable().unwrap();
+        assert!(handle.is_enabled());
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1), 1);
+
+        handle.disable().unwrap();
+        handle.disable().unwrap();
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1), 0);
+
+        // dropping gives back the reference if there is one, and only then
+        handle.enable().unwrap();
+        clocks.enable(Clock::ClkI2c1).unwrap();
+        drop(handle);
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1), 1);
+
+        drop(clocks.request("clk_i2c1").unwrap());
+        assert_eq!(clocks.enable_count(Clock::ClkI2c1), 1);
+    }
+}
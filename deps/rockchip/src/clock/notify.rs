@@ -0,0 +1,37 @@
+// Rate-change notifications.
+//
+// A driver whose hardware runs from a clock registers itself as a consumer
+// of that clock. Whenever something changes the rate of that clock (or of
+// anything above it in the tree), every consumer underneath hears about it
+// three times at most:
+//
+//  - `pre_rate_change` before any register is touched. Any consumer can
+//    veto the change here; nothing has happened yet, so everyone who already
+//    agreed just gets `abort_rate_change`.
+//  - `post_rate_change` once the new rate is in place.
+//  - `abort_rate_change` instead, if the change was vetoed or the hardware
+//    couldn't be reprogrammed (the old configuration is put back first).
+
+use super::Clock;
+
+/// Most consumers the clock manager keeps track of at once.
+pub const MAX_CONSUMERS: usize = 16;
+
+/// Returned by a consumer that can't live with a proposed rate change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Veto;
+
+/// A driver that wants to know when its clock changes rate.
+///
+/// `clk` is always the clock the consumer registered for; `old` and `new`
+/// are its rates either side of the change, in Hz.
+pub trait ClockConsumer {
+    /// Get ready for the change (eg. drain FIFOs), or refuse it.
+    fn pre_rate_change(&self, clk: Clock, old: u32, new: u32) -> Result<(), Veto>;
+
+    /// The clock is now running at `new`.
+    fn post_rate_change(&self, clk: Clock, old: u32, new: u32);
+
+    /// The change didn't happen; the clock is still at `old`.
+    fn abort_rate_change(&self, clk: Clock, old: u32, new: u32);
+}
@@ -0,0 +1,329 @@
+// RK3399 PLLs.
+//
+// All eight PLLs (APLL_L, APLL_B, DPLL, CPLL, GPLL, NPLL, VPLL in the CRU
+// and PPLL in the PMUCRU) share the same register layout:
+//
+//   CON0 [11:0]  FBDIV
+//   CON1 [5:0]   REFDIV, [10:8] POSTDIV1, [14:12] POSTDIV2
+//   CON2 [23:0]  FRACDIV, [31] lock status
+//   CON3 [0]     power down, [3] DSMPD (1 = integer mode), [9:8] mode
+//
+// CON0, CON1 and CON3 have a write mask in their top 16 bits; CON2 doesn't.
+//
+//   Fout = (24MHz / REFDIV) * (FBDIV + FRACDIV / 2^24) / POSTDIV1 / POSTDIV2
+
+use super::{ClockRegisters, PLLConfiguration, PLLError, PLLSource, Reg, PLL};
+use super::{Clock, Kind};
+
+use delay::{self, Timeout};
+
+const FBDIV_SHIFT: u8 = 0;
+const FBDIV_WIDTH: u8 = 12;
+const REFDIV_SHIFT: u8 = 0;
+const REFDIV_WIDTH: u8 = 6;
+const POSTDIV1_SHIFT: u8 = 8;
+const POSTDIV2_SHIFT: u8 = 12;
+const POSTDIV_WIDTH: u8 = 3;
+const FRAC_MASK: u32 = 0xffffff;
+const LOCK_STATUS: u32 = 1 << 31;
+const POWER_DOWN_SHIFT: u8 = 0;
+const DSMPD_SHIFT: u8 = 3;
+const MODE_SHIFT: u8 = 8;
+const MODE_WIDTH: u8 = 2;
+
+const MODE_SLOW: u32 = 0;
+const MODE_NORMAL: u32 = 1;
+const MODE_DEEP_SLOW: u32 = 2;
+
+const FREF_HZ: u64 = 24_000_000;
+const DEEP_SLOW_HZ: u32 = 32_768;
+
+// limits from the TRM's PLL description
+const VCO_MIN_HZ: u64 = 800_000_000;
+const VCO_MAX_HZ: u64 = 3_200_000_000;
+const FOUT_MIN_HZ: u32 = 16_000_000;
+const FREF_DIV_MIN_HZ: u64 = 1_000_000;
+const REFDIV_MAX: u32 = 63;
+const POSTDIV_MAX: u32 = 7;
+const FBDIV_INT_MIN: u32 = 16;
+const FBDIV_INT_MAX: u32 = 3200;
+const FBDIV_FRAC_MIN: u32 = 20;
+const FBDIV_FRAC_MAX: u32 = 320;
+
+// lock usually takes a few hundred reference cycles, tens of us; wait a
+// good deal longer than that before giving up
+const LOCK_TIMEOUT_US: u32 = 1000;
+
+/// One of the RK3399's PLLs.
+pub struct RkPLL<'r, R>
+where
+    R: 'r + ClockRegisters,
+{
+    regs: &'r R,
+    con: Reg,
+}
+
+impl<'r, R> RkPLL<'r, R>
+where
+    R: 'r + ClockRegisters,
+{
+    /// The PLL behind `clk`, if it is one.
+    pub fn new(regs: &'r R, clk: Clock) -> Option<RkPLL<'r, R>> {
+        match clk.node().kind {
+            Kind::Pll { con } => Some(RkPLL { regs: regs, con: con }),
+            _ => None,
+        }
+    }
+
+    /// Current output rate in Hz; 0 if powered down or misconfigured.
+    pub fn rate(&self) -> u32 {
+        let config = self.get_config();
+
+        match config.source {
+            PLLSource::Slow_24MHz_28MHz => return FREF_HZ as u32,
+            PLLSource::DeepSlow_32_768MHz => return DEEP_SLOW_HZ,
+            PLLSource::Normal => (),
+        }
+
+        if self.regs.read_field(self.con.next(3), POWER_DOWN_SHIFT, 1) != 0 {
+            return 0;
+        }
+
+        config.rate()
+    }
+
+    /// Relock the PLL as close to `rate` as it can get, returning the rate
+    /// it's running at afterwards.
+    pub fn set_rate(&self, rate: u32) -> Result<u32, PLLError> {
+        let config = solve(rate)?;
+        let achieved = config.rate();
+
+        self.set_config(config)?;
+
+        Ok(achieved)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.regs.read(self.con.next(2)) & LOCK_STATUS != 0
+    }
+
+    /// Power the PLL up with whatever dividers it has, and wait for it to
+    /// lock before switching anything over to it.
+    pub fn power_up(&self) -> Result<(), PLLError> {
+        let con3 = self.con.next(3);
+        if self.regs.read_field(con3, POWER_DOWN_SHIFT, 1) == 0 {
+            return Ok(());
+        }
+
+        self.set_mode(MODE_SLOW);
+        self.regs.write_field(con3, POWER_DOWN_SHIFT, 1, 0);
+
+        self.wait_lock()?;
+        self.set_mode(MODE_NORMAL);
+
+        Ok(())
+    }
+
+    /// Drop to slow mode and power the PLL down.
+    pub fn power_down(&self) {
+        self.set_mode(MODE_SLOW);
+        self.regs.write_field(self.con.next(3), POWER_DOWN_SHIFT, 1, 1);
+    }
+
+    fn set_mode(&self, mode: u32) {
+        self.regs.write_field(self.con.next(3), MODE_SHIFT, MODE_WIDTH, mode);
+    }
+
+    fn wait_lock(&self) -> Result<(), PLLError> {
+        let timeout = Timeout::new(delay::system_timer(), LOCK_TIMEOUT_US);
+        while !timeout.expired() {
+            if self.is_locked() {
+                return Ok(());
+            }
+        }
+
+        Err(PLLError::LockTimeout)
+    }
+}
+
+impl<'r, R> PLL for RkPLL<'r, R>
+where
+    R: 'r + ClockRegisters,
+{
+    fn get_config(&self) -> PLLConfiguration {
+        let source = match self.regs.read_field(self.con.next(3), MODE_SHIFT, MODE_WIDTH) {
+            MODE_SLOW => PLLSource::Slow_24MHz_28MHz,
+            MODE_DEEP_SLOW => PLLSource::DeepSlow_32_768MHz,
+            _ => PLLSource::Normal,
+        };
+
+        let con1 = self.con.next(1);
+        let integer = self.regs.read_field(self.con.next(3), DSMPD_SHIFT, 1) != 0;
+
+        PLLConfiguration {
+            source: source,
+            refdiv: self.regs.read_field(con1, REFDIV_SHIFT, REFDIV_WIDTH),
+            fbdiv: self.regs.read_field(self.con, FBDIV_SHIFT, FBDIV_WIDTH),
+            postdiv1: self.regs.read_field(con1, POSTDIV1_SHIFT, POSTDIV_WIDTH),
+            postdiv2: self.regs.read_field(con1, POSTDIV2_SHIFT, POSTDIV_WIDTH),
+            frac: if integer { None } else { Some(self.regs.read(self.con.next(2)) & FRAC_MASK) },
+        }
+    }
+
+    /// Reprogram the dividers, and move to the requested mode.
+    ///
+    /// Anything running from the PLL drops to 24MHz (slow mode) while it
+    /// relocks, so this never hands out a half-settled clock.
+    fn set_config(&self, config: PLLConfiguration) -> Result<(), PLLError> {
+        match config.source {
+            PLLSource::Slow_24MHz_28MHz => {
+                self.set_mode(MODE_SLOW);
+                return Ok(());
+            },
+            PLLSource::DeepSlow_32_768MHz => {
+                self.set_mode(MODE_DEEP_SLOW);
+                return Ok(());
+            },
+            PLLSource::Normal => (),
+        }
+
+        config.validate()?;
+
+        self.set_mode(MODE_SLOW);
+
+        let con1 = self.con.next(1);
+        let con3 = self.con.next(3);
+
+        self.regs.write_field(self.con, FBDIV_SHIFT, FBDIV_WIDTH, config.fbdiv);
+        self.regs.write_field(con1, REFDIV_SHIFT, REFDIV_WIDTH, config.refdiv);
+        self.regs.write_field(con1, POSTDIV1_SHIFT, POSTDIV_WIDTH, config.postdiv1);
+        self.regs.write_field(con1, POSTDIV2_SHIFT, POSTDIV_WIDTH, config.postdiv2);
+
+        match config.frac {
+            Some(frac) => {
+                let con2 = self.regs.read(self.con.next(2)) & !FRAC_MASK;
+                self.regs.write(self.con.next(2), con2 | (frac & FRAC_MASK));
+                self.regs.write_field(con3, DSMPD_SHIFT, 1, 0);
+            },
+            None => self.regs.write_field(con3, DSMPD_SHIFT, 1, 1),
+        }
+
+        self.regs.write_field(con3, POWER_DOWN_SHIFT, 1, 0);
+
+        // leave it in slow mode if it never locks; 24MHz beats garbage
+        self.wait_lock()?;
+        self.set_mode(MODE_NORMAL);
+
+        Ok(())
+    }
+}
+
+impl PLLConfiguration {
+    /// Output rate in normal mode.
+    pub fn rate(&self) -> u32 {
+        if self.refdiv == 0 || self.postdiv1 == 0 || self.postdiv2 == 0 {
+            return 0;
+        }
+
+        let (vco, _) = self.vco();
+        (vco / self.postdiv1 as u64 / self.postdiv2 as u64) as u32
+    }
+
+    // VCO rate, and FREF / REFDIV
+    fn vco(&self) -> (u64, u64) {
+        let refdiv = self.refdiv as u64;
+        let mut vco = FREF_HZ * self.fbdiv as u64 / refdiv;
+
+        if let Some(frac) = self.frac {
+            vco += (FREF_HZ * frac as u64 / refdiv) >> 24;
+        }
+
+        (vco, FREF_HZ / refdiv)
+    }
+
+    /// Check the dividers are within what the PLL can do.
+    pub fn validate(&self) -> Result<(), PLLError> {
+        let (fbdiv_min, fbdiv_max) = match self.frac {
+            Some(_) => (FBDIV_FRAC_MIN, FBDIV_FRAC_MAX),
+            None => (FBDIV_INT_MIN, FBDIV_INT_MAX),
+        };
+
+        if self.refdiv < 1 || self.refdiv > REFDIV_MAX ||
+           self.fbdiv < fbdiv_min || self.fbdiv > fbdiv_max ||
+           self.postdiv1 < 1 || self.postdiv1 > POSTDIV_MAX ||
+           self.postdiv2 < 1 || self.postdiv2 > self.postdiv1 {
+            return Err(PLLError::InvalidConfig);
+        }
+
+        let (vco, fref) = self.vco();
+        if vco < VCO_MIN_HZ || vco > VCO_MAX_HZ || fref < FREF_DIV_MIN_HZ {
+            return Err(PLLError::InvalidConfig);
+        }
+
+        Ok(())
+    }
+}
+
+/// Find dividers giving `rate`.
+///
+/// Exact integer solutions are preferred (lowest post dividers first, then
+/// lowest REFDIV, since that gives the least jitter); otherwise fall back
+/// to fractional mode with REFDIV = 1.
+pub fn solve(rate: u32) -> Result<PLLConfiguration, PLLError> {
+    if rate < FOUT_MIN_HZ || rate as u64 > VCO_MAX_HZ {
+        return Err(PLLError::OutOfRange);
+    }
+
+    let mut fallback = None;
+
+    for postdiv1 in 1..POSTDIV_MAX + 1 {
+        for postdiv2 in 1..postdiv1 + 1 {
+            let vco = rate as u64 * postdiv1 as u64 * postdiv2 as u64;
+            if vco < VCO_MIN_HZ || vco > VCO_MAX_HZ {
+                continue;
+            }
+
+            for refdiv in 1..REFDIV_MAX + 1 {
+                if FREF_HZ / (refdiv as u64) < FREF_DIV_MIN_HZ {
+                    break;
+                }
+
+                if (vco * refdiv as u64) % FREF_HZ != 0 {
+                    continue;
+                }
+
+                let fbdiv = (vco * refdiv as u64 / FREF_HZ) as u32;
+                if fbdiv < FBDIV_INT_MIN || fbdiv > FBDIV_INT_MAX {
+                    continue;
+                }
+
+                return Ok(PLLConfiguration {
+                    source: PLLSource::Normal,
+                    refdiv: refdiv,
+                    fbdiv: fbdiv,
+                    postdiv1: postdiv1,
+                    postdiv2: postdiv2,
+                    frac: None,
+                });
+            }
+
+            if fallback.is_none() {
+                let fbdiv = (vco / FREF_HZ) as u32;
+                let frac = (((vco % FREF_HZ) << 24) / FREF_HZ) as u32;
+
+                if fbdiv >= FBDIV_FRAC_MIN && fbdiv <= FBDIV_FRAC_MAX {
+                    fallback = Some(PLLConfiguration {
+                        source: PLLSource::Normal,
+                        refdiv: 1,
+                        fbdiv: fbdiv,
+                        postdiv1: postdiv1,
+                        postdiv2: postdiv2,
+                        frac: Some(frac),
+                    });
+                }
+            }
+        }
+    }
+
+    fallback.ok_or(PLLError::NoSolution)
+}
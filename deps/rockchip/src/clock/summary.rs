@@ -0,0 +1,170 @@
+// clk_summary, after the Linux debugfs file of the same name: the whole
+// tree as the hardware has it right now, plus a check for setups that can't
+// be right.
+
+use core::fmt;
+
+use super::{Clock, ClockManager, ClockRegisters, Kind, CLOCKS};
+
+// widest name in the table, plus room for a few levels of indent
+const NAME_WIDTH: usize = 40;
+
+/// Something off about the clock tree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Issue {
+    /// `clk` is running (or wanted) but something above it is gated, so it
+    /// isn't getting a clock
+    GatedParent { clk: Clock, parent: Clock },
+
+    /// `clk` is running faster than it's rated for
+    OverMax { clk: Clock, rate: u32, max: u32 },
+
+    /// mux is set to an input we don't model
+    UnknownParent { clk: Clock },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::GatedParent { clk, parent } => {
+                write!(f, "{} is enabled but its parent {} is gated", clk.name(), parent.name())
+            },
+            Issue::OverMax { clk, rate, max } => {
+                write!(f, "{} is running at {} Hz, over its maximum of {} Hz", clk.name(), rate, max)
+            },
+            Issue::UnknownParent { clk } => {
+                write!(f, "{} is set to an unknown input", clk.name())
+            },
+        }
+    }
+}
+
+/// Printable dump of every clock in the tree, children indented under
+/// their current parent.
+pub struct Summary<'m, 'c: 'm, R>
+where
+    R: 'm + ClockRegisters,
+{
+    manager: &'m ClockManager<'c, R>,
+}
+
+impl<'m, 'c, R> Summary<'m, 'c, R>
+where
+    R: 'm + ClockRegisters,
+{
+    fn write_node(&self, f: &mut fmt::Formatter, clk: Clock, depth: usize) -> fmt::Result {
+        let manager = self.manager;
+
+        let gate = match clk.node().kind {
+            Kind::Gate { .. } if manager.is_gated(clk) => "gated",
+            Kind::Gate { .. } => "on",
+            _ => "-",
+        };
+
+        let parent = match manager.parent(clk) {
+            Some(parent) => parent.name(),
+            None => match clk.node().kind {
+                Kind::Mux { .. } => "?",
+                _ => "-",
+            },
+        };
+
+        for _ in 0..depth {
+            f.write_str("  ")?;
+        }
+
+        writeln!(f, "{:<width$} {:>12} {:>6} {:>6}  {}",
+            clk.name(), manager.rate(clk), gate, manager.enable_count(clk), parent,
+            width = NAME_WIDTH - 2 * depth)?;
+
+        for child in CLOCKS.iter().map(|node| node.id) {
+            if manager.parent(child) == Some(clk) {
+                self.write_node(f, child, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'m, 'c, R> fmt::Display for Summary<'m, 'c, R>
+where
+    R: 'm + ClockRegisters,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<width$} {:>12} {:>6} {:>6}  {}",
+            "clock", "rate", "gate", "count", "parent", width = NAME_WIDTH)?;
+
+        // roots are the oscillators and PLLs, plus any mux pointing
+        // somewhere we don't model, so nothing gets left out
+        for clk in CLOCKS.iter().map(|node| node.id) {
+            if self.manager.parent(clk).is_none() {
+                self.write_node(f, clk, 0)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'c, R> ClockManager<'c, R>
+where
+    R: ClockRegisters,
+{
+    /// Every clock's parent, rate, gate state and enable count, for
+    /// printing.
+    pub fn summary<'m>(&'m self) -> Summary<'m, 'c, R> {
+        Summary {
+            manager: self,
+        }
+    }
+
+    /// Look over the whole tree, handing anything suspicious to `report`.
+    /// Returns the number of issues found.
+    pub fn check<F>(&self, mut report: F) -> usize
+    where
+        F: FnMut(Issue),
+    {
+        let mut found = 0;
+
+        for clk in CLOCKS.iter().map(|node| node.id) {
+            if let Kind::Mux { .. } = clk.node().kind {
+                if self.parent(clk).is_none() {
+                    report(Issue::UnknownParent { clk: clk });
+                    found += 1;
+                }
+            }
+
+            if let Some(max) = clk.max_rate() {
+                let rate = self.rate(clk);
+                if rate > max {
+                    report(Issue::OverMax { clk: clk, rate: rate, max: max });
+                    found += 1;
+                }
+            }
+
+            // only gates can be on or off, so only they can be let down by
+            // a parent; report the nearest gated one
+            let enabled = match clk.node().kind {
+                Kind::Gate { .. } => !self.is_gated(clk) || self.enable_count(clk) > 0,
+                _ => false,
+            };
+
+            if enabled {
+                let mut current = self.parent(clk);
+
+                while let Some(parent) = current {
+                    if self.is_gated(parent) {
+                        report(Issue::GatedParent { clk: clk, parent: parent });
+                        found += 1;
+                        break;
+                    }
+
+                    current = self.parent(parent);
+                }
+            }
+        }
+
+        found
+    }
+}
@@ -0,0 +1,396 @@
+// RK3399 clock tree, as a table.
+//
+// Each node is a fixed oscillator, a PLL, a mux, a divider or a gate, and
+// names its parent(s) by `Clock`. Composite clocks in the TRM (mux + divider
+// + gate in one CLKSEL/CLKGATE pair) are split into a node per stage, and
+// named after the Linux clock driver where possible so the two can be
+// compared.
+//
+// Only the parts of the tree feo actually cares about are here; add to it
+// as drivers need more.
+
+use super::{Bank, Reg};
+
+/// Every clock in the model. The discriminant is the node's index in
+/// `CLOCKS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clock {
+    Xin24m,
+    Xin32k,
+
+    ApllL,
+    ApllB,
+    Dpll,
+    Cpll,
+    Gpll,
+    Npll,
+    Vpll,
+    Ppll,
+
+    ClkCoreLSrc,
+    ClkCoreL,
+    ClkCoreBSrc,
+    ClkCoreB,
+
+    AclkPerilp0Src,
+    AclkPerilp0Div,
+    AclkPerilp0,
+    HclkPerilp0Div,
+    HclkPerilp0,
+    PclkPerilp0Div,
+    PclkPerilp0,
+
+    ClkI2c1Src,
+    ClkI2c1Div,
+    ClkI2c1,
+    ClkI2c2Src,
+    ClkI2c2Div,
+    ClkI2c2,
+    ClkI2c3Src,
+    ClkI2c3Div,
+    ClkI2c3,
+
+    ClkI2c0PmuDiv,
+    ClkI2c0Pmu,
+    ClkI2c4PmuDiv,
+    ClkI2c4Pmu,
+    ClkI2c8PmuDiv,
+    ClkI2c8Pmu,
+
+    ClkUartSrc,
+    ClkUart0Src,
+    ClkUart0Divider,
+    ClkUart0Div,
+    ClkUart0Fracdiv,
+    ClkUart0Frac,
+    ClkUart0,
+    ClkUart1Divider,
+    ClkUart1Div,
+    ClkUart1Fracdiv,
+    ClkUart1Frac,
+    ClkUart1,
+    ClkUart2Divider,
+    ClkUart2Div,
+    ClkUart2Fracdiv,
+    ClkUart2Frac,
+    ClkUart2,
+    ClkUart3Divider,
+    ClkUart3Div,
+    ClkUart3Fracdiv,
+    ClkUart3Frac,
+    ClkUart3,
+    ClkUart4Src,
+    ClkUart4Divider,
+    ClkUart4Div,
+    ClkUart4Fracdiv,
+    ClkUart4Frac,
+    ClkUart4,
+
+    ClkI2s0Src,
+    ClkI2s0Divider,
+    ClkI2s0Div,
+    ClkI2s0Fracdiv,
+    ClkI2s0Frac,
+    ClkI2s0Mux,
+    ClkI2s0,
+    ClkI2s1Src,
+    ClkI2s1Divider,
+    ClkI2s1Div,
+    ClkI2s1Fracdiv,
+    ClkI2s1Frac,
+    ClkI2s1Mux,
+    ClkI2s1,
+    ClkI2s2Src,
+    ClkI2s2Divider,
+    ClkI2s2Div,
+    ClkI2s2Fracdiv,
+    ClkI2s2Frac,
+    ClkI2s2Mux,
+    ClkI2s2,
+
+    PclkPmuSrc,
+    FclkCm0sPmuPpllSrc,
+    FclkCm0sSrcPmuMux,
+    FclkCm0sSrcPmuDiv,
+    FclkCm0sSrcPmu,
+    FclkCm0sPmu,
+    SclkCm0sPmu,
+    HclkCm0sPmu,
+    DclkCm0sPmu,
+
+    FclkCm0sMux,
+    FclkCm0sDiv,
+    FclkCm0s,
+    SclkM0Perilp,
+    HclkM0Perilp,
+    DclkM0Perilp,
+    ClkM0PerilpDec,
+    HclkM0PerilpNoc,
+}
+
+pub const CLOCK_COUNT: usize = 102;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    /// free running oscillator
+    Fixed { rate: u32 },
+
+    /// PLL, configured through its CON0..CON5 registers starting at `con`
+    Pll { con: Reg },
+
+    /// picks one of `parents` with `width` bits at `shift`
+    Mux { parents: &'static [Clock], reg: Reg, shift: u8, width: u8 },
+
+    /// divides by (field + 1), `width` bits at `shift`
+    Div { parent: Clock, reg: Reg, shift: u8, width: u8 },
+
+    /// fractional divider: multiplies by numerator [31:16] over denominator
+    /// [15:0]. the register has no write mask
+    Frac { parent: Clock, reg: Reg },
+
+    /// passes `parent` through unless `bit` is set
+    Gate { parent: Clock, reg: Reg, bit: u8 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClockNode {
+    pub id: Clock,
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+// register helpers; see CRU and PMUCRU register descriptions in TRM part 1
+const fn pll_con(bank: Bank, offset: u16) -> Reg {
+    Reg { bank: bank, offset: offset }
+}
+
+const fn clksel(n: u16) -> Reg {
+    Reg { bank: Bank::Cru, offset: 0x100 + n * 4 }
+}
+
+const fn clkgate(n: u16) -> Reg {
+    Reg { bank: Bank::Cru, offset: 0x300 + n * 4 }
+}
+
+const fn pmu_clksel(n: u16) -> Reg {
+    Reg { bank: Bank::PmuCru, offset: 0x080 + n * 4 }
+}
+
+const fn pmu_clkgate(n: u16) -> Reg {
+    Reg { bank: Bank::PmuCru, offset: 0x100 + n * 4 }
+}
+
+static MUX_CORE: [Clock; 4] = [Clock::ApllL, Clock::ApllB, Clock::Dpll, Clock::Gpll];
+static MUX_CPLL_GPLL: [Clock; 2] = [Clock::Cpll, Clock::Gpll];
+static MUX_24M_PPLL: [Clock; 2] = [Clock::Xin24m, Clock::Ppll];
+static MUX_UART0: [Clock; 3] = [Clock::ClkUart0Div, Clock::ClkUart0Frac, Clock::Xin24m];
+static MUX_UART1: [Clock; 3] = [Clock::ClkUart1Div, Clock::ClkUart1Frac, Clock::Xin24m];
+static MUX_UART2: [Clock; 3] = [Clock::ClkUart2Div, Clock::ClkUart2Frac, Clock::Xin24m];
+static MUX_UART3: [Clock; 3] = [Clock::ClkUart3Div, Clock::ClkUart3Frac, Clock::Xin24m];
+static MUX_UART4: [Clock; 3] = [Clock::ClkUart4Div, Clock::ClkUart4Frac, Clock::Xin24m];
+static MUX_I2S0: [Clock; 2] = [Clock::ClkI2s0Div, Clock::ClkI2s0Frac];
+static MUX_I2S1: [Clock; 2] = [Clock::ClkI2s1Div, Clock::ClkI2s1Frac];
+static MUX_I2S2: [Clock; 2] = [Clock::ClkI2s2Div, Clock::ClkI2s2Frac];
+static MUX_FCLK_CM0S_PMU: [Clock; 2] = [Clock::FclkCm0sPmuPpllSrc, Clock::Xin24m];
+
+macro_rules! node {
+    ($id:ident, $name:expr, $kind:expr) => (
+        ClockNode { id: Clock::$id, name: $name, kind: $kind }
+    );
+}
+
+macro_rules! fixed {
+    ($rate:expr) => (Kind::Fixed { rate: $rate });
+}
+
+macro_rules! pll {
+    ($bank:ident, $con:expr) => (Kind::Pll { con: pll_con(Bank::$bank, $con) });
+}
+
+macro_rules! mux {
+    ($parents:expr, $reg:expr, $shift:expr, $width:expr) => (
+        Kind::Mux { parents: &$parents, reg: $reg, shift: $shift, width: $width }
+    );
+}
+
+macro_rules! div {
+    ($parent:ident, $reg:expr, $shift:expr, $width:expr) => (
+        Kind::Div { parent: Clock::$parent, reg: $reg, shift: $shift, width: $width }
+    );
+}
+
+macro_rules! frac {
+    ($parent:ident, $reg:expr) => (Kind::Frac { parent: Clock::$parent, reg: $reg });
+}
+
+macro_rules! gate {
+    ($parent:ident, $reg:expr, $bit:expr) => (
+        Kind::Gate { parent: Clock::$parent, reg: $reg, bit: $bit }
+    );
+}
+
+pub static CLOCKS: [ClockNode; CLOCK_COUNT] = [
+    node!(Xin24m, "xin24m", fixed!(24_000_000)),
+    node!(Xin32k, "xin32k", fixed!(32_768)),
+
+    node!(ApllL, "apll_l", pll!(Cru, 0x000)),
+    node!(ApllB, "apll_b", pll!(Cru, 0x020)),
+    node!(Dpll, "dpll", pll!(Cru, 0x040)),
+    node!(Cpll, "cpll", pll!(Cru, 0x060)),
+    node!(Gpll, "gpll", pll!(Cru, 0x080)),
+    node!(Npll, "npll", pll!(Cru, 0x0a0)),
+    node!(Vpll, "vpll", pll!(Cru, 0x0c0)),
+    node!(Ppll, "ppll", pll!(PmuCru, 0x000)),
+
+    node!(ClkCoreLSrc, "clk_core_l_src", mux!(MUX_CORE, clksel(0), 6, 2)),
+    node!(ClkCoreL, "clk_core_l", div!(ClkCoreLSrc, clksel(0), 0, 5)),
+    node!(ClkCoreBSrc, "clk_core_b_src", mux!(MUX_CORE, clksel(2), 6, 2)),
+    node!(ClkCoreB, "clk_core_b", div!(ClkCoreBSrc, clksel(2), 0, 5)),
+
+    node!(AclkPerilp0Src, "aclk_perilp0_src", mux!(MUX_CPLL_GPLL, clksel(23), 7, 1)),
+    node!(AclkPerilp0Div, "aclk_perilp0_div", div!(AclkPerilp0Src, clksel(23), 0, 5)),
+    node!(AclkPerilp0, "aclk_perilp0", gate!(AclkPerilp0Div, clkgate(7), 2)),
+    node!(HclkPerilp0Div, "hclk_perilp0_div", div!(AclkPerilp0, clksel(23), 8, 2)),
+    node!(HclkPerilp0, "hclk_perilp0", gate!(HclkPerilp0Div, clkgate(7), 3)),
+    node!(PclkPerilp0Div, "pclk_perilp0_div", div!(AclkPerilp0, clksel(23), 12, 3)),
+    node!(PclkPerilp0, "pclk_perilp0", gate!(PclkPerilp0Div, clkgate(7), 4)),
+
+    node!(ClkI2c1Src, "clk_i2c1_src", mux!(MUX_CPLL_GPLL, clksel(61), 7, 1)),
+    node!(ClkI2c1Div, "clk_i2c1_div", div!(ClkI2c1Src, clksel(61), 0, 7)),
+    node!(ClkI2c1, "clk_i2c1", gate!(ClkI2c1Div, clkgate(10), 0)),
+    node!(ClkI2c2Src, "clk_i2c2_src", mux!(MUX_CPLL_GPLL, clksel(62), 7, 1)),
+    node!(ClkI2c2Div, "clk_i2c2_div", div!(ClkI2c2Src, clksel(62), 0, 7)),
+    node!(ClkI2c2, "clk_i2c2", gate!(ClkI2c2Div, clkgate(10), 2)),
+    node!(ClkI2c3Src, "clk_i2c3_src", mux!(MUX_CPLL_GPLL, clksel(63), 7, 1)),
+    node!(ClkI2c3Div, "clk_i2c3_div", div!(ClkI2c3Src, clksel(63), 0, 7)),
+    node!(ClkI2c3, "clk_i2c3", gate!(ClkI2c3Div, clkgate(10), 4)),
+
+    node!(ClkI2c0PmuDiv, "clk_i2c0_pmu_div", div!(Ppll, pmu_clksel(2), 0, 7)),
+    node!(ClkI2c0Pmu, "clk_i2c0_pmu", gate!(ClkI2c0PmuDiv, pmu_clkgate(0), 9)),
+    node!(ClkI2c4PmuDiv, "clk_i2c4_pmu_div", div!(Ppll, pmu_clksel(3), 0, 7)),
+    node!(ClkI2c4Pmu, "clk_i2c4_pmu", gate!(ClkI2c4PmuDiv, pmu_clkgate(0), 10)),
+    node!(ClkI2c8PmuDiv, "clk_i2c8_pmu_div", div!(Ppll, pmu_clksel(2), 8, 7)),
+    node!(ClkI2c8Pmu, "clk_i2c8_pmu", gate!(ClkI2c8PmuDiv, pmu_clkgate(0), 11)),
+
+    // UART0 has its own source mux; UART1-3 share one. the 2-bit UART0 mux
+    // can also pick UPLL, which we don't model.
+    node!(ClkUartSrc, "clk_uart_src", mux!(MUX_CPLL_GPLL, clksel(33), 15, 1)),
+    node!(ClkUart0Src, "clk_uart0_src", mux!(MUX_CPLL_GPLL, clksel(33), 12, 2)),
+    node!(ClkUart0Divider, "clk_uart0_divider", div!(ClkUart0Src, clksel(33), 0, 7)),
+    node!(ClkUart0Div, "clk_uart0_div", gate!(ClkUart0Divider, clkgate(9), 0)),
+    node!(ClkUart0Fracdiv, "clk_uart0_fracdiv", frac!(ClkUart0Div, clksel(100))),
+    node!(ClkUart0Frac, "clk_uart0_frac", gate!(ClkUart0Fracdiv, clkgate(9), 1)),
+    node!(ClkUart0, "clk_uart0", mux!(MUX_UART0, clksel(33), 8, 2)),
+    node!(ClkUart1Divider, "clk_uart1_divider", div!(ClkUartSrc, clksel(34), 0, 7)),
+    node!(ClkUart1Div, "clk_uart1_div", gate!(ClkUart1Divider, clkgate(9), 2)),
+    node!(ClkUart1Fracdiv, "clk_uart1_fracdiv", frac!(ClkUart1Div, clksel(101))),
+    node!(ClkUart1Frac, "clk_uart1_frac", gate!(ClkUart1Fracdiv, clkgate(9), 3)),
+    node!(ClkUart1, "clk_uart1", mux!(MUX_UART1, clksel(34), 8, 2)),
+    node!(ClkUart2Divider, "clk_uart2_divider", div!(ClkUartSrc, clksel(35), 0, 7)),
+    node!(ClkUart2Div, "clk_uart2_div", gate!(ClkUart2Divider, clkgate(9), 4)),
+    node!(ClkUart2Fracdiv, "clk_uart2_fracdiv", frac!(ClkUart2Div, clksel(102))),
+    node!(ClkUart2Frac, "clk_uart2_frac", gate!(ClkUart2Fracdiv, clkgate(9), 5)),
+    node!(ClkUart2, "clk_uart2", mux!(MUX_UART2, clksel(35), 8, 2)),
+    node!(ClkUart3Divider, "clk_uart3_divider", div!(ClkUartSrc, clksel(36), 0, 7)),
+    node!(ClkUart3Div, "clk_uart3_div", gate!(ClkUart3Divider, clkgate(9), 6)),
+    node!(ClkUart3Fracdiv, "clk_uart3_fracdiv", frac!(ClkUart3Div, clksel(103))),
+    node!(ClkUart3Frac, "clk_uart3_frac", gate!(ClkUart3Fracdiv, clkgate(9), 7)),
+    node!(ClkUart3, "clk_uart3", mux!(MUX_UART3, clksel(36), 8, 2)),
+    node!(ClkUart4Src, "clk_uart4_src", mux!(MUX_24M_PPLL, pmu_clksel(5), 10, 1)),
+    node!(ClkUart4Divider, "clk_uart4_divider", div!(ClkUart4Src, pmu_clksel(5), 0, 7)),
+    node!(ClkUart4Div, "clk_uart4_div", gate!(ClkUart4Divider, pmu_clkgate(0), 5)),
+    node!(ClkUart4Fracdiv, "clk_uart4_fracdiv", frac!(ClkUart4Div, pmu_clksel(6))),
+    node!(ClkUart4Frac, "clk_uart4_frac", gate!(ClkUart4Fracdiv, pmu_clkgate(0), 6)),
+    node!(ClkUart4, "clk_uart4", mux!(MUX_UART4, pmu_clksel(5), 8, 2)),
+
+    // the 2-bit I2S muxes can also pick clkin_i2s and xin12m, which we
+    // don't model
+    node!(ClkI2s0Src, "clk_i2s0_src", mux!(MUX_CPLL_GPLL, clksel(28), 7, 1)),
+    node!(ClkI2s0Divider, "clk_i2s0_divider", div!(ClkI2s0Src, clksel(28), 0, 7)),
+    node!(ClkI2s0Div, "clk_i2s0_div", gate!(ClkI2s0Divider, clkgate(8), 3)),
+    node!(ClkI2s0Fracdiv, "clk_i2s0_fracdiv", frac!(ClkI2s0Div, clksel(96))),
+    node!(ClkI2s0Frac, "clk_i2s0_frac", gate!(ClkI2s0Fracdiv, clkgate(8), 4)),
+    node!(ClkI2s0Mux, "clk_i2s0_mux", mux!(MUX_I2S0, clksel(28), 8, 2)),
+    node!(ClkI2s0, "clk_i2s0", gate!(ClkI2s0Mux, clkgate(8), 5)),
+    node!(ClkI2s1Src, "clk_i2s1_src", mux!(MUX_CPLL_GPLL, clksel(29), 7, 1)),
+    node!(ClkI2s1Divider, "clk_i2s1_divider", div!(ClkI2s1Src, clksel(29), 0, 7)),
+    node!(ClkI2s1Div, "clk_i2s1_div", gate!(ClkI2s1Divider, clkgate(8), 6)),
+    node!(ClkI2s1Fracdiv, "clk_i2s1_fracdiv", frac!(ClkI2s1Div, clksel(97))),
+    node!(ClkI2s1Frac, "clk_i2s1_frac", gate!(ClkI2s1Fracdiv, clkgate(8), 7)),
+    node!(ClkI2s1Mux, "clk_i2s1_mux", mux!(MUX_I2S1, clksel(29), 8, 2)),
+    node!(ClkI2s1, "clk_i2s1", gate!(ClkI2s1Mux, clkgate(8), 8)),
+    node!(ClkI2s2Src, "clk_i2s2_src", mux!(MUX_CPLL_GPLL, clksel(30), 7, 1)),
+    node!(ClkI2s2Divider, "clk_i2s2_divider", div!(ClkI2s2Src, clksel(30), 0, 7)),
+    node!(ClkI2s2Div, "clk_i2s2_div", gate!(ClkI2s2Divider, clkgate(8), 9)),
+    node!(ClkI2s2Fracdiv, "clk_i2s2_fracdiv", frac!(ClkI2s2Div, clksel(98))),
+    node!(ClkI2s2Frac, "clk_i2s2_frac", gate!(ClkI2s2Fracdiv, clkgate(8), 10)),
+    node!(ClkI2s2Mux, "clk_i2s2_mux", mux!(MUX_I2S2, clksel(30), 8, 2)),
+    node!(ClkI2s2, "clk_i2s2", gate!(ClkI2s2Mux, clkgate(8), 11)),
+
+    node!(PclkPmuSrc, "pclk_pmu_src", div!(Ppll, pmu_clksel(0), 0, 5)),
+    node!(FclkCm0sPmuPpllSrc, "fclk_cm0s_pmu_ppll_src", gate!(Ppll, pmu_clkgate(0), 1)),
+    node!(FclkCm0sSrcPmuMux, "fclk_cm0s_src_pmu_mux", mux!(MUX_FCLK_CM0S_PMU, pmu_clksel(0), 15, 1)),
+    node!(FclkCm0sSrcPmuDiv, "fclk_cm0s_src_pmu_div", div!(FclkCm0sSrcPmuMux, pmu_clksel(0), 8, 5)),
+    node!(FclkCm0sSrcPmu, "fclk_cm0s_src_pmu", gate!(FclkCm0sSrcPmuDiv, pmu_clkgate(0), 2)),
+    node!(FclkCm0sPmu, "fclk_cm0s_pmu", gate!(FclkCm0sSrcPmu, pmu_clkgate(2), 0)),
+    node!(SclkCm0sPmu, "sclk_cm0s_pmu", gate!(FclkCm0sSrcPmu, pmu_clkgate(2), 1)),
+    node!(HclkCm0sPmu, "hclk_cm0s_pmu", gate!(FclkCm0sSrcPmu, pmu_clkgate(2), 2)),
+    node!(DclkCm0sPmu, "dclk_cm0s_pmu", gate!(FclkCm0sSrcPmu, pmu_clkgate(2), 3)),
+
+    node!(FclkCm0sMux, "fclk_cm0s_mux", mux!(MUX_CPLL_GPLL, clksel(24), 15, 1)),
+    node!(FclkCm0sDiv, "fclk_cm0s_div", div!(FclkCm0sMux, clksel(24), 8, 5)),
+    node!(FclkCm0s, "fclk_cm0s", gate!(FclkCm0sDiv, clkgate(7), 9)),
+    node!(SclkM0Perilp, "sclk_m0_perilp", gate!(FclkCm0s, clkgate(24), 8)),
+    node!(HclkM0Perilp, "hclk_m0_perilp", gate!(FclkCm0s, clkgate(24), 9)),
+    node!(DclkM0Perilp, "dclk_m0_perilp", gate!(FclkCm0s, clkgate(24), 10)),
+    node!(ClkM0PerilpDec, "clk_m0_perilp_dec", gate!(FclkCm0s, clkgate(24), 11)),
+    node!(HclkM0PerilpNoc, "hclk_m0_perilp_noc", gate!(FclkCm0s, clkgate(25), 11)),
+];
+
+impl Clock {
+    pub fn node(&self) -> &'static ClockNode {
+        let node = &CLOCKS[*self as usize];
+        debug_assert!(node.id == *self);
+        node
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.node().name
+    }
+
+    /// Clocks that stay on even once nobody holds a reference, because
+    /// things we don't model (the CPUs, DDR, the buses) run from them.
+    pub fn is_critical(&self) -> bool {
+        match *self {
+            Clock::ApllL | Clock::ApllB | Clock::Dpll | Clock::Cpll |
+            Clock::Gpll | Clock::Npll | Clock::Ppll => true,
+
+            Clock::AclkPerilp0 | Clock::HclkPerilp0 | Clock::PclkPerilp0 => true,
+
+            _ => false,
+        }
+    }
+
+    /// Fastest this clock is meant to run, where we know it. Core limits are
+    /// the top of the Linux OPP tables; the rest are from the TRM.
+    pub fn max_rate(&self) -> Option<u32> {
+        match *self {
+            Clock::ClkCoreL => Some(1_416_000_000),
+            Clock::ClkCoreB => Some(1_800_000_000),
+
+            Clock::AclkPerilp0 => Some(300_000_000),
+            Clock::HclkPerilp0 => Some(150_000_000),
+            Clock::PclkPerilp0 => Some(75_000_000),
+
+            Clock::ClkI2c1 | Clock::ClkI2c2 | Clock::ClkI2c3 |
+            Clock::ClkI2c0Pmu | Clock::ClkI2c4Pmu | Clock::ClkI2c8Pmu => Some(200_000_000),
+
+            _ => None,
+        }
+    }
+
+    /// Look a clock up by its name in the table.
+    pub fn by_name(name: &str) -> Option<Clock> {
+        CLOCKS.iter().find(|node| node.name == name).map(|node| node.id)
+    }
+}
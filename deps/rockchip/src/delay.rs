@@ -0,0 +1,304 @@
+// Busy-wait delays and timeouts against a free-running counter.
+//
+// The AP has the ARM generic timer (CNTPCT_EL0, at CNTFRQ_EL0), which
+// firmware has already set running. The M0 has no such thing, so it uses
+// one of the rk-timers, clocked from the 24MHz oscillator, which
+// `system_timer` starts the first time it's asked for.
+//
+// Either way, waits are measured in counter ticks rather than loop
+// iterations, so they don't change with the CPU clock or the compiler.
+//
+// Host tests have neither, so there `system_timer` is a counter that moves
+// on a microsecond every time it's read: a timeout of N us gives up after
+// about N polls, whatever it's polling.
+
+use hal::blocking::delay::{DelayMs, DelayUs};
+
+#[cfg(test)]
+use std::cell::Cell;
+
+#[cfg(not(target_arch = "aarch64"))]
+use core::ptr;
+
+/// A free-running up-counter.
+pub trait Clocksource {
+    fn now(&self) -> u64;
+
+    /// Ticks per second.
+    fn frequency(&self) -> u32;
+
+    fn us_to_ticks(&self, us: u32) -> u64 {
+        us as u64 * self.frequency() as u64 / 1_000_000
+    }
+}
+
+/// The ARM generic timer's physical counter.
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Copy)]
+pub struct GenericTimer;
+
+#[cfg(target_arch = "aarch64")]
+impl Clocksource for GenericTimer {
+    fn now(&self) -> u64 {
+        let ticks: u64;
+        // isb, or the read can be hoisted above whatever we're timing
+        unsafe { asm!("isb; mrs $0, cntpct_el0" : "=r"(ticks) ::: "volatile"); }
+        ticks
+    }
+
+    fn frequency(&self) -> u32 {
+        let freq: u64;
+        unsafe { asm!("mrs $0, cntfrq_el0" : "=r"(freq) ::: "volatile"); }
+
+        // only firmware can set it, and not all of it does; the counter
+        // runs from the 24MHz oscillator regardless
+        if freq == 0 { 24_000_000 } else { freq as u32 }
+    }
+}
+
+// rk-timer registers
+#[cfg(not(target_arch = "aarch64"))]
+const LOAD_COUNT0: usize = 0x00;
+#[cfg(not(target_arch = "aarch64"))]
+const LOAD_COUNT1: usize = 0x04;
+#[cfg(not(target_arch = "aarch64"))]
+const CURRENT_VALUE0: usize = 0x08;
+#[cfg(not(target_arch = "aarch64"))]
+const CURRENT_VALUE1: usize = 0x0c;
+#[cfg(not(target_arch = "aarch64"))]
+const CONTROL: usize = 0x1c;
+
+// enabled, free running, no interrupt
+#[cfg(not(target_arch = "aarch64"))]
+const CONTROL_ENABLE: u32 = 1 << 0;
+
+/// The PMU's rk-timer (0xff360000 on the AP), as the M0 sees it.
+#[cfg(not(target_arch = "aarch64"))]
+pub const PMU_TIMER_BASE: usize = 0x47360000;
+
+/// One of the rk-timers, running free from 24MHz. Counts down from its load
+/// count, which `start` sets as high as it goes; `now` inverts it, so it
+/// reads as counting up from zero.
+#[cfg(not(target_arch = "aarch64"))]
+#[derive(Clone, Copy)]
+pub struct RkTimer {
+    base: usize,
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+impl RkTimer {
+    pub const fn new(base: usize) -> RkTimer {
+        RkTimer {
+            base: base,
+        }
+    }
+
+    /// The PMU timer; `start` it once before use, or get it from
+    /// `system_timer`, which does.
+    pub const fn pmu() -> RkTimer {
+        RkTimer::new(PMU_TIMER_BASE)
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    /// Whether it's been started.
+    pub fn running(&self) -> bool {
+        unsafe { ptr::read_volatile(self.reg(CONTROL)) & CONTROL_ENABLE != 0 }
+    }
+
+    /// Restart the count from zero.
+    pub fn start(&self) {
+        unsafe {
+            ptr::write_volatile(self.reg(CONTROL), 0);
+            ptr::write_volatile(self.reg(LOAD_COUNT0), 0xffffffff);
+            ptr::write_volatile(self.reg(LOAD_COUNT1), 0xffffffff);
+            ptr::write_volatile(self.reg(CONTROL), CONTROL_ENABLE);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+impl Clocksource for RkTimer {
+    fn now(&self) -> u64 {
+        unsafe {
+            // the low half can borrow from the high between the two reads
+            loop {
+                let high = ptr::read_volatile(self.reg(CURRENT_VALUE1));
+                let low = ptr::read_volatile(self.reg(CURRENT_VALUE0));
+
+                if ptr::read_volatile(self.reg(CURRENT_VALUE1)) == high {
+                    return !((high as u64) << 32 | low as u64);
+                }
+            }
+        }
+    }
+
+    fn frequency(&self) -> u32 {
+        24_000_000
+    }
+}
+
+/// A counter for host tests, a microsecond on every read.
+#[cfg(test)]
+#[derive(Clone, Copy)]
+pub struct TestTimer;
+
+#[cfg(test)]
+thread_local! {
+    static TEST_NOW: Cell<u64> = Cell::new(0);
+}
+
+#[cfg(test)]
+impl Clocksource for TestTimer {
+    fn now(&self) -> u64 {
+        TEST_NOW.with(|now| {
+            now.set(now.get() + 1);
+            now.get()
+        })
+    }
+
+    fn frequency(&self) -> u32 {
+        1_000_000
+    }
+}
+
+/// Whichever counter this side of the SoC uses.
+#[cfg(all(target_arch = "aarch64", not(test)))]
+pub type SystemTimer = GenericTimer;
+
+#[cfg(all(not(target_arch = "aarch64"), not(test)))]
+pub type SystemTimer = RkTimer;
+
+#[cfg(test)]
+pub type SystemTimer = TestTimer;
+
+#[cfg(all(target_arch = "aarch64", not(test)))]
+pub fn system_timer() -> SystemTimer {
+    GenericTimer
+}
+
+/// On the M0, this is the PMU timer, started here if nothing has yet.
+#[cfg(all(not(target_arch = "aarch64"), not(test)))]
+pub fn system_timer() -> SystemTimer {
+    let timer = RkTimer::pmu();
+    if !timer.running() {
+        timer.start();
+    }
+
+    timer
+}
+
+#[cfg(test)]
+pub fn system_timer() -> SystemTimer {
+    TestTimer
+}
+
+/// Blocking delays.
+pub struct Delay<C>
+where
+    C: Clocksource,
+{
+    source: C,
+}
+
+impl<C> Delay<C>
+where
+    C: Clocksource,
+{
+    pub fn new(source: C) -> Delay<C> {
+        Delay {
+            source: source,
+        }
+    }
+
+    fn wait(&self, ticks: u64) {
+        let start = self.source.now();
+        while self.source.now().wrapping_sub(start) < ticks { }
+    }
+}
+
+impl<C> DelayUs<u32> for Delay<C>
+where
+    C: Clocksource,
+{
+    fn delay_us(&mut self, us: u32) {
+        let ticks = self.source.us_to_ticks(us);
+        self.wait(ticks);
+    }
+}
+
+impl<C> DelayUs<u16> for Delay<C>
+where
+    C: Clocksource,
+{
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(us as u32);
+    }
+}
+
+impl<C> DelayUs<u8> for Delay<C>
+where
+    C: Clocksource,
+{
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(us as u32);
+    }
+}
+
+impl<C> DelayMs<u32> for Delay<C>
+where
+    C: Clocksource,
+{
+    fn delay_ms(&mut self, ms: u32) {
+        let ticks = ms as u64 * self.source.frequency() as u64 / 1000;
+        self.wait(ticks);
+    }
+}
+
+impl<C> DelayMs<u16> for Delay<C>
+where
+    C: Clocksource,
+{
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+impl<C> DelayMs<u8> for Delay<C>
+where
+    C: Clocksource,
+{
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+/// A deadline, for giving up on polling hardware.
+pub struct Timeout<C>
+where
+    C: Clocksource,
+{
+    source: C,
+    start: u64,
+    ticks: u64,
+}
+
+impl<C> Timeout<C>
+where
+    C: Clocksource,
+{
+    /// Time out `us` microseconds from now.
+    pub fn new(source: C, us: u32) -> Timeout<C> {
+        Timeout {
+            start: source.now(),
+            ticks: source.us_to_ticks(us),
+            source: source,
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        self.source.now().wrapping_sub(self.start) >= self.ticks
+    }
+}
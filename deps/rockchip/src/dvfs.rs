@@ -0,0 +1,260 @@
+// CPU frequency and voltage scaling for the two clusters.
+//
+// Each cluster's cores run from clk_core_{l,b}: a mux picking APLL_L/APLL_B
+// (or DPLL/GPLL), then a divider. Changing frequency means relocking the
+// cluster's APLL, which drops its output to 24MHz for the duration, so the
+// cores are moved over to GPLL (divided down to no faster than either the
+// old or new rate) until the APLL has locked again.
+//
+// Voltage always leads on the way up and trails on the way down, so the
+// cores are never running faster than their supply allows.
+
+use clock::{Clock, ClockError, ClockManager, ClockRegisters};
+use regulator::{self, Regulator};
+
+/// An error from changing operating point
+#[derive(Debug)]
+pub enum Error {
+    /// No operating point at that index
+    InvalidOpp,
+
+    /// Reconfiguring the cluster's clocks failed; the cores may have been
+    /// left running from GPLL
+    Clock(ClockError),
+
+    /// Changing the supply voltage failed
+    Regulator(regulator::Error),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<ClockError> for Error {
+    fn from(e: ClockError) -> Error {
+        Error::Clock(e)
+    }
+}
+
+impl From<regulator::Error> for Error {
+    fn from(e: regulator::Error) -> Error {
+        Error::Regulator(e)
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An operating point: a frequency, and the voltage it needs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Opp {
+    pub hz: u32,
+    pub uv: u32,
+}
+
+macro_rules! opp {
+    ($mhz:expr, $uv:expr) => (Opp { hz: $mhz * 1_000_000, uv: $uv });
+}
+
+/// Little cluster operating points, from the Linux rk3399-opp.dtsi.
+pub static OPP_LITTLE: [Opp; 6] = [
+    opp!(408, 825000),
+    opp!(600, 825000),
+    opp!(816, 850000),
+    opp!(1008, 925000),
+    opp!(1200, 1000000),
+    opp!(1416, 1125000),
+];
+
+/// Big cluster operating points, from the Linux rk3399-opp.dtsi.
+pub static OPP_BIG: [Opp; 8] = [
+    opp!(408, 825000),
+    opp!(600, 825000),
+    opp!(816, 825000),
+    opp!(1008, 875000),
+    opp!(1200, 950000),
+    opp!(1416, 1025000),
+    opp!(1608, 1100000),
+    opp!(1800, 1200000),
+];
+
+/// One of the two CPU clusters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cluster {
+    Little,
+    Big,
+}
+
+impl Cluster {
+    pub fn pll(&self) -> Clock {
+        match *self {
+            Cluster::Little => Clock::ApllL,
+            Cluster::Big => Clock::ApllB,
+        }
+    }
+
+    fn core_mux(&self) -> Clock {
+        match *self {
+            Cluster::Little => Clock::ClkCoreLSrc,
+            Cluster::Big => Clock::ClkCoreBSrc,
+        }
+    }
+
+    pub fn core(&self) -> Clock {
+        match *self {
+            Cluster::Little => Clock::ClkCoreL,
+            Cluster::Big => Clock::ClkCoreB,
+        }
+    }
+
+    pub fn opps(&self) -> &'static [Opp] {
+        match *self {
+            Cluster::Little => &OPP_LITTLE,
+            Cluster::Big => &OPP_BIG,
+        }
+    }
+}
+
+/// Picks the next operating point. `load` is how busy the cluster has
+/// been since the last call, as a percentage.
+pub trait Governor {
+    fn select(&mut self, opps: &[Opp], current: Option<usize>, load: u8) -> usize;
+}
+
+/// Always the fastest operating point.
+pub struct Performance;
+
+impl Governor for Performance {
+    fn select(&mut self, opps: &[Opp], _current: Option<usize>, _load: u8) -> usize {
+        opps.len() - 1
+    }
+}
+
+/// Always the slowest operating point.
+pub struct Powersave;
+
+impl Governor for Powersave {
+    fn select(&mut self, _opps: &[Opp], _current: Option<usize>, _load: u8) -> usize {
+        0
+    }
+}
+
+/// Jump to the top when busy, then step down one operating point at a time
+/// while mostly idle.
+pub struct OnDemand {
+    pub up_threshold: u8,
+    pub down_threshold: u8,
+}
+
+impl Governor for OnDemand {
+    fn select(&mut self, opps: &[Opp], current: Option<usize>, load: u8) -> usize {
+        let top = opps.len() - 1;
+
+        match current {
+            None => top,
+            Some(_) if load >= self.up_threshold => top,
+            Some(n) if load < self.down_threshold && n > 0 => n - 1,
+            Some(n) => n,
+        }
+    }
+}
+
+/// Frequency and voltage control for one cluster.
+pub struct Cpufreq<'m, 'c: 'm, R, V>
+where
+    R: 'm + ClockRegisters,
+    V: Regulator,
+{
+    clocks: &'m ClockManager<'c, R>,
+    cluster: Cluster,
+    supply: V,
+}
+
+impl<'m, 'c, R, V> Cpufreq<'m, 'c, R, V>
+where
+    R: 'm + ClockRegisters,
+    V: Regulator,
+{
+    /// `supply` is the cluster's core rail: RK808 BUCK1 for the little
+    /// cluster and a FAN53555 for the big one, on most boards.
+    pub fn new(clocks: &'m ClockManager<'c, R>, cluster: Cluster, supply: V) -> Cpufreq<'m, 'c, R, V> {
+        Cpufreq {
+            clocks: clocks,
+            cluster: cluster,
+            supply: supply,
+        }
+    }
+
+    pub fn opps(&self) -> &'static [Opp] {
+        self.cluster.opps()
+    }
+
+    /// Current core clock rate in Hz.
+    pub fn rate(&self) -> u32 {
+        self.clocks.rate(self.cluster.core())
+    }
+
+    /// Index of the operating point the cluster is running at; `None` if
+    /// it isn't at one (eg. straight out of U-Boot).
+    pub fn current(&self) -> Option<usize> {
+        let rate = self.rate();
+        self.opps().iter().position(|opp| opp.hz == rate)
+    }
+
+    /// Ask `governor` where to go next, and go there.
+    pub fn update<G>(&mut self, governor: &mut G, load: u8) -> Result<usize>
+    where
+        G: Governor,
+    {
+        let current = self.current();
+        let next = governor.select(self.opps(), current, load);
+
+        if Some(next) != current {
+            self.set_opp(next)?;
+        }
+
+        Ok(next)
+    }
+
+    /// Move to operating point `index`.
+    pub fn set_opp(&mut self, index: usize) -> Result<()> {
+        let opp = *self.opps().get(index).ok_or(Error::InvalidOpp)?;
+
+        let old_uv = self.supply.voltage()?;
+        let old_hz = self.rate();
+
+        if opp.uv > old_uv {
+            self.supply.set_voltage(opp.uv)?;
+        }
+
+        self.relock(old_hz, opp.hz)?;
+
+        if opp.uv < old_uv {
+            self.supply.set_voltage(opp.uv)?;
+        }
+
+        Ok(())
+    }
+
+    // move the cores to GPLL, relock the APLL at `new_hz` and move them
+    // back; the divider only ever goes up before a switch, and down after,
+    // so the cores never see more than the slower of the two rates
+    fn relock(&self, old_hz: u32, new_hz: u32) -> Result<()> {
+        let clocks = self.clocks;
+        let core = self.cluster.core();
+        let mux = self.cluster.core_mux();
+        let pll = self.cluster.pll();
+
+        let ceiling = if old_hz < new_hz { old_hz } else { new_hz };
+        let gpll = clocks.rate(Clock::Gpll);
+        let safe_div = if ceiling == 0 || gpll <= ceiling { 1 } else { (gpll + ceiling - 1) / ceiling };
+
+        clocks.set_div(core, safe_div)?;
+        clocks.set_parent(mux, Clock::Gpll)?;
+
+        clocks.set_rate(pll, new_hz)?;
+
+        clocks.set_parent(mux, pll)?;
+        clocks.set_div(core, 1)?;
+
+        Ok(())
+    }
+}
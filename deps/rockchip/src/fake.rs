@@ -0,0 +1,64 @@
+// A register file for host tests, standing in for a GRF, CRU or SGRF.
+//
+// Nearly every register in those blocks takes a write mask in its top 16
+// bits; `store_masked` applies it the way the hardware does, and `store`
+// takes the value whole, for the few that don't. Each driver's tests
+// implement its register trait on `&FakeRegisters<Reg>` in terms of these.
+//
+// Writes are also logged exactly as they were made, for tests that care
+// what was written as well as what the register ended up holding.
+
+use std::cell::RefCell;
+use std::vec::Vec;
+
+pub struct FakeRegisters<R> {
+    values: RefCell<Vec<(R, u32)>>,
+    writes: RefCell<Vec<(R, u32)>>,
+}
+
+impl<R> FakeRegisters<R>
+where
+    R: Copy + PartialEq,
+{
+    pub fn new() -> FakeRegisters<R> {
+        FakeRegisters {
+            values: RefCell::new(Vec::new()),
+            writes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// What `reg` holds; 0 until something's written to it.
+    pub fn value(&self, reg: R) -> u32 {
+        self.values.borrow().iter().find(|v| v.0 == reg).map_or(0, |v| v.1)
+    }
+
+    /// Write `value` whole.
+    pub fn store(&self, reg: R, value: u32) {
+        self.writes.borrow_mut().push((reg, value));
+        self.set(reg, value);
+    }
+
+    /// Write the low 16 bits of `value` through the mask in its top 16.
+    pub fn store_masked(&self, reg: R, value: u32) {
+        let mask = value >> 16;
+        let merged = self.value(reg) & !mask & 0xffff | value & mask;
+
+        self.writes.borrow_mut().push((reg, value));
+        self.set(reg, merged);
+    }
+
+    /// Every write since the last `clear_writes`, as it was made.
+    pub fn writes(&self) -> Vec<(R, u32)> {
+        self.writes.borrow().clone()
+    }
+
+    pub fn clear_writes(&self) {
+        self.writes.borrow_mut().clear();
+    }
+
+    fn set(&self, reg: R, value: u32) {
+        let mut values = self.values.borrow_mut();
+        values.retain(|v| v.0 != reg);
+        values.push((reg, value));
+    }
+}
@@ -0,0 +1,288 @@
+use i2c::{self, I2CTrait};
+use regulator::{self, LinearRange, Regulator, Error};
+
+const VSEL0: u8 = 0x00;
+const VSEL1: u8 = 0x01;
+const CONTROL: u8 = 0x02;
+const ID1: u8 = 0x03;
+const ID2: u8 = 0x04;
+const MONITOR: u8 = 0x05;
+
+const VSEL_BUCK_EN: u8 = 1 << 7;
+const VSEL_MODE: u8 = 1 << 6;
+const VSEL_NSEL_MASK: u8 = 0x3f;
+
+const CONTROL_SLEW_SHIFT: u8 = 4;
+const CONTROL_SLEW_MASK: u8 = 0x7 << CONTROL_SLEW_SHIFT;
+
+const ID1_VENDOR_SHIFT: u8 = 5;
+const ID1_DIE_ID_MASK: u8 = 0x0f;
+const ID2_DIE_REV_MASK: u8 = 0x0f;
+
+// vendor code Fairchild parts put in ID1[7:5]; the Silergy clones don't
+const VENDOR_FAIRCHILD: u8 = 0b100;
+
+// Fairchild die 0 revisions; the range moved between them
+const FAIRCHILD_REV_00: u8 = 0x3;
+const FAIRCHILD_REV_13: u8 = 0xf;
+
+// Silergy die IDs
+const SILERGY_SYR82X: u8 = 8;
+const SILERGY_SYR83X: u8 = 9;
+
+/// Output slew rates for each CONTROL[6:4] value, in uV/us.
+const SLEW_RATES: [u32; 8] = [64_000, 32_000, 16_000, 8_000, 4_000, 2_000, 1_000, 500];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vendor {
+    Fairchild,
+    Silergy,
+}
+
+/// Contents of the ID registers.
+#[derive(Clone, Copy, Debug)]
+pub struct ChipId {
+    pub vendor: Option<Vendor>,
+    pub die_id: u8,
+    pub die_rev: u8,
+}
+
+/// Which of the two VSEL registers is driving the output is decided by the
+/// part's VSEL pin, so it depends on board wiring. The other one takes over
+/// when the pin flips (usually on suspend).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vsel {
+    Vsel0,
+    Vsel1,
+}
+
+impl Vsel {
+    fn reg(&self) -> u8 {
+        match *self {
+            Vsel::Vsel0 => VSEL0,
+            Vsel::Vsel1 => VSEL1,
+        }
+    }
+
+    fn other(&self) -> Vsel {
+        match *self {
+            Vsel::Vsel0 => Vsel::Vsel1,
+            Vsel::Vsel1 => Vsel::Vsel0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// drop into PFM at light load
+    Auto,
+    /// always PWM; lower ripple, worse light-load efficiency
+    ForcedPwm,
+}
+
+/// A Fairchild FAN53555, or one of Silergy's register compatible SYR82x /
+/// SYR83x parts.
+pub struct Fan53555<T>
+where
+    T: I2CTrait,
+{
+    bus: T,
+    address: u8,
+    active: Vsel,
+    id: ChipId,
+    range: LinearRange,
+}
+
+// output range for a given part, as Linux's fan53555 driver has them; all
+// of them have 6-bit NSEL
+fn range_for(vendor: Vendor, die_id: u8, die_rev: u8) -> Option<LinearRange> {
+    let (min_uv, step_uv) = match (vendor, die_id) {
+        (Vendor::Fairchild, 0) => match die_rev {
+            FAIRCHILD_REV_00 => (600_000, 10_000),
+            FAIRCHILD_REV_13 => (800_000, 10_000),
+            _ => return None,
+        },
+        (Vendor::Fairchild, 1) |
+        (Vendor::Fairchild, 3) |
+        (Vendor::Fairchild, 5) |
+        (Vendor::Fairchild, 8) => (600_000, 10_000),
+        (Vendor::Fairchild, 4) => (603_000, 12_826),
+        (Vendor::Silergy, SILERGY_SYR82X) |
+        (Vendor::Silergy, SILERGY_SYR83X) => (712_500, 12_500),
+        _ => return None,
+    };
+
+    Some(LinearRange {
+        min_uv: min_uv,
+        min_sel: 0,
+        max_sel: VSEL_NSEL_MASK,
+        step_uv: step_uv,
+    })
+}
+
+impl<T> Fan53555<T>
+where
+    T: I2CTrait,
+{
+    /// Read the ID registers and work out what the part is.
+    ///
+    /// Silergy parts don't carry a vendor code, so anything not claiming to
+    /// be Fairchild with a known Silergy die ID is taken to be Silergy.
+    pub fn probe(bus: T, address: u8, active: Vsel) -> regulator::Result<Fan53555<T>> {
+        let mut ids = [0u8; 2];
+        bus.read_from(address, Some(ID1), &mut ids)?;
+
+        let die_id = ids[0] & ID1_DIE_ID_MASK;
+        let vendor = match ids[0] >> ID1_VENDOR_SHIFT {
+            VENDOR_FAIRCHILD => Some(Vendor::Fairchild),
+            _ if die_id == SILERGY_SYR82X || die_id == SILERGY_SYR83X => Some(Vendor::Silergy),
+            _ => None,
+        };
+
+        let id = ChipId {
+            vendor: vendor,
+            die_id: die_id,
+            die_rev: ids[1] & ID2_DIE_REV_MASK,
+        };
+
+        let range = vendor.and_then(|v| range_for(v, id.die_id, id.die_rev))
+            .ok_or(Error::Unsupported)?;
+
+        Ok(Fan53555 {
+            bus: bus,
+            address: address,
+            active: active,
+            id: id,
+            range: range,
+        })
+    }
+
+    pub fn id(&self) -> ChipId {
+        self.id
+    }
+
+    /// Give back the underlying bus.
+    pub fn free(self) -> T {
+        self.bus
+    }
+
+    /// Set the voltage the output switches to when VSEL flips.
+    pub fn set_suspend_voltage(&self, uv: u32) -> regulator::Result<()> {
+        self.set_nsel(self.active.other(), uv)
+    }
+
+    pub fn set_suspend_enabled(&self, enabled: bool) -> regulator::Result<()> {
+        let reg = self.active.other().reg();
+        Ok(self.update_reg(reg, VSEL_BUCK_EN, if enabled { VSEL_BUCK_EN } else { 0 })?)
+    }
+
+    /// Limit how fast the output moves between voltages. Picks the fastest
+    /// rate that doesn't exceed `uv_per_us`.
+    pub fn set_slew_rate(&self, uv_per_us: u32) -> regulator::Result<u32> {
+        let idx = SLEW_RATES.iter().position(|rate| *rate <= uv_per_us).ok_or(Error::OutOfRange)?;
+
+        self.update_reg(CONTROL, CONTROL_SLEW_MASK, (idx as u8) << CONTROL_SLEW_SHIFT)?;
+
+        Ok(SLEW_RATES[idx])
+    }
+
+    pub fn slew_rate(&self) -> regulator::Result<u32> {
+        let idx = (self.read_reg(CONTROL)? & CONTROL_SLEW_MASK) >> CONTROL_SLEW_SHIFT;
+        Ok(SLEW_RATES[idx as usize])
+    }
+
+    pub fn set_mode(&self, mode: Mode) -> regulator::Result<()> {
+        let bits = match mode {
+            Mode::Auto => 0,
+            Mode::ForcedPwm => VSEL_MODE,
+        };
+
+        Ok(self.update_reg(self.active.reg(), VSEL_MODE, bits)?)
+    }
+
+    pub fn mode(&self) -> regulator::Result<Mode> {
+        match self.read_reg(self.active.reg())? & VSEL_MODE {
+            0 => Ok(Mode::Auto),
+            _ => Ok(Mode::ForcedPwm),
+        }
+    }
+
+    /// Raw MONITOR register (power good, thermal and fault flags).
+    pub fn monitor(&self) -> regulator::Result<u8> {
+        Ok(self.read_reg(MONITOR)?)
+    }
+
+    fn set_nsel(&self, vsel: Vsel, uv: u32) -> regulator::Result<()> {
+        let sel = self.range.selector(uv).ok_or(Error::OutOfRange)?;
+        Ok(self.update_reg(vsel.reg(), VSEL_NSEL_MASK, sel)?)
+    }
+
+    fn read_reg(&self, reg: u8) -> i2c::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.bus.read_from(self.address, Some(reg), &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn update_reg(&self, reg: u8, mask: u8, value: u8) -> i2c::Result<()> {
+        let old = self.read_reg(reg)?;
+        let new = (old & !mask) | (value & mask);
+
+        if new != old {
+            self.bus.write_to(self.address, Some(reg), &[new])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Regulator for Fan53555<T>
+where
+    T: I2CTrait,
+{
+    fn voltage(&self) -> regulator::Result<u32> {
+        let sel = self.read_reg(self.active.reg())? & VSEL_NSEL_MASK;
+        self.range.voltage(sel).ok_or(Error::OutOfRange)
+    }
+
+    fn set_voltage(&self, uv: u32) -> regulator::Result<()> {
+        self.set_nsel(self.active, uv)
+    }
+
+    fn is_enabled(&self) -> regulator::Result<bool> {
+        Ok(self.read_reg(self.active.reg())? & VSEL_BUCK_EN != 0)
+    }
+
+    fn set_enabled(&self, enabled: bool) -> regulator::Result<()> {
+        Ok(self.update_reg(self.active.reg(), VSEL_BUCK_EN, if enabled { VSEL_BUCK_EN } else { 0 })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn min_and_step(vendor: Vendor, die_id: u8, die_rev: u8) -> Option<(u32, u32)> {
+        range_for(vendor, die_id, die_rev).map(|r| (r.min_uv, r.step_uv))
+    }
+
+    #[test]
+    fn fairchild_ranges() {
+        assert_eq!(min_and_step(Vendor::Fairchild, 0, FAIRCHILD_REV_00), Some((600_000, 10_000)));
+        assert_eq!(min_and_step(Vendor::Fairchild, 0, FAIRCHILD_REV_13), Some((800_000, 10_000)));
+        assert_eq!(min_and_step(Vendor::Fairchild, 0, 0), None);
+        assert_eq!(min_and_step(Vendor::Fairchild, 1, 0), Some((600_000, 10_000)));
+        assert_eq!(min_and_step(Vendor::Fairchild, 2, 0), None);
+        assert_eq!(min_and_step(Vendor::Fairchild, 3, 0), Some((600_000, 10_000)));
+        assert_eq!(min_and_step(Vendor::Fairchild, 4, 0), Some((603_000, 12_826)));
+        assert_eq!(min_and_step(Vendor::Fairchild, 5, 0), Some((600_000, 10_000)));
+        assert_eq!(min_and_step(Vendor::Fairchild, 6, 0), None);
+        assert_eq!(min_and_step(Vendor::Fairchild, 8, 0), Some((600_000, 10_000)));
+    }
+
+    #[test]
+    fn silergy_ranges() {
+        assert_eq!(min_and_step(Vendor::Silergy, SILERGY_SYR82X, 0), Some((712_500, 12_500)));
+        assert_eq!(min_and_step(Vendor::Silergy, SILERGY_SYR83X, 0), Some((712_500, 12_500)));
+        assert_eq!(min_and_step(Vendor::Silergy, 0, 0), None);
+    }
+}
@@ -0,0 +1,234 @@
+use spin::{Mutex, MutexGuard};
+
+use super::{I2CLock, I2CSpeed, I2CTrait, Result};
+
+/// Standard-mode SCL rate; anything on an I2C bus should cope with this.
+pub const STANDARD_MODE_HZ: u32 = 100_000;
+
+/// Fast-mode SCL rate.
+pub const FAST_MODE_HZ: u32 = 400_000;
+
+struct BusState<T> {
+    controller: T,
+
+    /// SCL rate last programmed into the controller, if we've done so yet
+    scl_hz: Option<u32>,
+}
+
+/// An I2C controller shared between several device drivers.
+///
+/// `I2C` itself is `Copy`, so nothing stops two drivers from interleaving
+/// their frames on the same controller. Instead, hand the controller to an
+/// `I2CBus`, and give each driver a `BusDevice` proxy from `device`.
+///
+/// Every transfer through a proxy holds the bus lock for its whole duration,
+/// and reprograms SCL to suit the device first if the last user of the bus
+/// wanted a different speed.
+pub struct I2CBus<T>
+where
+    T: I2CTrait + I2CSpeed,
+{
+    state: Mutex<BusState<T>>,
+
+    /// rate of the clock feeding the controller, used to derive SCL
+    input_hz: u32,
+}
+
+impl<T> I2CBus<T>
+where
+    T: I2CTrait + I2CSpeed,
+{
+    pub fn new(controller: T, input_hz: u32) -> I2CBus<T> {
+        I2CBus {
+            state: Mutex::new(BusState {
+                controller: controller,
+                scl_hz: None,
+            }),
+            input_hz: input_hz,
+        }
+    }
+
+    /// Create a proxy for the device at 7-bit `address`, which can be
+    /// clocked at up to `scl_hz`.
+    pub fn device(&self, address: u8, scl_hz: u32) -> BusDevice<T> {
+        BusDevice {
+            bus: self,
+            address: address,
+            scl_hz: scl_hz,
+        }
+    }
+
+    // take the lock, and make sure the controller is running at the speed
+    // the caller needs before handing it over
+    fn acquire(&self, scl_hz: u32) -> MutexGuard<BusState<T>> {
+        let mut state = self.state.lock();
+
+        if state.scl_hz != Some(scl_hz) {
+            state.controller.set_scl_rate(self.input_hz, scl_hz);
+            state.scl_hz = Some(scl_hz);
+        }
+
+        state
+    }
+}
+
+/// A single device's view of a shared `I2CBus`.
+///
+/// Other devices on the bus can't get a transfer in while one of ours is in
+/// progress. Use `transaction` when several transfers must happen back to
+/// back (eg. a read-modify-write of a register).
+pub struct BusDevice<'b, T>
+where
+    T: 'b + I2CTrait + I2CSpeed,
+{
+    bus: &'b I2CBus<T>,
+    address: u8,
+    scl_hz: u32,
+}
+
+impl<'b, T> BusDevice<'b, T>
+where
+    T: 'b + I2CTrait + I2CSpeed,
+{
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn scl_rate(&self) -> u32 {
+        self.scl_hz
+    }
+
+    /// Read from this device, optionally starting at `register`.
+    pub fn read(&self, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+        self.transaction(|t| t.read(register, buf))
+    }
+
+    /// Write to this device, optionally starting at `register`.
+    pub fn write(&self, register: Option<u8>, data: &[u8]) -> Result<usize> {
+        self.transaction(|t| t.write(register, data))
+    }
+
+    /// Write up to three bytes, then read back after a repeated START.
+    pub fn write_read(&self, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+        self.transaction(|t| t.write_read(out, buf))
+    }
+
+    /// Run `f` with exclusive use of the bus.
+    ///
+    /// Don't touch the same bus through another proxy from within `f`; the
+    /// lock isn't reentrant, so that will spin forever.
+    pub fn transaction<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Transaction<T>) -> R,
+    {
+        let state = self.bus.acquire(self.scl_hz);
+
+        f(&Transaction {
+            controller: &state.controller,
+            address: self.address,
+        })
+    }
+}
+
+impl<'b, T> Clone for BusDevice<'b, T>
+where
+    T: 'b + I2CTrait + I2CSpeed,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'b, T> Copy for BusDevice<'b, T>
+where
+    T: 'b + I2CTrait + I2CSpeed,
+{
+}
+
+/// Transfers to other addresses still go through the lock and use this
+/// device's speed, so that things like muxes and multi-address EEPROMs can
+/// sit behind a single proxy.
+impl<'b, T> I2CTrait for BusDevice<'b, T>
+where
+    T: 'b + I2CTrait + I2CSpeed,
+{
+    fn read_from(&self, address: u8, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+        self.transaction(|t| t.read_from(address, register, buf))
+    }
+
+    fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> Result<usize> {
+        self.transaction(|t| t.write_to(address, register, data))
+    }
+
+    fn write_read(&self, address: u8, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+        self.transaction(|t| I2CTrait::write_read(t, address, out, buf))
+    }
+}
+
+impl<'b, T> I2CLock for BusDevice<'b, T>
+where
+    T: 'b + I2CTrait + I2CSpeed,
+{
+    fn locked<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&I2CTrait) -> Result<R>,
+    {
+        self.transaction(|t| f(t))
+    }
+}
+
+/// The bus while it's locked by a `BusDevice`.
+pub struct Transaction<'t, T>
+where
+    T: 't + I2CTrait,
+{
+    controller: &'t T,
+    address: u8,
+}
+
+impl<'t, T> Transaction<'t, T>
+where
+    T: 't + I2CTrait,
+{
+    pub fn read(&self, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+        self.controller.read_from(self.address, register, buf)
+    }
+
+    pub fn write(&self, register: Option<u8>, data: &[u8]) -> Result<usize> {
+        self.controller.write_to(self.address, register, data)
+    }
+
+    pub fn write_read(&self, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+        self.controller.write_read(self.address, out, buf)
+    }
+}
+
+impl<'t, T> I2CTrait for Transaction<'t, T>
+where
+    T: 't + I2CTrait,
+{
+    fn read_from(&self, address: u8, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+        self.controller.read_from(address, register, buf)
+    }
+
+    fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> Result<usize> {
+        self.controller.write_to(address, register, data)
+    }
+
+    fn write_read(&self, address: u8, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+        self.controller.write_read(address, out, buf)
+    }
+}
+
+/// Already locked, so this just hands it over.
+impl<'t, T> I2CLock for Transaction<'t, T>
+where
+    T: 't + I2CTrait,
+{
+    fn locked<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&I2CTrait) -> Result<R>,
+    {
+        f(self)
+    }
+}
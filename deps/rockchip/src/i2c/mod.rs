@@ -4,12 +4,24 @@ use core::any::{Any};
 use core::ops::Deref;
 // use core::ptr;
 
+use clock::{ClockError, ClockHandle, ClockManager, ClockRegisters};
+use delay::{self, Timeout};
+
 #[cfg(target_arch = "aarch64")]
 use rk3399_tools::{I2C0, I2C1, I2C2, I2C3, I2C4, i2c0};
 
 #[cfg(not(target_arch = "aarch64"))]
 use rk3399_m0::{I2C0, I2C1, I2C2, I2C3, I2C4, i2c0};
 
+pub mod bus;
+pub use self::bus::{I2CBus, BusDevice};
+
+pub mod smbus;
+pub use self::smbus::SMBus;
+
+pub mod mux;
+pub use self::mux::{I2CMux, MuxChannel, MuxKind};
+
 #[derive(Debug)]
 pub enum I2CError {
     StartBitTimeout,
@@ -21,6 +33,19 @@ pub enum I2CError {
     /// Slave replied to packet with NAK instead of ACK.
     SlaveNak,
 
+    /// Transfer is longer than the controller can send in one go.
+    TransferTooLarge,
+
+    /// SMBus packet error code didn't match the data received.
+    PecMismatch,
+
+    /// SMBus block transfer with a byte count outside 1..32.
+    InvalidBlockLength,
+
+    /// Device behind a mux has the mux's own address, which answers on the
+    /// parent bus whichever channel is selected.
+    MuxAddressCollision,
+
     #[doc(hidden)]
     _Extensible,
 }
@@ -28,18 +53,42 @@ pub enum I2CError {
 pub type Result<T> = ::core::result::Result<T, nb::Error<I2CError>>;
 
 pub unsafe trait I2CDevice: Deref<Target = i2c0::RegisterBlock> {
-    
+    /// name of the controller's functional clock
+    const CLOCK: &'static str;
 }
 
-unsafe impl I2CDevice for I2C0 { }
-unsafe impl I2CDevice for I2C1 { }
-unsafe impl I2CDevice for I2C2 { }
-unsafe impl I2CDevice for I2C3 { }
-unsafe impl I2CDevice for I2C4 { }
+// I2C0 and I2C4 are the PMU controllers
+unsafe impl I2CDevice for I2C0 { const CLOCK: &'static str = "clk_i2c0_pmu"; }
+unsafe impl I2CDevice for I2C1 { const CLOCK: &'static str = "clk_i2c1"; }
+unsafe impl I2CDevice for I2C2 { const CLOCK: &'static str = "clk_i2c2"; }
+unsafe impl I2CDevice for I2C3 { const CLOCK: &'static str = "clk_i2c3"; }
+unsafe impl I2CDevice for I2C4 { const CLOCK: &'static str = "clk_i2c4_pmu"; }
 
 pub trait I2CTrait {
     fn read_from(&self, address: u8, register: Option<u8>, &mut [u8]) -> Result<usize>;
     fn write_to(&self, address: u8, register: Option<u8>, &[u8]) -> Result<usize>;
+
+    /// Write up to three bytes (eg. a command and its arguments), then read
+    /// into the slice after a repeated START, without releasing the bus.
+    fn write_read(&self, address: u8, &[u8], &mut [u8]) -> Result<usize>;
+}
+
+/// A controller whose SCL frequency can be changed between transactions.
+pub trait I2CSpeed {
+    /// Program the controller to clock SCL at no more than `scl_hz`, given
+    /// the rate of the controller's input clock. Returns the actual SCL rate;
+    /// asking for less than the controller can go, including 0, gets the
+    /// slowest it can.
+    fn set_scl_rate(&self, input_hz: u32, scl_hz: u32) -> u32;
+}
+
+/// A bus that can be held for several transfers in a row, so nothing else
+/// gets one in between; eg. switching a mux, then talking through it.
+pub trait I2CLock {
+    /// Run `f` with the bus to ourselves.
+    fn locked<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&I2CTrait) -> Result<R>;
 }
 
 // so, datasheet says max 32 bytes, and I2C code in uboot has the same constant
@@ -57,7 +106,11 @@ const I2C_MODE_TX: u8 = 0b00;
 const I2C_MODE_TRX: u8 = 0b01;
 const I2C_MODE_RX: u8  = 0b10;
 
-const TIMEOUT_LOOP: u32 = 10000;
+// largest DIVL + DIVH, which are 16 bits each
+const MAX_CLKDIV: u32 = 2 * 0xffff;
+
+// long enough for a full FIFO at 100kHz, with room for some stretching
+const TIMEOUT_US: u32 = 10_000;
 
 // TODO: investigate how long clock stretching is permitted to happen for.
 // If it is a set number of clock cycles, then we can perform an operation blocking
@@ -70,6 +123,14 @@ where
     U: Any + I2CDevice;
 
 impl<'a, U> I2C<'a, U> where U: Any + I2CDevice {
+    /// A handle on the controller's clock, to enable before using it.
+    pub fn clock<'m, 'c, R>(&self, clocks: &'m ClockManager<'c, R>) -> ::core::result::Result<ClockHandle<'m, 'c, R>, ClockError>
+    where
+        R: ClockRegisters,
+    {
+        clocks.request(U::CLOCK)
+    }
+
     fn clear_interrupts(&self) {
         let i2c = self.0;
 
@@ -100,12 +161,9 @@ impl<'a, U> I2C<'a, U> where U: Any + I2CDevice {
             stopien().set_bit());
 
         // wait for finish stop interrupt to fire
-        // TODO: handle timeout
-        let mut attempts = 0;
+        let timeout = Timeout::new(delay::system_timer(), TIMEOUT_US);
         while i2c.rki2c_ipd.read().stopipd().bit_is_clear() {
-            attempts += 1;
-
-            if attempts > TIMEOUT_LOOP {
+            if timeout.expired() {
                 self.disable();
                 return Err(nb::Error::Other(I2CError::StopBitTimeout));
             }
@@ -132,12 +190,9 @@ impl<'a, U> I2C<'a, U> where U: Any + I2CDevice {
             startien().set_bit());
 
         // wait for finish start interrupt to fire
-        // TODO: handle timeout
-        let mut attempts = 0;
+        let timeout = Timeout::new(delay::system_timer(), TIMEOUT_US);
         while i2c.rki2c_ipd.read().startipd().bit_is_clear() {
-            attempts += 1;
-
-            if attempts > TIMEOUT_LOOP {
+            if timeout.expired() {
                 self.disable();
                 return Err(nb::Error::Other(I2CError::StartBitTimeout));
             }
@@ -149,6 +204,32 @@ impl<'a, U> I2C<'a, U> where U: Any + I2CDevice {
         Ok(())
     }
 
+    // keep checking for error states or completion of a TX chunk
+    fn wait_for_tx(&self) -> Result<()> {
+        let i2c = self.0;
+
+        let timeout = Timeout::new(delay::system_timer(), TIMEOUT_US);
+        loop {
+            let pending_interrupts = i2c.rki2c_ipd.read();
+
+            // slave replied with NAK; terminate + return error
+            if pending_interrupts.nakrcvipd().bit_is_set() {
+                let _ = self.terminate();
+                return Err(nb::Error::Other(I2CError::SlaveNak));
+            }
+
+            // transmission complete
+            if pending_interrupts.mbtfipd().bit_is_set() {
+                return Ok(());
+            }
+
+            if timeout.expired() {
+                let _ = self.terminate();
+                return Err(nb::Error::Other(I2CError::Timeout));
+            }
+        }
+    }
+
     fn disable(&self) {
         let i2c = self.0;
         i2c.rki2c_con.write(|w| unsafe { w.bits(0) });
@@ -161,6 +242,47 @@ impl<'a, U> I2C<'a, U> where U: Any + I2CDevice {
     }
 }
 
+impl<'a, U> I2CSpeed for I2C<'a, U> where U: Any + I2CDevice {
+    // SCL = input / (8 * (DIVL + 1 + DIVH + 1))
+    //
+    // same calculation as uboot's rk_i2c_set_clk; when the divider is odd
+    // the extra cycle goes to the high period
+    fn set_scl_rate(&self, input_hz: u32, scl_hz: u32) -> u32 {
+        let i2c = self.0;
+
+        // there's no meeting 0, so it gets the slowest there is
+        let div = match scl_hz {
+            0 => MAX_CLKDIV,
+            _ => {
+                let div = (input_hz + scl_hz * 8 - 1) / (scl_hz * 8);
+                if div >= 2 { div - 2 } else { 0 }
+            },
+        };
+        let div = if div > MAX_CLKDIV { MAX_CLKDIV } else { div };
+
+        let divl = div / 2;
+        let divh = (div + 1) / 2;
+
+        i2c.rki2c_clkdiv.write(|w| unsafe { w.bits(divh << 16 | divl) });
+
+        input_hz / (8 * (divl + 1 + divh + 1))
+    }
+}
+
+/// A bare controller has no lock of its own; it's only as exclusive as
+/// whoever holds it keeps it.
+impl<'a, U> I2CLock for I2C<'a, U>
+where
+    U: Any + I2CDevice,
+{
+    fn locked<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&I2CTrait) -> Result<R>,
+    {
+        f(self)
+    }
+}
+
 impl<'a, U> Clone for I2C<'a, U>
 where
     U: Any + I2CDevice,
@@ -185,8 +307,14 @@ where
     /// Read bytes into a slice.
     ///
     /// `address` is a 7-bit I2C address.
-    ///
-    // For each chunk of data, we tell the I2C controller to enter
+    fn read_from(&self, address: u8, register: Option<u8>, recvdata: &mut [u8]) -> Result<usize> {
+        match register {
+            Some(register) => self.write_read(address, &[register], recvdata),
+            None => self.write_read(address, &[], recvdata),
+        }
+    }
+
+    // For the first chunk of data, we tell the I2C controller to enter
     // "TRX" mode (0b01).
     // 
     // It will send the slave's address (stored in the MRXADDR register)
@@ -206,13 +334,22 @@ where
     // will send an ACK, and increment the FIFO register it is storing
     // into.
     //
+    // The FIFO only holds I2C_FIFO_SIZE_BYTES, so longer reads are done
+    // in chunks; every chunk after the first is plain "RX" mode, which
+    // carries on clocking in data without another START or address.
+    //
     // After the expected number of bytes has been received (stored in
     // the MRXCNT register), the master should send a NACK to indicate to
     // the slave that it should stop sending and release SDA.
     //
     // After all data has been transferred, the master should then send
     // a STOP condition to release the bus.
-    fn read_from(&self, address: u8, register: Option<u8>, recvdata: &mut [u8]) -> Result<usize> {
+    fn write_read(&self, address: u8, raddr: &[u8], recvdata: &mut [u8]) -> Result<usize> {
+        // MRXRADDR only has room for 3 bytes
+        if raddr.len() > 3 {
+            return Err(nb::Error::Other(I2CError::TransferTooLarge));
+        }
+
         self.send_start_bit()?;
 
         let i2c = self.0;
@@ -228,11 +365,17 @@ where
         });
 
         // write the register address, if provided
-        if register != None {
-            i2c.rki2c_mrxraddr.write(|w| unsafe { w.
-                sraddr().bits(register.unwrap() as u32).
-                sraddlvld().set_bit() // low byte valid
-            });
+        //
+        // low byte goes out on the bus first, and each byte has its own
+        // valid bit starting at bit 24 (sraddlvld)
+        if !raddr.is_empty() {
+            let mut sraddr = 0;
+            for (idx, byte) in raddr.iter().enumerate() {
+                sraddr |= (*byte as u32) << (idx as u32 * BITS_PER_BYTE);
+                sraddr |= 1 << (24 + idx);
+            }
+
+            i2c.rki2c_mrxraddr.write(|w| unsafe { w.bits(sraddr) });
         } else {
             // no register addr, set to 0, and mark all u8s invalid
             i2c.rki2c_mrxraddr.reset();
@@ -243,25 +386,29 @@ where
         // controller can read up to I2C_FIFO_SIZE_BYTES bytes per transaction
         // so, group the buffer into that number of transactions with the I2C controller
         let mut transaction_it = recvdata.chunks_mut(I2C_FIFO_SIZE_BYTES as usize).peekable();
+        let mut first = true;
 
         while transaction_it.peek() != None {
             let transaction_bytes = transaction_it.next().unwrap();
 
-            // setup controller to enter TRX receive mode (see above)
+            // only the first chunk sends the address and register (TRX);
+            // later ones carry on reading where the last left off (RX)
+            let mode = if first { I2C_MODE_TRX } else { I2C_MODE_RX };
+            first = false;
+
             if transaction_it.peek() == None {
                 // last FIFO chunk, so get controller to send a NAK after
                 // receive is complete
-
                 i2c.rki2c_con.modify(|_, w| unsafe { w.
                     i2c_en().set_bit().
-                    i2c_mode().bits(I2C_MODE_TRX).
+                    i2c_mode().bits(mode).
                     ack().set_bit() 
                 });
             } else {
                 // not the last chunk yet
                 i2c.rki2c_con.modify(|_, w| unsafe { w.
                     i2c_en().set_bit().
-                    i2c_mode().bits(I2C_MODE_TRX)
+                    i2c_mode().bits(mode)
                 });
             }
 
@@ -270,11 +417,15 @@ where
                 mbrfien().set_bit().
                 nakrcvien().set_bit());
 
+            // clear "data finished" from the previous chunk
+            i2c.rki2c_ipd.write(|w| w.mbrfipd().set_bit());
+
             // write out expected receive size
             // controller will attempt reading after this write completes
             i2c.rki2c_mrxcnt.write(|w| unsafe { w.mrxcnt().bits(transaction_bytes.len() as u8) });
 
             // keep checking for error states or completion
+            let timeout = Timeout::new(delay::system_timer(), TIMEOUT_US);
             loop {
                 let pending_interrupts = i2c.rki2c_ipd.read();
 
@@ -289,7 +440,10 @@ where
                     break;
                 }
 
-                // TODO: handle timeout
+                if timeout.expired() {
+                    let _ = self.terminate();
+                    return Err(nb::Error::Other(I2CError::Timeout));
+                }
             }
 
             // copy data from FIFO registers into slice
@@ -317,10 +471,16 @@ where
         Ok(len)
     }
 
+    /// Write bytes from a slice.
+    ///
+    /// `address` is a 7-bit I2C address. Returns the number of bytes put
+    /// on the bus, including the address and register frames.
+    //
+    // The controller sends up to I2C_FIFO_SIZE_BYTES bytes from the TXDATA
+    // registers each time MTXCNT is written. The first chunk begins with the
+    // slave address (and register, if any); later chunks carry straight on
+    // from where the last left off, without another START.
     fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> Result<usize> {
-        // FIXME: remove this eventually
-        assert!(data.len() <= 32 - 2);
-
         self.send_start_bit()?;
 
         let i2c = self.0;
@@ -336,84 +496,50 @@ where
             mbtfien().set_bit().
             nakrcvien().set_bit());
 
+        let header = [address << 1 | RW_BIT_MASTER_WRITE, register.unwrap_or(0)];
         let address_bytes = match register {
             None => 1,
             Some(_) => 2
         };
 
-        let mut len:u8 = address_bytes;
-
-        let (first_frame, remaining_frames) = match data.len() > address_bytes as usize {
-            true => {
-                let (a, b) = data.split_at(address_bytes as usize);
-                (a, Some(b))
-            },
-            _    => (data, None)
-        };
-
-        // first tx register is special case; it contains the slave address
-        // and possibly the register address
-        let mut tx_0 = (address << 1 | RW_BIT_MASTER_WRITE) as u32;
-        if register != None {
-            tx_0 |= (register.unwrap() as u32) << 8;
-        }
-
-        // remaining 2 bytes for first tx register
-        for (idx, byte) in first_frame.iter().enumerate() {
-            tx_0 |= (*byte as u32) << (8 * (idx + 2));
-            len += 1;
-        }
-
-        i2c.rki2c_txdata[0].write(|w| unsafe { w.bits(tx_0) });
+        let mut bytes = header[..address_bytes].iter().chain(data.iter()).peekable();
+        let mut len = 0;
 
-        // do any remaining tx registers
-        if remaining_frames != None {
-            let txreg_idx = 1;
+        while bytes.peek() != None {
+            let mut chunk_len:u8 = 0;
+            let mut txreg_idx = 0;
 
-            for bytes_in_word in remaining_frames.unwrap().chunks(4) {
+            while bytes.peek() != None && txreg_idx < (I2C_FIFO_SIZE_BYTES / 4) as usize {
                 let mut bitstuffed:u32 = 0;
-                for (off, byte) in bytes_in_word.iter().enumerate() {
-                    bitstuffed = bitstuffed | ((*byte as u32) << (off as u32 * BITS_PER_BYTE));
-                    len += 1;
+                for off in 0..4 {
+                    match bytes.next() {
+                        Some(byte) => {
+                            bitstuffed = bitstuffed | ((*byte as u32) << (off * BITS_PER_BYTE));
+                            chunk_len += 1;
+                        },
+                        None => break
+                    }
                 }
 
                 i2c.rki2c_txdata[txreg_idx].write(|w| unsafe { w.bits(bitstuffed) });
+                txreg_idx += 1;
             }
-        }
 
-        // write out tx length; this initiates transfer
-        i2c.rki2c_mtxcnt.write(|w| unsafe { w.mtxcnt().bits(len) });    
-    
-        // keep checking for error states or completion
-        // TODO: move into separate function
-        let mut attempts = 0;
-        loop {
-            let pending_interrupts = i2c.rki2c_ipd.read();
+            // clear "data finished" from the previous chunk
+            i2c.rki2c_ipd.write(|w| w.mbtfipd().set_bit());
 
-            // slave replied with NAK; terminate + return error
-            if pending_interrupts.nakrcvipd().bit_is_set() {
-                let _ = self.terminate();
-                return Err(nb::Error::Other(I2CError::SlaveNak));
-            }
-
-            // transmission complete
-            if pending_interrupts.mbtfipd().bit_is_set() {
-                break;
-            }
+            // write out tx length; this initiates transfer
+            i2c.rki2c_mtxcnt.write(|w| unsafe { w.mtxcnt().bits(chunk_len) });
 
-            // TODO: handle timeout
-            attempts += 1;
+            self.wait_for_tx()?;
 
-            if attempts > TIMEOUT_LOOP {
-                let _ = self.terminate();
-                return Err(nb::Error::Other(I2CError::Timeout));  
-            }
+            len += chunk_len as usize;
         }
 
         // free up bus
         self.terminate()?;
 
-        // and return number of bytes read
-        Ok(len as usize)
+        // and return number of bytes written
+        Ok(len)
     }
 }
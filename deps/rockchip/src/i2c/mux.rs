@@ -0,0 +1,342 @@
+use nb;
+
+use core::cell::Cell;
+
+use super::{I2CError, I2CLock, I2CTrait, Result};
+
+/// Parts in the PCA954x family differ in how many downstream channels they
+/// have, and in how a channel is picked in the control register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MuxKind {
+    /// 2 channels, channel number plus enable bit
+    PCA9542,
+    /// 4 channels, channel number plus enable bit
+    PCA9544,
+    /// 4 channels, one bit per channel
+    PCA9546,
+    /// 8 channels, one bit per channel
+    PCA9548,
+}
+
+// enable bit for parts that encode the channel number
+const MUX_ENABLE: u8 = 1 << 2;
+
+impl MuxKind {
+    pub fn channels(&self) -> u8 {
+        match *self {
+            MuxKind::PCA9542 => 2,
+            MuxKind::PCA9544 => 4,
+            MuxKind::PCA9546 => 4,
+            MuxKind::PCA9548 => 8,
+        }
+    }
+
+    // control register value that connects only `channel`
+    fn select(&self, channel: u8) -> u8 {
+        match *self {
+            MuxKind::PCA9542 | MuxKind::PCA9544 => MUX_ENABLE | channel,
+            MuxKind::PCA9546 | MuxKind::PCA9548 => 1 << channel,
+        }
+    }
+}
+
+/// A PCA954x I2C multiplexer sitting on a parent bus.
+///
+/// Each downstream channel is handed out as a `MuxChannel`, which behaves
+/// like a bus of its own. The mux is switched over before every transfer
+/// on a channel, unless it's already pointing there, with the parent bus
+/// held across both so nothing else can switch it in between.
+///
+/// The selection is only cached here, so anything else that writes to the
+/// mux's control register behind our back must call `invalidate`.
+pub struct I2CMux<T>
+where
+    T: I2CTrait + I2CLock,
+{
+    parent: T,
+    address: u8,
+    kind: MuxKind,
+
+    /// channel the mux was last switched to, if we know
+    selected: Cell<Option<u8>>,
+}
+
+impl<T> I2CMux<T>
+where
+    T: I2CTrait + I2CLock,
+{
+    pub fn new(parent: T, address: u8, kind: MuxKind) -> I2CMux<T> {
+        I2CMux {
+            parent: parent,
+            address: address,
+            kind: kind,
+            selected: Cell::new(None),
+        }
+    }
+
+    /// One of the downstream buses, or `None` if this part doesn't have
+    /// that many channels.
+    pub fn channel(&self, channel: u8) -> Option<MuxChannel<T>> {
+        if channel >= self.kind.channels() {
+            return None;
+        }
+
+        Some(MuxChannel {
+            mux: self,
+            channel: channel,
+        })
+    }
+
+    /// Disconnect all downstream channels.
+    pub fn deselect(&self) -> Result<()> {
+        self.parent.locked(|bus| {
+            self.selected.set(None);
+            bus.write_to(self.address, None, &[0])?;
+            Ok(())
+        })
+    }
+
+    /// Forget which channel is selected, so the next transfer reselects it.
+    pub fn invalidate(&self) {
+        self.selected.set(None);
+    }
+
+    /// Give back the parent bus.
+    pub fn free(self) -> T {
+        self.parent
+    }
+
+    // switch over on `bus`, the parent bus, which the caller has locked
+    fn select(&self, bus: &I2CTrait, channel: u8) -> Result<()> {
+        if self.selected.get() == Some(channel) {
+            return Ok(());
+        }
+
+        // if this fails we've no idea what state the mux is in
+        self.selected.set(None);
+        bus.write_to(self.address, None, &[self.kind.select(channel)])?;
+        self.selected.set(Some(channel));
+
+        Ok(())
+    }
+}
+
+/// A downstream bus of an `I2CMux`.
+pub struct MuxChannel<'m, T>
+where
+    T: 'm + I2CTrait + I2CLock,
+{
+    mux: &'m I2CMux<T>,
+    channel: u8,
+}
+
+impl<'m, T> MuxChannel<'m, T>
+where
+    T: 'm + I2CTrait + I2CLock,
+{
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+}
+
+/// Holds the parent bus, with this channel selected.
+impl<'m, T> I2CLock for MuxChannel<'m, T>
+where
+    T: 'm + I2CTrait + I2CLock,
+{
+    fn locked<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&I2CTrait) -> Result<R>,
+    {
+        self.mux.parent.locked(|bus| {
+            self.mux.select(bus, self.channel)?;
+
+            f(&Selected {
+                bus: bus,
+                mux_address: self.mux.address,
+            })
+        })
+    }
+}
+
+impl<'m, T> Clone for MuxChannel<'m, T>
+where
+    T: 'm + I2CTrait + I2CLock,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'m, T> Copy for MuxChannel<'m, T>
+where
+    T: 'm + I2CTrait + I2CLock,
+{
+}
+
+impl<'m, T> I2CTrait for MuxChannel<'m, T>
+where
+    T: 'm + I2CTrait + I2CLock,
+{
+    fn read_from(&self, address: u8, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+        self.locked(|bus| bus.read_from(address, register, buf))
+    }
+
+    fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> Result<usize> {
+        self.locked(|bus| bus.write_to(address, register, data))
+    }
+
+    fn write_read(&self, address: u8, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+        self.locked(|bus| bus.write_read(address, out, buf))
+    }
+}
+
+// the parent bus, locked with a channel selected
+struct Selected<'b> {
+    bus: &'b I2CTrait,
+    mux_address: u8,
+}
+
+impl<'b> Selected<'b> {
+    // the mux itself answers on the parent bus at its own address whatever
+    // channel is selected, so a device behind it can't share that address
+    fn check_address(&self, address: u8) -> Result<()> {
+        if address == self.mux_address {
+            return Err(nb::Error::Other(I2CError::MuxAddressCollision));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'b> I2CTrait for Selected<'b> {
+    fn read_from(&self, address: u8, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+        self.check_address(address)?;
+        self.bus.read_from(address, register, buf)
+    }
+
+    fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> Result<usize> {
+        self.check_address(address)?;
+        self.bus.write_to(address, register, data)
+    }
+
+    fn write_read(&self, address: u8, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+        self.check_address(address)?;
+        self.bus.write_read(address, out, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    const MUX: u8 = 0x70;
+
+    // logs every transfer as (address, bytes sent, whether the bus was held)
+    struct FakeBus {
+        held: Cell<bool>,
+        log: RefCell<Vec<(u8, Vec<u8>, bool)>>,
+    }
+
+    impl FakeBus {
+        fn new() -> FakeBus {
+            FakeBus {
+                held: Cell::new(false),
+                log: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn push(&self, address: u8, sent: Vec<u8>) {
+            self.log.borrow_mut().push((address, sent, self.held.get()));
+        }
+    }
+
+    impl<'a> I2CTrait for &'a FakeBus {
+        fn read_from(&self, address: u8, register: Option<u8>, buf: &mut [u8]) -> Result<usize> {
+            self.push(address, register.into_iter().collect());
+            Ok(buf.len())
+        }
+
+        fn write_to(&self, address: u8, register: Option<u8>, data: &[u8]) -> Result<usize> {
+            let mut sent: Vec<u8> = register.into_iter().collect();
+            sent.extend_from_slice(data);
+            self.push(address, sent);
+            Ok(data.len())
+        }
+
+        fn write_read(&self, address: u8, out: &[u8], buf: &mut [u8]) -> Result<usize> {
+            self.push(address, out.to_vec());
+            Ok(buf.len())
+        }
+    }
+
+    impl<'a> I2CLock for &'a FakeBus {
+        fn locked<F, R>(&self, f: F) -> Result<R>
+        where
+            F: FnOnce(&I2CTrait) -> Result<R>,
+        {
+            assert!(!self.held.get(), "bus locked twice");
+
+            self.held.set(true);
+            let r = f(self);
+            self.held.set(false);
+
+            r
+        }
+    }
+
+    #[test]
+    fn select_under_lock() {
+        let bus = FakeBus::new();
+        let mux = I2CMux::new(&bus, MUX, MuxKind::PCA9548);
+        let channel = mux.channel(3).unwrap();
+
+        channel.write_to(0x50, Some(1), &[2]).unwrap();
+        channel.read_from(0x50, Some(1), &mut [0]).unwrap();
+
+        // switched once, and held across the switch and each transfer
+        assert_eq!(*bus.log.borrow(), vec![
+            (MUX, vec![1 << 3], true),
+            (0x50, vec![1, 2], true),
+            (0x50, vec![1], true),
+        ]);
+        assert!(!bus.held.get());
+    }
+
+    #[test]
+    fn switching() {
+        let bus = FakeBus::new();
+        let mux = I2CMux::new(&bus, MUX, MuxKind::PCA9544);
+
+        mux.channel(0).unwrap().write_to(0x50, None, &[]).unwrap();
+        mux.channel(1).unwrap().write_to(0x50, None, &[]).unwrap();
+        mux.deselect().unwrap();
+        mux.channel(1).unwrap().write_to(0x50, None, &[]).unwrap();
+
+        let selects: Vec<Vec<u8>> = bus.log.borrow().iter()
+            .filter(|&&(address, _, _)| address == MUX)
+            .map(|&(_, ref sent, _)| sent.clone())
+            .collect();
+
+        assert_eq!(selects, vec![vec![4], vec![5], vec![0], vec![5]]);
+        assert!(mux.channel(4).is_none());
+    }
+
+    #[test]
+    fn address_collision() {
+        let bus = FakeBus::new();
+        let mux = I2CMux::new(&bus, MUX, MuxKind::PCA9548);
+        let channel = mux.channel(0).unwrap();
+
+        match channel.read_from(MUX, None, &mut [0]) {
+            Err(nb::Error::Other(I2CError::MuxAddressCollision)) => (),
+            r => panic!("{:?}", r),
+        }
+
+        // nothing reached the device side
+        assert!(bus.log.borrow().iter().all(|&(address, _, _)| address == MUX));
+    }
+}
@@ -0,0 +1,238 @@
+use nb;
+
+use super::{I2CError, I2CTrait, Result};
+
+/// Largest payload of an SMBus block transfer.
+pub const SMBUS_BLOCK_MAX: usize = 32;
+
+// 8th bit in the address frame
+const RW_BIT_READ: u8 = 1;
+const RW_BIT_WRITE: u8 = 0;
+
+/// SMBus packet error code: CRC-8 with polynomial x^8 + x^2 + x + 1,
+/// no reflection, initial value 0.
+pub fn pec(crc: u8, data: &[u8]) -> u8 {
+    let mut crc = crc;
+
+    for byte in data {
+        crc ^= *byte;
+
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// An SMBus device on top of a raw I2C bus.
+///
+/// SMBus words are little endian. When PEC is turned on, every write has a
+/// packet error code appended and every read expects one at the end, which
+/// is checked against the bytes seen on the bus (including address frames).
+pub struct SMBus<T>
+where
+    T: I2CTrait,
+{
+    bus: T,
+    address: u8,
+    pec: bool,
+}
+
+impl<T> SMBus<T>
+where
+    T: I2CTrait,
+{
+    pub fn new(bus: T, address: u8) -> SMBus<T> {
+        SMBus {
+            bus: bus,
+            address: address,
+            pec: false,
+        }
+    }
+
+    pub fn set_pec(&mut self, enabled: bool) {
+        self.pec = enabled;
+    }
+
+    pub fn pec_enabled(&self) -> bool {
+        self.pec
+    }
+
+    /// Give back the underlying bus.
+    pub fn free(self) -> T {
+        self.bus
+    }
+
+    /// Quick command: just the address frame, with the R/W bit clear.
+    ///
+    /// The controller always wants at least one byte for a read, so the
+    /// read flavour isn't available.
+    pub fn quick(&self) -> Result<()> {
+        self.bus.write_to(self.address, None, &[]).map(|_| ())
+    }
+
+    /// Send byte: a single data byte with no command code.
+    pub fn write_byte(&self, value: u8) -> Result<()> {
+        self.write(None, &[value])
+    }
+
+    /// Receive byte: a single data byte with no command code.
+    pub fn read_byte(&self) -> Result<u8> {
+        let mut buf = [0u8; 2];
+        let len = self.read_len(1);
+
+        self.bus.read_from(self.address, None, &mut buf[..len])?;
+        self.check_pec(&[], &buf[..len])?;
+
+        Ok(buf[0])
+    }
+
+    pub fn write_byte_data(&self, command: u8, value: u8) -> Result<()> {
+        self.write(Some(command), &[value])
+    }
+
+    pub fn read_byte_data(&self, command: u8) -> Result<u8> {
+        let mut buf = [0u8; 2];
+        self.read(command, &mut buf, 1)?;
+
+        Ok(buf[0])
+    }
+
+    pub fn write_word_data(&self, command: u8, value: u16) -> Result<()> {
+        self.write(Some(command), &[value as u8, (value >> 8) as u8])
+    }
+
+    pub fn read_word_data(&self, command: u8) -> Result<u16> {
+        let mut buf = [0u8; 3];
+        self.read(command, &mut buf, 2)?;
+
+        Ok(buf[0] as u16 | (buf[1] as u16) << 8)
+    }
+
+    /// Process call: write a word to `command`, and read a word back
+    /// without releasing the bus.
+    pub fn process_call(&self, command: u8, value: u16) -> Result<u16> {
+        let out = [command, value as u8, (value >> 8) as u8];
+
+        let mut buf = [0u8; 3];
+        let len = self.read_len(2);
+
+        self.bus.write_read(self.address, &out, &mut buf[..len])?;
+        self.check_pec(&out, &buf[..len])?;
+
+        Ok(buf[0] as u16 | (buf[1] as u16) << 8)
+    }
+
+    /// Block write: the byte count, followed by up to 32 bytes of `data`.
+    pub fn write_block_data(&self, command: u8, data: &[u8]) -> Result<()> {
+        if data.is_empty() || data.len() > SMBUS_BLOCK_MAX {
+            return Err(nb::Error::Other(I2CError::InvalidBlockLength));
+        }
+
+        let mut buf = [0u8; SMBUS_BLOCK_MAX + 1];
+        buf[0] = data.len() as u8;
+        buf[1..data.len() + 1].copy_from_slice(data);
+
+        self.write(Some(command), &buf[..data.len() + 1])
+    }
+
+    /// Block read into `data`, returning the number of bytes the device sent.
+    ///
+    /// The controller needs to know how much to read before it starts, but
+    /// the count comes from the device. So we always clock in the largest
+    /// possible block and pick out what's valid afterwards; devices just
+    /// send 0xff past the end of the block.
+    pub fn read_block_data(&self, command: u8, data: &mut [u8]) -> Result<usize> {
+        let mut buf = [0u8; SMBUS_BLOCK_MAX + 2];
+        let len = self.read_len(SMBUS_BLOCK_MAX + 1);
+
+        self.bus.read_from(self.address, Some(command), &mut buf[..len])?;
+
+        let count = buf[0] as usize;
+        if count == 0 || count > SMBUS_BLOCK_MAX {
+            return Err(nb::Error::Other(I2CError::InvalidBlockLength));
+        }
+
+        // PEC immediately follows the last byte of the block, not the end
+        // of what we clocked in
+        self.check_pec(&[command], &buf[..count + 2])?;
+
+        let copied = if count < data.len() { count } else { data.len() };
+        data[..copied].copy_from_slice(&buf[1..copied + 1]);
+
+        Ok(count)
+    }
+
+    // number of bytes to clock in for `len` bytes of payload
+    fn read_len(&self, len: usize) -> usize {
+        if self.pec { len + 1 } else { len }
+    }
+
+    fn write(&self, command: Option<u8>, data: &[u8]) -> Result<()> {
+        if !self.pec {
+            return self.bus.write_to(self.address, command, data).map(|_| ());
+        }
+
+        let mut buf = [0u8; SMBUS_BLOCK_MAX + 2];
+        buf[..data.len()].copy_from_slice(data);
+
+        let mut crc = pec(0, &[self.address << 1 | RW_BIT_WRITE]);
+        if let Some(command) = command {
+            crc = pec(crc, &[command]);
+        }
+        buf[data.len()] = pec(crc, data);
+
+        self.bus.write_to(self.address, command, &buf[..data.len() + 1]).map(|_| ())
+    }
+
+    fn read(&self, command: u8, buf: &mut [u8], len: usize) -> Result<()> {
+        let len = self.read_len(len);
+
+        self.bus.read_from(self.address, Some(command), &mut buf[..len])?;
+        self.check_pec(&[command], &buf[..len])
+    }
+
+    // `written` is whatever went out between the write address frame and
+    // the repeated START; `received` ends in the device's PEC byte
+    fn check_pec(&self, written: &[u8], received: &[u8]) -> Result<()> {
+        if !self.pec {
+            return Ok(());
+        }
+
+        let (data, expected) = received.split_at(received.len() - 1);
+
+        let mut crc = 0;
+        if !written.is_empty() {
+            crc = pec(crc, &[self.address << 1 | RW_BIT_WRITE]);
+            crc = pec(crc, written);
+        }
+        crc = pec(crc, &[self.address << 1 | RW_BIT_READ]);
+        crc = pec(crc, data);
+
+        if crc != expected[0] {
+            return Err(nb::Error::Other(I2CError::PecMismatch));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pec_known_answer() {
+        // CRC-8 (poly 0x07, init 0): the catalogue check value
+        assert_eq!(pec(0, b"123456789"), 0xf4);
+
+        // carrying on from a partial CRC is the same as doing it in one go
+        assert_eq!(pec(pec(0, b"1234"), b"56789"), 0xf4);
+        assert_eq!(pec(0, &[]), 0);
+    }
+}
@@ -1,3 +1,4 @@
+#![feature(asm)]
 #![feature(const_fn)]
 #![feature(get_type_id)]
 #![feature(never_type)]
@@ -6,6 +7,11 @@
 
 extern crate embedded_hal as hal;
 extern crate nb;
+extern crate spin;
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
 
 #[cfg(target_arch = "aarch64")]
 pub extern crate rk3399_tools;
@@ -13,6 +19,27 @@ pub extern crate rk3399_tools;
 #[cfg(not(target_arch = "aarch64"))]
 pub extern crate rk3399_m0;
 
+#[cfg(test)]
+mod fake;
+
+pub mod cache;
+pub mod delay;
 pub mod serial;
 pub mod clock;
-pub mod i2c;
\ No newline at end of file
+pub mod i2c;
+pub mod regulator;
+pub mod rk808;
+pub mod rtc;
+pub mod fan53555;
+pub mod at24;
+pub mod dvfs;
+pub mod mailbox;
+pub mod pinctrl;
+pub mod ring;
+#[macro_use]
+pub mod m0log;
+pub mod m0status;
+
+// only reachable from the AP, but the tests run anywhere
+#[cfg(any(target_arch = "aarch64", test))]
+pub mod sgrf;
\ No newline at end of file
@@ -0,0 +1,159 @@
+// Log channel from the M0 to the AP.
+//
+// The M0 has no console, so it formats lines into a ring in shared memory
+// and the AP prints them. Layout, starting at a word-aligned `base`:
+//
+//   0x00  dropped   lines the M0 couldn't fit; only the M0 writes this
+//   0x04  reserved
+//   0x40  ring      see `ring`, one record per line
+//
+// Lines that don't fit in the ring are counted rather than waited on, so
+// logging never blocks the M0; the AP reports the count as it drains.
+//
+// The ring does its own cache maintenance; the count gets the same, from
+// here.
+
+use core::fmt;
+use core::ptr;
+use core::str;
+
+use cache::{self, SHARED_LINE};
+use ring::{self, Consumer, Producer, RingError};
+
+pub const LOG_HEADER_SIZE: usize = SHARED_LINE;
+
+/// Longest line; anything past this is cut off.
+pub const MAX_LINE: usize = 128;
+
+const DROPPED: isize = 0x00;
+
+/// Set up an empty log in the `len` bytes at `base`, for the M0 to write
+/// to and the AP to read from.
+pub unsafe fn init(base: *mut u8, len: usize) -> Result<(), RingError> {
+    if len <= LOG_HEADER_SIZE {
+        return Err(RingError::TooSmall);
+    }
+
+    ptr::write_volatile(base.offset(DROPPED) as *mut u32, 0);
+    cache::clean_invalidate(base as usize, LOG_HEADER_SIZE);
+
+    ring::init(base.offset(LOG_HEADER_SIZE as isize), len - LOG_HEADER_SIZE)
+}
+
+/// The M0's end: formats a line at a time.
+pub struct LogWriter {
+    producer: Producer,
+    dropped: *mut u32,
+    line: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl LogWriter {
+    pub unsafe fn attach(base: *mut u8) -> Result<LogWriter, RingError> {
+        Ok(LogWriter {
+            producer: Producer::attach(base.offset(LOG_HEADER_SIZE as isize))?,
+            dropped: base.offset(DROPPED) as *mut u32,
+            line: [0; MAX_LINE],
+            len: 0,
+        })
+    }
+
+    /// Log one line; use through `m0_log!`.
+    pub fn log(&mut self, args: fmt::Arguments) {
+        self.len = 0;
+
+        // only fails if we do, and we just truncate instead
+        let _ = fmt::write(self, args);
+
+        let len = self.len;
+        if self.producer.write_record(&self.line[..len]).is_err() {
+            unsafe {
+                let dropped = ptr::read_volatile(self.dropped);
+                ptr::write_volatile(self.dropped, dropped.wrapping_add(1));
+            }
+
+            cache::clean(self.dropped as usize, 4);
+        }
+    }
+}
+
+impl fmt::Write for LogWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_LINE - self.len;
+        let n = if s.len() < room { s.len() } else { room };
+
+        self.line[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+
+        Ok(())
+    }
+}
+
+/// Log a formatted line from the M0.
+#[macro_export]
+macro_rules! m0_log {
+    ($log:expr, $($arg:tt)*) => ($log.log(format_args!($($arg)*)));
+}
+
+/// The AP's end.
+pub struct LogReader {
+    consumer: Consumer,
+    dropped: *const u32,
+    reported: u32,
+}
+
+impl LogReader {
+    pub unsafe fn attach(base: *mut u8) -> Result<LogReader, RingError> {
+        let dropped = base.offset(DROPPED) as *const u32;
+        cache::clean_invalidate(dropped as usize, 4);
+
+        Ok(LogReader {
+            consumer: Consumer::attach(base.offset(LOG_HEADER_SIZE as isize))?,
+            dropped: dropped,
+            reported: ptr::read_volatile(dropped),
+        })
+    }
+
+    /// Hand every waiting line to `f`, returning how many there were.
+    ///
+    /// A line cut off mid-character (or otherwise not UTF-8) is passed on
+    /// up to the bad byte.
+    pub fn drain<F>(&mut self, mut f: F) -> Result<usize, RingError>
+    where
+        F: FnMut(&str),
+    {
+        let mut buf = [0u8; MAX_LINE];
+        let mut lines = 0;
+
+        loop {
+            let len = match self.consumer.read_record(&mut buf) {
+                Ok(Some(len)) => len,
+                Ok(None) => return Ok(lines),
+                Err(RingError::BufferTooSmall(_)) => {
+                    // not from a LogWriter; nothing useful we can do with it
+                    self.consumer.skip_record()?;
+                    continue;
+                },
+                Err(e) => return Err(e),
+            };
+
+            let line = match str::from_utf8(&buf[..len]) {
+                Ok(line) => line,
+                Err(e) => unsafe { str::from_utf8_unchecked(&buf[..e.valid_up_to()]) },
+            };
+
+            f(line);
+            lines += 1;
+        }
+    }
+
+    /// Lines the M0 has had to drop since the last call.
+    pub fn dropped(&mut self) -> u32 {
+        cache::clean_invalidate(self.dropped as usize, 4);
+        let dropped = unsafe { ptr::read_volatile(self.dropped) };
+        let new = dropped.wrapping_sub(self.reported);
+
+        self.reported = dropped;
+        new
+    }
+}
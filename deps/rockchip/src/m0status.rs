@@ -0,0 +1,156 @@
+// Status block the M0 shares with the AP, for supervision.
+//
+// Layout, all words, starting at a word-aligned `base`:
+//
+//   0x00  magic        STATUS_MAGIC once the M0 is up
+//   0x04  heartbeat    bumped by the M0 from its main loop
+//   0x08  crash        CRASH_MAGIC once the record below is filled in
+//   0x0c  r0
+//   0x10  r1
+//   0x14  r2
+//   0x18  r3
+//   0x1c  r12
+//   0x20  lr
+//   0x24  pc
+//   0x28  xpsr         r0-xpsr are the frame the core stacked on the fault
+//   0x2c  sp           stack pointer the frame was found at
+//
+// ARMv6-M has no fault status registers, so the stacked frame (and the
+// exception number in xPSR) is all there is to go on.
+//
+// The AP clears the block before starting the M0; after that, only the M0
+// writes to it. The AP doesn't snoop the M0's writes, so every read on that
+// side invalidates first, and the clear is written straight back.
+
+use core::fmt;
+use core::ptr;
+
+use cache;
+
+pub const STATUS_MAGIC: u32 = 0x4248304d; // "M0HB"
+pub const CRASH_MAGIC: u32 = 0x44414544; // "DEAD"
+pub const STATUS_SIZE: usize = 0x30;
+
+const MAGIC: isize = 0;
+const HEARTBEAT: isize = 1;
+const CRASH: isize = 2;
+const FRAME: isize = 3;
+const FRAME_WORDS: usize = 9;
+
+/// Registers at the time the M0 faulted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrashRecord {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+    pub sp: u32,
+}
+
+impl CrashRecord {
+    /// Exception that was being taken (3 for HardFault).
+    pub fn exception(&self) -> u32 {
+        self.xpsr & 0x3f
+    }
+
+    fn words(&self) -> [u32; FRAME_WORDS] {
+        [self.r0, self.r1, self.r2, self.r3, self.r12, self.lr, self.pc, self.xpsr, self.sp]
+    }
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "exception {} at pc 0x{:08x}, lr 0x{:08x}, sp 0x{:08x}, xpsr 0x{:08x}",
+            self.exception(), self.pc, self.lr, self.sp, self.xpsr)?;
+        write!(f, "r0 0x{:08x} r1 0x{:08x} r2 0x{:08x} r3 0x{:08x} r12 0x{:08x}",
+            self.r0, self.r1, self.r2, self.r3, self.r12)
+    }
+}
+
+/// Either side's view of the status block.
+pub struct Status {
+    base: *mut u32,
+}
+
+impl Status {
+    pub unsafe fn at(base: *mut u8) -> Status {
+        Status {
+            base: base as *mut u32,
+        }
+    }
+
+    fn load(&self, word: isize) -> u32 {
+        let addr = unsafe { self.base.offset(word) };
+        cache::clean_invalidate(addr as usize, 4);
+
+        unsafe { ptr::read_volatile(addr) }
+    }
+
+    fn store(&self, word: isize, value: u32) {
+        unsafe { ptr::write_volatile(self.base.offset(word), value) }
+    }
+
+    /// Wipe the block; the AP does this before (re)starting the M0.
+    pub fn clear(&self) {
+        for word in 0..(STATUS_SIZE / 4) as isize {
+            self.store(word, 0);
+        }
+
+        cache::clean_invalidate(self.base as usize, STATUS_SIZE);
+    }
+
+    /// Whether the M0 has got as far as `start`.
+    pub fn is_up(&self) -> bool {
+        self.load(MAGIC) == STATUS_MAGIC
+    }
+
+    pub fn heartbeat(&self) -> u32 {
+        self.load(HEARTBEAT)
+    }
+
+    /// The M0's crash record, if it managed to write one.
+    pub fn crash(&self) -> Option<CrashRecord> {
+        if self.load(CRASH) != CRASH_MAGIC {
+            return None;
+        }
+
+        let w = |n: isize| self.load(FRAME + n);
+
+        Some(CrashRecord {
+            r0: w(0),
+            r1: w(1),
+            r2: w(2),
+            r3: w(3),
+            r12: w(4),
+            lr: w(5),
+            pc: w(6),
+            xpsr: w(7),
+            sp: w(8),
+        })
+    }
+
+    /// M0 side: announce we're up.
+    pub fn start(&self) {
+        self.store(HEARTBEAT, 0);
+        self.store(MAGIC, STATUS_MAGIC);
+    }
+
+    /// M0 side: we're still alive.
+    pub fn beat(&self) {
+        let beat = self.load(HEARTBEAT);
+        self.store(HEARTBEAT, beat.wrapping_add(1));
+    }
+
+    /// M0 side: record a fault, from the fault handler.
+    pub fn record_crash(&self, record: &CrashRecord) {
+        for (n, word) in record.words().iter().enumerate() {
+            self.store(FRAME + n as isize, *word);
+        }
+
+        self.store(CRASH, CRASH_MAGIC);
+    }
+}
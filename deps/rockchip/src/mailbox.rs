@@ -0,0 +1,161 @@
+// RK3399 mailbox.
+//
+// Four channels in each direction between the application processors ("A")
+// and the M0 ("B"). A message is a command word and a data word; writing the
+// data word raises the channel's status bit (and interrupt, if enabled) on
+// the other side, which reads both words then writes 1 to clear the status
+// bit. The status bit staying set is how the sender knows the last message
+// hasn't been picked up yet.
+//
+//   0x00        A2B_INTEN    [3:0] per channel
+//   0x04        A2B_STATUS   [3:0] per channel, write 1 to clear
+//   0x08 + n*8  A2B_CMD n
+//   0x0c + n*8  A2B_DAT n
+//   0x28        B2A_INTEN
+//   0x2c        B2A_STATUS
+//   0x30 + n*8  B2A_CMD n
+//   0x34 + n*8  B2A_DAT n
+//
+// Which half is "ours" depends on which side we're built for.
+
+use nb;
+
+use core::ptr;
+
+pub const CHANNELS: usize = 4;
+
+#[cfg(target_arch = "aarch64")]
+const MAILBOX_BASE: usize = 0xff6b0000;
+
+// the M0 sees the peripheral space at a different address
+#[cfg(not(target_arch = "aarch64"))]
+const MAILBOX_BASE: usize = 0x476b0000;
+
+const A2B_BASE: usize = 0x00;
+const B2A_BASE: usize = 0x28;
+
+// offsets from A2B_BASE / B2A_BASE
+const INTEN: usize = 0x00;
+const STATUS: usize = 0x04;
+const CMD: usize = 0x08;
+const DAT: usize = 0x0c;
+const CHANNEL_STRIDE: usize = 0x08;
+
+#[cfg(target_arch = "aarch64")]
+const TX_BASE: usize = A2B_BASE;
+#[cfg(target_arch = "aarch64")]
+const RX_BASE: usize = B2A_BASE;
+
+#[cfg(not(target_arch = "aarch64"))]
+const TX_BASE: usize = B2A_BASE;
+#[cfg(not(target_arch = "aarch64"))]
+const RX_BASE: usize = A2B_BASE;
+
+pub type Result<T> = ::core::result::Result<T, nb::Error<Error>>;
+
+/// An error
+#[derive(Debug)]
+pub enum Error {
+    /// No such channel
+    InvalidChannel,
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// One mailbox message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Message {
+    pub cmd: u32,
+    pub data: u32,
+}
+
+/// Our end of the mailbox.
+pub struct Mailbox {
+    base: usize,
+}
+
+impl Mailbox {
+    pub const fn new() -> Mailbox {
+        Mailbox {
+            base: MAILBOX_BASE,
+        }
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn check(channel: usize) -> Result<()> {
+        if channel >= CHANNELS {
+            return Err(nb::Error::Other(Error::InvalidChannel));
+        }
+
+        Ok(())
+    }
+
+    /// Send a message to the other side, unless it still hasn't picked up
+    /// the last one on this channel.
+    pub fn send(&self, channel: usize, msg: Message) -> Result<()> {
+        Mailbox::check(channel)?;
+
+        if !self.tx_done(channel) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let offset = TX_BASE + channel * CHANNEL_STRIDE;
+
+        unsafe {
+            ptr::write_volatile(self.reg(offset + CMD), msg.cmd);
+            // the data write is what signals the other side
+            ptr::write_volatile(self.reg(offset + DAT), msg.data);
+        }
+
+        Ok(())
+    }
+
+    /// Take a message from the other side, if there is one.
+    pub fn receive(&self, channel: usize) -> Result<Message> {
+        Mailbox::check(channel)?;
+
+        if !self.rx_pending(channel) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let offset = RX_BASE + channel * CHANNEL_STRIDE;
+
+        unsafe {
+            let msg = Message {
+                cmd: ptr::read_volatile(self.reg(offset + CMD)),
+                data: ptr::read_volatile(self.reg(offset + DAT)),
+            };
+
+            ptr::write_volatile(self.reg(RX_BASE + STATUS), 1 << channel);
+            Ok(msg)
+        }
+    }
+
+    /// Whether the other side has picked up the last message we sent on
+    /// `channel`.
+    pub fn tx_done(&self, channel: usize) -> bool {
+        unsafe { ptr::read_volatile(self.reg(TX_BASE + STATUS)) & (1 << channel) == 0 }
+    }
+
+    /// Whether there's a message waiting for us on `channel`.
+    pub fn rx_pending(&self, channel: usize) -> bool {
+        unsafe { ptr::read_volatile(self.reg(RX_BASE + STATUS)) & (1 << channel) != 0 }
+    }
+
+    /// Raise our mailbox interrupt when a message arrives on `channel`.
+    pub fn set_interrupt(&self, channel: usize, enabled: bool) -> Result<()> {
+        Mailbox::check(channel)?;
+
+        unsafe {
+            let inten = ptr::read_volatile(self.reg(RX_BASE + INTEN));
+            let inten = if enabled { inten | 1 << channel } else { inten & !(1 << channel) };
+            ptr::write_volatile(self.reg(RX_BASE + INTEN), inten);
+        }
+
+        Ok(())
+    }
+}
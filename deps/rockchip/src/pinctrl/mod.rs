@@ -0,0 +1,594 @@
+// Pin multiplexing and pad configuration.
+//
+// Drivers claim the pins they need, by group, before using them; the claim
+// sets up the iomux and remembers who has each pin, so two drivers wanting
+// the same pad is an error rather than a mystery. Pull and drive strength
+// can then be set by whoever owns the pin.
+
+use core::fmt;
+use core::ptr;
+
+use spin::Mutex;
+
+#[cfg(target_arch = "aarch64")]
+use rk3399_tools::{GRF, PMUGRF};
+
+#[cfg(not(target_arch = "aarch64"))]
+use rk3399_m0::{GRF, PMUGRF};
+
+pub mod table;
+pub use self::table::{check, DriveType, Group, PinFunction, PortRegs, PullType, TableIssue,
+    BANKS, BANK_COUNT, GROUPS, PIN_COUNT, PINS_PER_BANK};
+
+/// An error
+#[derive(Debug, PartialEq)]
+pub enum PinError {
+    /// No group by that name
+    NoSuchGroup,
+
+    /// Pin doesn't exist
+    NoSuchPin(Pin),
+
+    /// Pin is already claimed by someone else
+    Conflict { pin: Pin, owner: &'static str },
+
+    /// Pin isn't claimed by whoever is trying to configure it
+    NotOwner(Pin),
+
+    /// Pin isn't routed to the iomux, or the function is out of range
+    BadMux(Pin),
+
+    /// Pad has no drive strength control
+    NoDrive(Pin),
+
+    /// Pad can't drive this many mA
+    BadStrength(u8),
+
+    /// Pad doesn't offer this pull
+    BadPull(Pin),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+pub type Result<T> = ::core::result::Result<T, PinError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Block {
+    Grf,
+    PmuGrf,
+}
+
+/// A pin control register, as an offset into its block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reg {
+    pub block: Block,
+    pub offset: u16,
+}
+
+impl Reg {
+    /// The register `n` words after this one.
+    pub fn next(&self, n: u16) -> Reg {
+        Reg { block: self.block, offset: self.offset + n * 4 }
+    }
+}
+
+/// Access to the pin control registers, by block and offset. Writes go
+/// straight through, write mask and all; it's up to the caller to set it.
+pub trait PinRegisters {
+    fn read(&self, reg: Reg) -> u32;
+    fn write(&self, reg: Reg, value: u32);
+}
+
+/// The real GRF and PMUGRF.
+pub struct Mmio;
+
+impl Mmio {
+    fn address(&self, reg: Reg) -> *mut u32 {
+        let base = match reg.block {
+            Block::Grf => GRF.get() as *mut u8,
+            Block::PmuGrf => PMUGRF.get() as *mut u8,
+        };
+
+        unsafe { base.offset(reg.offset as isize) as *mut u32 }
+    }
+}
+
+impl PinRegisters for Mmio {
+    fn read(&self, reg: Reg) -> u32 {
+        unsafe { ptr::read_volatile(self.address(reg)) }
+    }
+
+    fn write(&self, reg: Reg, value: u32) {
+        unsafe { ptr::write_volatile(self.address(reg), value) }
+    }
+}
+
+/// A to D, within a bank.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+}
+
+/// One pin, eg. GPIO1_B1 is `Pin::new(1, Port::B, 1)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub bank: u8,
+
+    /// 0 to 31 within the bank
+    pub index: u8,
+}
+
+impl Pin {
+    pub const fn new(bank: u8, port: Port, pin: u8) -> Pin {
+        Pin { bank: bank, index: port as u8 * 8 + pin }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (self.bank as usize) < BANK_COUNT && (self.index as usize) < PINS_PER_BANK
+    }
+
+    /// Index across all banks.
+    pub fn id(&self) -> usize {
+        self.bank as usize * PINS_PER_BANK + self.index as usize
+    }
+
+    /// `None` if the pin doesn't exist.
+    pub fn regs(&self) -> Option<&'static PortRegs> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        Some(&BANKS[self.bank as usize][self.index as usize / 8])
+    }
+
+    // whether the iomux can select `mux` for this pin
+    fn can_mux(&self, mux: u8) -> bool {
+        self.regs().map_or(false, |regs| regs.iomux.is_some()) && mux <= 3
+    }
+
+    // pin within its port
+    fn offset(&self) -> u8 {
+        self.index % 8
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let port = (b'a' + self.index / 8) as char;
+        write!(f, "gpio{}{}{}", self.bank, port, self.index % 8)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+    BusHold,
+}
+
+impl Pull {
+    // `None` if the pad can't do it; 1.8V-only pads have no bus hold
+    fn encode(&self, pull_type: PullType) -> Option<u32> {
+        match (*self, pull_type) {
+            (Pull::None, _) => Some(0),
+            (Pull::Up, PullType::Default) | (Pull::Down, PullType::Io1v8Only) => Some(1),
+            (Pull::Down, PullType::Default) => Some(2),
+            (Pull::BusHold, PullType::Default) | (Pull::Up, PullType::Io1v8Only) => Some(3),
+            (Pull::BusHold, PullType::Io1v8Only) => None,
+        }
+    }
+
+    fn decode(value: u32, pull_type: PullType) -> Pull {
+        match (value, pull_type) {
+            (0, _) | (2, PullType::Io1v8Only) => Pull::None,
+            (1, PullType::Default) | (3, PullType::Io1v8Only) => Pull::Up,
+            (1, PullType::Io1v8Only) | (2, PullType::Default) => Pull::Down,
+            _ => Pull::BusHold,
+        }
+    }
+}
+
+// a pin's registers, or why it hasn't got any
+fn regs(pin: Pin) -> Result<&'static PortRegs> {
+    pin.regs().ok_or(PinError::NoSuchPin(pin))
+}
+
+/// Pin ownership and configuration.
+pub struct Pinctrl<R>
+where
+    R: PinRegisters,
+{
+    regs: R,
+    owners: Mutex<[Option<&'static str>; PIN_COUNT]>,
+}
+
+impl<R> Pinctrl<R>
+where
+    R: PinRegisters,
+{
+    pub fn new(regs: R) -> Pinctrl<R> {
+        Pinctrl {
+            regs: regs,
+            owners: Mutex::new([None; PIN_COUNT]),
+        }
+    }
+
+    /// Who has `pin`, if anyone.
+    pub fn owner(&self, pin: Pin) -> Option<&'static str> {
+        if !pin.is_valid() {
+            return None;
+        }
+
+        self.owners.lock()[pin.id()]
+    }
+
+    /// Claim every pin in the group called `name` for `owner`, and switch
+    /// them to the group's functions. Nothing is claimed unless all of them
+    /// can be; claiming pins you already have is fine.
+    pub fn claim_group(&self, name: &str, owner: &'static str) -> Result<&'static Group> {
+        let group = Group::by_name(name).ok_or(PinError::NoSuchGroup)?;
+        let mut owners = self.owners.lock();
+
+        for func in group.pins.iter() {
+            let pin = func.pin;
+
+            if !pin.is_valid() {
+                return Err(PinError::NoSuchPin(pin));
+            }
+
+            if !pin.can_mux(func.mux) {
+                return Err(PinError::BadMux(pin));
+            }
+
+            match owners[pin.id()] {
+                Some(other) if other != owner => {
+                    return Err(PinError::Conflict { pin: pin, owner: other });
+                },
+                _ => (),
+            }
+        }
+
+        for func in group.pins.iter() {
+            owners[func.pin.id()] = Some(owner);
+            self.write_mux(func.pin, func.mux);
+        }
+
+        Ok(group)
+    }
+
+    /// Give back a group's pins, leaving them as they are. Pins `owner`
+    /// doesn't have are skipped.
+    pub fn release_group(&self, name: &str, owner: &'static str) -> Result<()> {
+        let group = Group::by_name(name).ok_or(PinError::NoSuchGroup)?;
+        let mut owners = self.owners.lock();
+
+        for func in group.pins.iter() {
+            if owners[func.pin.id()] == Some(owner) {
+                owners[func.pin.id()] = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Claim a single pin, eg. for use as a GPIO; doesn't touch the iomux.
+    pub fn claim(&self, pin: Pin, owner: &'static str) -> Result<()> {
+        if !pin.is_valid() {
+            return Err(PinError::NoSuchPin(pin));
+        }
+
+        let mut owners = self.owners.lock();
+        match owners[pin.id()] {
+            Some(other) if other != owner => Err(PinError::Conflict { pin: pin, owner: other }),
+            _ => {
+                owners[pin.id()] = Some(owner);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn release(&self, pin: Pin, owner: &'static str) -> Result<()> {
+        self.check_owner(pin, owner)?;
+        self.owners.lock()[pin.id()] = None;
+        Ok(())
+    }
+
+    fn check_owner(&self, pin: Pin, owner: &'static str) -> Result<()> {
+        if !pin.is_valid() {
+            return Err(PinError::NoSuchPin(pin));
+        }
+
+        if self.owners.lock()[pin.id()] != Some(owner) {
+            return Err(PinError::NotOwner(pin));
+        }
+
+        Ok(())
+    }
+
+    /// Current iomux setting.
+    pub fn mux(&self, pin: Pin) -> Result<u8> {
+        let reg = regs(pin)?.iomux.ok_or(PinError::BadMux(pin))?;
+        Ok(self.read_bits(reg, pin.offset() * 2, 2) as u8)
+    }
+
+    pub fn set_mux(&self, pin: Pin, owner: &'static str, mux: u8) -> Result<()> {
+        self.check_owner(pin, owner)?;
+
+        if !pin.can_mux(mux) {
+            return Err(PinError::BadMux(pin));
+        }
+
+        self.write_mux(pin, mux);
+        Ok(())
+    }
+
+    fn write_mux(&self, pin: Pin, mux: u8) {
+        if let Some(reg) = pin.regs().and_then(|regs| regs.iomux) {
+            self.write_bits(reg, pin.offset() * 2, 2, mux as u32);
+        }
+    }
+
+    pub fn pull(&self, pin: Pin) -> Result<Pull> {
+        let regs = regs(pin)?;
+        Ok(Pull::decode(self.read_bits(regs.pull, pin.offset() * 2, 2), regs.pull_type))
+    }
+
+    pub fn set_pull(&self, pin: Pin, owner: &'static str, pull: Pull) -> Result<()> {
+        self.check_owner(pin, owner)?;
+
+        let regs = regs(pin)?;
+        let value = pull.encode(regs.pull_type).ok_or(PinError::BadPull(pin))?;
+        self.write_bits(regs.pull, pin.offset() * 2, 2, value);
+
+        Ok(())
+    }
+
+    /// Drive strength in mA.
+    pub fn drive(&self, pin: Pin) -> Result<u8> {
+        let regs = regs(pin)?;
+        let bits = regs.drive_type.bits();
+        let value = self.read_bits(regs.drive, pin.offset() * bits, bits);
+
+        regs.drive_type.strengths().get(value as usize).cloned().ok_or(PinError::NoDrive(pin))
+    }
+
+    /// Set the drive strength to exactly `ma`, which has to be one the pad
+    /// offers.
+    pub fn set_drive(&self, pin: Pin, owner: &'static str, ma: u8) -> Result<()> {
+        self.check_owner(pin, owner)?;
+
+        let regs = regs(pin)?;
+        if regs.drive_type == DriveType::None {
+            return Err(PinError::NoDrive(pin));
+        }
+
+        let value = regs.drive_type.strengths().iter().position(|s| *s == ma)
+            .ok_or(PinError::BadStrength(ma))?;
+
+        let bits = regs.drive_type.bits();
+        self.write_bits(regs.drive, pin.offset() * bits, bits, value as u32);
+
+        Ok(())
+    }
+
+    // fields are numbered across the data halves of `reg` and the register
+    // after it, for the 3-bit drive fields that run on from one to the next
+    fn read_bits(&self, reg: Reg, shift: u8, width: u8) -> u32 {
+        let mut data = self.regs.read(reg) & 0xffff;
+        if shift + width > 16 {
+            data |= (self.regs.read(reg.next(1)) & 0xffff) << 16;
+        }
+
+        (data >> shift) & ((1 << width) - 1)
+    }
+
+    fn write_bits(&self, reg: Reg, shift: u8, width: u8, value: u32) {
+        let mask = ((1 << width) - 1) << shift;
+        let value = (value << shift) & mask;
+
+        if mask & 0xffff != 0 {
+            self.regs.write(reg, (mask & 0xffff) << 16 | value & 0xffff);
+        }
+
+        if mask >> 16 != 0 {
+            self.regs.write(reg.next(1), (mask >> 16) << 16 | value >> 16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use fake::FakeRegisters;
+    use super::*;
+
+    impl<'a> PinRegisters for &'a FakeRegisters<Reg> {
+        fn read(&self, reg: Reg) -> u32 {
+            self.value(reg)
+        }
+
+        fn write(&self, reg: Reg, value: u32) {
+            self.store_masked(reg, value);
+        }
+    }
+
+    fn grf(offset: u16) -> Reg {
+        Reg { block: Block::Grf, offset: offset }
+    }
+
+    fn pmugrf(offset: u16) -> Reg {
+        Reg { block: Block::PmuGrf, offset: offset }
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(check(|issue| panic!("{:?}", issue)), 0);
+
+        for group in GROUPS.iter() {
+            assert_eq!(Group::by_name(group.name).unwrap().name, group.name);
+        }
+        assert!(Group::by_name("i2c9").is_none());
+
+        // GRF drive registers are packed: one word for a 2-bit port, two
+        // for a 3-bit one
+        let drive = |bank: usize| -> Vec<u16> {
+            BANKS[bank].iter().map(|port| port.drive.offset).collect()
+        };
+        assert_eq!(drive(2), [0xe100, 0xe104, 0xe108, 0xe10c]);
+        assert_eq!(drive(3), [0xe110, 0xe118, 0xe120, 0xe128]);
+        assert_eq!(drive(4), [0xe12c, 0xe130, 0xe138, 0xe13c]);
+        assert_eq!(BANKS[3][3].drive_type, DriveType::Io1v8Or3v0);
+
+        for pair in BANKS[2..].iter().flat_map(|bank| bank.iter()).collect::<Vec<_>>().windows(2) {
+            let words = if pair[0].drive_type.bits() == 3 { 2 } else { 1 };
+            assert_eq!(pair[1].drive.offset, pair[0].drive.offset + words * 4);
+        }
+
+        assert_eq!(format!("{}", Pin::new(1, Port::B, 1)), "gpio1b1");
+    }
+
+    #[test]
+    fn set_mux() {
+        let fake = FakeRegisters::new();
+        let pinctrl = Pinctrl::new(&fake);
+        let pin = Pin::new(1, Port::B, 3);
+
+        pinctrl.claim(pin, "test").unwrap();
+        pinctrl.set_mux(pin, "test", 2).unwrap();
+
+        assert_eq!(fake.writes(), [(pmugrf(0x14), 0x00c0_0080)]);
+        assert_eq!(pinctrl.mux(pin), Ok(2));
+
+        assert_eq!(pinctrl.set_mux(pin, "test", 4), Err(PinError::BadMux(pin)));
+
+        let no_iomux = Pin::new(0, Port::C, 0);
+        pinctrl.claim(no_iomux, "test").unwrap();
+        assert_eq!(pinctrl.set_mux(no_iomux, "test", 1), Err(PinError::BadMux(no_iomux)));
+        assert_eq!(pinctrl.mux(no_iomux), Err(PinError::BadMux(no_iomux)));
+    }
+
+    #[test]
+    fn set_pull() {
+        let fake = FakeRegisters::new();
+        let pinctrl = Pinctrl::new(&fake);
+
+        // Default pads: up is 1
+        let pin = Pin::new(4, Port::C, 1);
+        pinctrl.claim(pin, "test").unwrap();
+        pinctrl.set_pull(pin, "test", Pull::Up).unwrap();
+        assert_eq!(fake.writes()[0], (grf(0xe068), 0x000c_0004));
+        assert_eq!(pinctrl.pull(pin), Ok(Pull::Up));
+
+        // 1.8V-only pads: up is 3, 2 is another way of saying none, and
+        // there's no bus hold
+        let pin = Pin::new(0, Port::A, 3);
+        pinctrl.claim(pin, "test").unwrap();
+        pinctrl.set_pull(pin, "test", Pull::Up).unwrap();
+        assert_eq!(fake.writes()[1], (pmugrf(0x40), 0x00c0_00c0));
+        assert_eq!(pinctrl.pull(pin), Ok(Pull::Up));
+        assert_eq!(pinctrl.set_pull(pin, "test", Pull::BusHold), Err(PinError::BadPull(pin)));
+        assert_eq!(fake.writes().len(), 2);
+
+        fake.store_masked(pmugrf(0x40), 0x00c0_0080);
+        assert_eq!(pinctrl.pull(pin), Ok(Pull::None));
+
+        assert_eq!(pinctrl.set_pull(pin, "other", Pull::Down), Err(PinError::NotOwner(pin)));
+    }
+
+    #[test]
+    fn set_drive() {
+        let fake = FakeRegisters::new();
+        let pinctrl = Pinctrl::new(&fake);
+
+        // 2-bit: 12mA is 3, at bits 4-5
+        let pin = Pin::new(2, Port::A, 2);
+        pinctrl.claim(pin, "test").unwrap();
+        pinctrl.set_drive(pin, "test", 12).unwrap();
+        assert_eq!(fake.writes(), [(grf(0xe100), 0x0030_0030)]);
+        assert_eq!(pinctrl.drive(pin), Ok(12));
+        assert_eq!(pinctrl.set_drive(pin, "test", 4), Err(PinError::BadStrength(4)));
+
+        // 3-bit, within the first register: 26mA is 7, at bits 6-8
+        fake.clear_writes();
+        let pin = Pin::new(3, Port::B, 2);
+        pinctrl.claim(pin, "test").unwrap();
+        pinctrl.set_drive(pin, "test", 26).unwrap();
+        assert_eq!(fake.writes(), [(grf(0xe118), 0x01c0_01c0)]);
+
+        // 3-bit, pin 5: bit 15 of the first register, bits 0-1 of the next;
+        // 16mA is 4, so only the top bit is set
+        fake.clear_writes();
+        let pin = Pin::new(3, Port::B, 5);
+        pinctrl.claim(pin, "test").unwrap();
+        pinctrl.set_drive(pin, "test", 16).unwrap();
+        assert_eq!(fake.writes(), [(grf(0xe118), 0x8000_0000), (grf(0xe11c), 0x0003_0002)]);
+        assert_eq!(pinctrl.drive(pin), Ok(16));
+
+        // and the pins either side are left alone
+        assert_eq!(pinctrl.drive(Pin::new(3, Port::B, 2)), Ok(26));
+        assert_eq!(pinctrl.drive(Pin::new(3, Port::B, 6)), Ok(4));
+
+        for ma in DriveType::Io3v3Only.strengths() {
+            pinctrl.set_drive(pin, "test", *ma).unwrap();
+            assert_eq!(pinctrl.drive(pin), Ok(*ma));
+        }
+
+        let no_drive = Pin::new(0, Port::C, 0);
+        pinctrl.claim(no_drive, "test").unwrap();
+        assert_eq!(pinctrl.set_drive(no_drive, "test", 4), Err(PinError::NoDrive(no_drive)));
+    }
+
+    #[test]
+    fn no_such_pin() {
+        let fake = FakeRegisters::new();
+        let pinctrl = Pinctrl::new(&fake);
+        let pin = Pin { bank: 5, index: 0 };
+
+        assert_eq!(pinctrl.claim(pin, "test"), Err(PinError::NoSuchPin(pin)));
+        assert_eq!(pinctrl.owner(pin), None);
+        assert_eq!(pinctrl.mux(pin), Err(PinError::NoSuchPin(pin)));
+        assert_eq!(pinctrl.pull(pin), Err(PinError::NoSuchPin(pin)));
+        assert_eq!(pinctrl.drive(pin), Err(PinError::NoSuchPin(pin)));
+        assert!(fake.writes().is_empty());
+    }
+
+    #[test]
+    fn ownership() {
+        let fake = FakeRegisters::new();
+        let pinctrl = Pinctrl::new(&fake);
+        let sda = Pin::new(1, Port::B, 3);
+        let scl = Pin::new(1, Port::B, 4);
+
+        pinctrl.claim(sda, "a").unwrap();
+        pinctrl.claim(sda, "a").unwrap();
+        assert_eq!(pinctrl.claim(sda, "b"), Err(PinError::Conflict { pin: sda, owner: "a" }));
+        assert_eq!(pinctrl.release(sda, "b"), Err(PinError::NotOwner(sda)));
+        pinctrl.release(sda, "a").unwrap();
+        assert_eq!(pinctrl.owner(sda), None);
+
+        // a failed group claim leaves everything as it was
+        pinctrl.claim(scl, "other").unwrap();
+        assert_eq!(pinctrl.claim_group("i2c4", "i2c").map(|g| g.name),
+            Err(PinError::Conflict { pin: scl, owner: "other" }));
+        assert_eq!(pinctrl.owner(sda), None);
+        assert_eq!(pinctrl.owner(scl), Some("other"));
+        assert!(fake.writes().is_empty());
+
+        pinctrl.release(scl, "other").unwrap();
+        pinctrl.claim_group("i2c4", "i2c").unwrap();
+        assert_eq!(pinctrl.owner(sda), Some("i2c"));
+        assert_eq!(pinctrl.owner(scl), Some("i2c"));
+        assert_eq!(pinctrl.mux(sda), Ok(1));
+        assert_eq!(pinctrl.mux(scl), Ok(1));
+
+        pinctrl.release_group("i2c4", "i2c").unwrap();
+        assert_eq!(pinctrl.owner(sda), None);
+        assert_eq!(pinctrl.claim_group("nope", "i2c").map(|g| g.name), Err(PinError::NoSuchGroup));
+    }
+}
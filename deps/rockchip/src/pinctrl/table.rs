@@ -0,0 +1,262 @@
+// RK3399 pin banks and pin groups, as tables.
+//
+// Register layout and pad types are from the Linux pinctrl-rockchip driver;
+// groups are from the Linux rk3399.dtsi. Banks 0 and 1 are in the PMUGRF,
+// 2 to 4 in the GRF. Each bank is four ports of eight pins (A to D), and
+// each port has one iomux register, one pull register and one or two drive
+// registers, all with write masks:
+//
+//   iomux  2 bits per pin
+//   pull   2 bits per pin
+//   drive  2 bits per pin in one register, or 3 bits per pin running on
+//          into a second, depending on the pad type
+//
+// In the GRF the drive registers are packed, so a 3-bit port moves the next
+// port's register on by two words rather than one. The PMUGRF ones are
+// spaced two words apart whatever the pad type.
+
+use super::{Block, Pin, Port, Reg};
+
+pub const BANK_COUNT: usize = 5;
+pub const PINS_PER_BANK: usize = 32;
+pub const PIN_COUNT: usize = BANK_COUNT * PINS_PER_BANK;
+
+/// What the pull field's values mean; it depends on the pad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PullType {
+    /// none, up, down, bus hold
+    Default,
+
+    /// none, down, none, up; no bus hold
+    Io1v8Only,
+}
+
+/// Drive strengths a pad offers, in mA, in field order. 3-bit pads have
+/// eight settings, the rest four.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DriveType {
+    /// no drive strength control
+    None,
+    Io1v8Or3v0,
+    Io1v8Only,
+    Io1v83v0Auto,
+    Io3v3Only,
+}
+
+static DRIVE_1V8_OR_3V0: [u8; 4] = [3, 6, 9, 12];
+static DRIVE_1V8_ONLY: [u8; 4] = [5, 10, 15, 20];
+static DRIVE_1V8_3V0_AUTO: [u8; 8] = [4, 6, 8, 10, 12, 14, 16, 18];
+static DRIVE_3V3_ONLY: [u8; 8] = [4, 7, 10, 13, 16, 19, 22, 26];
+
+impl DriveType {
+    pub fn strengths(&self) -> &'static [u8] {
+        match *self {
+            DriveType::None => &[],
+            DriveType::Io1v8Or3v0 => &DRIVE_1V8_OR_3V0,
+            DriveType::Io1v8Only => &DRIVE_1V8_ONLY,
+            DriveType::Io1v83v0Auto => &DRIVE_1V8_3V0_AUTO,
+            DriveType::Io3v3Only => &DRIVE_3V3_ONLY,
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        match *self {
+            DriveType::Io1v83v0Auto | DriveType::Io3v3Only => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Registers for one port of eight pins.
+#[derive(Clone, Copy, Debug)]
+pub struct PortRegs {
+    /// `None` if the pins aren't routed to the iomux
+    pub iomux: Option<Reg>,
+    pub pull: Reg,
+    pub pull_type: PullType,
+
+    /// first of two for 3-bit pads
+    pub drive: Reg,
+    pub drive_type: DriveType,
+}
+
+const fn pmugrf(offset: u16) -> Reg {
+    Reg { block: Block::PmuGrf, offset: offset }
+}
+
+const fn grf(offset: u16) -> Reg {
+    Reg { block: Block::Grf, offset: offset }
+}
+
+macro_rules! group {
+    ($iomux:expr, $pull:expr, $pull_type:ident, $drive:expr, $drive_type:ident) => (
+        PortRegs {
+            iomux: $iomux,
+            pull: $pull,
+            pull_type: PullType::$pull_type,
+            drive: $drive,
+            drive_type: DriveType::$drive_type,
+        }
+    );
+}
+
+pub static BANKS: [[PortRegs; 4]; BANK_COUNT] = [
+    [
+        group!(Some(pmugrf(0x00)), pmugrf(0x40), Io1v8Only, pmugrf(0x80), Io1v8Only),
+        group!(Some(pmugrf(0x04)), pmugrf(0x44), Io1v8Only, pmugrf(0x88), Io1v8Only),
+        group!(None, pmugrf(0x48), Default, pmugrf(0x90), None),
+        group!(None, pmugrf(0x4c), Default, pmugrf(0x98), None),
+    ],
+    [
+        group!(Some(pmugrf(0x10)), pmugrf(0x50), Default, pmugrf(0xa0), Io1v8Or3v0),
+        group!(Some(pmugrf(0x14)), pmugrf(0x54), Default, pmugrf(0xa8), Io1v8Or3v0),
+        group!(Some(pmugrf(0x18)), pmugrf(0x58), Default, pmugrf(0xb0), Io1v8Or3v0),
+        group!(Some(pmugrf(0x1c)), pmugrf(0x5c), Default, pmugrf(0xb8), Io1v8Or3v0),
+    ],
+    [
+        group!(Some(grf(0xe000)), grf(0xe040), Default, grf(0xe100), Io1v8Or3v0),
+        group!(Some(grf(0xe004)), grf(0xe044), Default, grf(0xe104), Io1v8Or3v0),
+        group!(Some(grf(0xe008)), grf(0xe048), Io1v8Only, grf(0xe108), Io1v8Only),
+        group!(Some(grf(0xe00c)), grf(0xe04c), Io1v8Only, grf(0xe10c), Io1v8Only),
+    ],
+    [
+        group!(Some(grf(0xe010)), grf(0xe050), Default, grf(0xe110), Io3v3Only),
+        group!(Some(grf(0xe014)), grf(0xe054), Default, grf(0xe118), Io3v3Only),
+        group!(Some(grf(0xe018)), grf(0xe058), Default, grf(0xe120), Io3v3Only),
+        group!(Some(grf(0xe01c)), grf(0xe05c), Default, grf(0xe128), Io1v8Or3v0),
+    ],
+    [
+        group!(Some(grf(0xe020)), grf(0xe060), Default, grf(0xe12c), Io1v8Or3v0),
+        group!(Some(grf(0xe024)), grf(0xe064), Default, grf(0xe130), Io1v83v0Auto),
+        group!(Some(grf(0xe028)), grf(0xe068), Default, grf(0xe138), Io1v8Or3v0),
+        group!(Some(grf(0xe02c)), grf(0xe06c), Default, grf(0xe13c), Io1v8Or3v0),
+    ],
+];
+
+/// A pin set to a function by its iomux.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PinFunction {
+    pub pin: Pin,
+
+    /// iomux value; 0 is always GPIO
+    pub mux: u8,
+
+    /// signal name, as in the TRM
+    pub name: &'static str,
+}
+
+/// Pins a driver needs, all claimed together.
+#[derive(Clone, Copy, Debug)]
+pub struct Group {
+    pub name: &'static str,
+    pub pins: &'static [PinFunction],
+}
+
+macro_rules! func {
+    ($bank:expr, $port:ident, $pin:expr, $mux:expr, $name:expr) => (
+        PinFunction { pin: Pin::new($bank, Port::$port, $pin), mux: $mux, name: $name }
+    );
+}
+
+static PMU_M0_JTAG: [PinFunction; 2] = [
+    func!(1, B, 1, 1, "pmum0jtag_tck"),
+    func!(1, B, 2, 1, "pmum0jtag_tms"),
+];
+
+static I2C0: [PinFunction; 2] = [
+    func!(1, B, 7, 2, "i2c0pmu_sda"),
+    func!(1, C, 0, 2, "i2c0pmu_scl"),
+];
+
+static I2C1: [PinFunction; 2] = [
+    func!(4, A, 1, 1, "i2c1_sda"),
+    func!(4, A, 2, 1, "i2c1_scl"),
+];
+
+static I2C3: [PinFunction; 2] = [
+    func!(4, C, 0, 1, "i2c3hdmi_sda"),
+    func!(4, C, 1, 1, "i2c3hdmi_scl"),
+];
+
+static I2C4: [PinFunction; 2] = [
+    func!(1, B, 3, 1, "i2c4pmu_sda"),
+    func!(1, B, 4, 1, "i2c4pmu_scl"),
+];
+
+static I2C8: [PinFunction; 2] = [
+    func!(1, C, 4, 1, "i2c8sensor_sda"),
+    func!(1, C, 5, 1, "i2c8sensor_scl"),
+];
+
+static UART0: [PinFunction; 2] = [
+    func!(2, C, 0, 1, "uart0_rx"),
+    func!(2, C, 1, 1, "uart0_tx"),
+];
+
+static UART2C: [PinFunction; 2] = [
+    func!(4, C, 3, 1, "uart2dbgc_sin"),
+    func!(4, C, 4, 1, "uart2dbgc_sout"),
+];
+
+pub static GROUPS: [Group; 8] = [
+    Group { name: "pmu_m0_jtag", pins: &PMU_M0_JTAG },
+    Group { name: "i2c0", pins: &I2C0 },
+    Group { name: "i2c1", pins: &I2C1 },
+    Group { name: "i2c3", pins: &I2C3 },
+    Group { name: "i2c4", pins: &I2C4 },
+    Group { name: "i2c8", pins: &I2C8 },
+    Group { name: "uart0", pins: &UART0 },
+    Group { name: "uart2c", pins: &UART2C },
+];
+
+impl Group {
+    /// Look a group up by name.
+    pub fn by_name(name: &str) -> Option<&'static Group> {
+        GROUPS.iter().find(|group| group.name == name)
+    }
+}
+
+/// Something wrong with the tables.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableIssue {
+    /// Pin doesn't exist
+    NoSuchPin(&'static str, Pin),
+
+    /// Pin isn't routed to the iomux, or the function is out of range
+    BadMux(&'static str, Pin),
+
+    /// Pin appears twice in a group
+    Duplicate(&'static str, Pin),
+}
+
+/// Check the group tables for mistakes, passing each one to `f`; returns
+/// how many there were.
+pub fn check<F>(mut f: F) -> usize
+where
+    F: FnMut(TableIssue),
+{
+    let mut issues = 0;
+
+    for group in GROUPS.iter() {
+        for (n, func) in group.pins.iter().enumerate() {
+            let pin = func.pin;
+
+            let issue = if !pin.is_valid() {
+                Some(TableIssue::NoSuchPin(group.name, pin))
+            } else if pin.regs().map_or(true, |regs| regs.iomux.is_none()) || func.mux > 3 {
+                Some(TableIssue::BadMux(group.name, pin))
+            } else if group.pins[..n].iter().any(|other| other.pin == pin) {
+                Some(TableIssue::Duplicate(group.name, pin))
+            } else {
+                None
+            };
+
+            if let Some(issue) = issue {
+                f(issue);
+                issues += 1;
+            }
+        }
+    }
+
+    issues
+}
@@ -0,0 +1,131 @@
+use nb;
+
+use i2c::I2CError;
+
+/// An error from a voltage regulator
+#[derive(Debug)]
+pub enum Error {
+    /// Requested voltage is outside what the regulator can output
+    OutOfRange,
+
+    /// Regulator can't do that (eg. its voltage is set by external resistors)
+    Unsupported,
+
+    /// Talking to the regulator over I2C failed
+    Bus(I2CError),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<nb::Error<I2CError>> for Error {
+    fn from(e: nb::Error<I2CError>) -> Error {
+        match e {
+            nb::Error::Other(e) => Error::Bus(e),
+            nb::Error::WouldBlock => Error::Bus(I2CError::Timeout),
+        }
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// A linear range of selectable voltages: `min_uv + step_uv * n` for
+/// selector values `min_sel..max_sel` inclusive.
+#[derive(Clone, Copy, Debug)]
+pub struct LinearRange {
+    pub min_uv: u32,
+    pub min_sel: u8,
+    pub max_sel: u8,
+    pub step_uv: u32,
+}
+
+impl LinearRange {
+    pub fn max_uv(&self) -> u32 {
+        self.min_uv + self.step_uv * (self.max_sel - self.min_sel) as u32
+    }
+
+    pub fn voltage(&self, sel: u8) -> Option<u32> {
+        if sel < self.min_sel || sel > self.max_sel {
+            return None;
+        }
+
+        Some(self.min_uv + self.step_uv * (sel - self.min_sel) as u32)
+    }
+
+    /// Lowest selector giving at least `uv`.
+    pub fn selector(&self, uv: u32) -> Option<u8> {
+        if uv < self.min_uv || uv > self.max_uv() {
+            return None;
+        }
+
+        if self.step_uv == 0 {
+            return Some(self.min_sel);
+        }
+
+        let steps = (uv - self.min_uv + self.step_uv - 1) / self.step_uv;
+        Some(self.min_sel + steps as u8)
+    }
+}
+
+/// Pick the voltage for selector `sel` from a set of ranges.
+pub fn ranges_voltage(ranges: &[LinearRange], sel: u8) -> Option<u32> {
+    ranges.iter().filter_map(|r| r.voltage(sel)).next()
+}
+
+/// Pick the lowest selector giving at least `uv` from a set of ranges. A
+/// voltage in a gap between two ranges rounds up to the start of the one
+/// above; one below all of them or above all of them is out of range.
+pub fn ranges_selector(ranges: &[LinearRange], uv: u32) -> Option<u8> {
+    if let Some(sel) = ranges.iter().filter_map(|r| r.selector(uv)).next() {
+        return Some(sel);
+    }
+
+    if !ranges.iter().any(|r| r.min_uv < uv) {
+        return None;
+    }
+
+    ranges.iter().filter(|r| r.min_uv > uv).min_by_key(|r| r.min_uv).map(|r| r.min_sel)
+}
+
+/// A single power rail.
+pub trait Regulator {
+    /// Output voltage, in microvolts.
+    fn voltage(&self) -> Result<u32>;
+
+    /// Change the output voltage, rounding up to the regulator's next step.
+    /// Fails with `OutOfRange` rather than clamping.
+    fn set_voltage(&self, uv: u32) -> Result<()>;
+
+    fn is_enabled(&self) -> Result<bool>;
+    fn set_enabled(&self, enabled: bool) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the RK808's LDO3: nothing between 2.1V and 2.5V
+    static GAPPED: [LinearRange; 2] = [
+        LinearRange { min_uv: 800_000, min_sel: 0, max_sel: 13, step_uv: 100_000 },
+        LinearRange { min_uv: 2_500_000, min_sel: 15, max_sel: 15, step_uv: 0 },
+    ];
+
+    #[test]
+    fn selector() {
+        assert_eq!(ranges_selector(&GAPPED, 800_000), Some(0));
+        assert_eq!(ranges_selector(&GAPPED, 850_000), Some(1));
+        assert_eq!(ranges_selector(&GAPPED, 2_100_000), Some(13));
+        assert_eq!(ranges_selector(&GAPPED, 2_500_000), Some(15));
+
+        assert_eq!(ranges_selector(&GAPPED, 700_000), None);
+        assert_eq!(ranges_selector(&GAPPED, 2_600_000), None);
+    }
+
+    #[test]
+    fn gap() {
+        assert_eq!(ranges_selector(&GAPPED, 2_100_001), Some(15));
+        assert_eq!(ranges_selector(&GAPPED, 2_200_000), Some(15));
+        assert_eq!(ranges_selector(&GAPPED, 2_400_000), Some(15));
+        assert_eq!(ranges_voltage(&GAPPED, 15), Some(2_500_000));
+    }
+}
@@ -0,0 +1,532 @@
+// Single-producer, single-consumer byte ring in memory shared by the AP and
+// the M0.
+//
+// Layout, all words little endian, starting at a word-aligned `base`:
+//
+//   0x00  magic      RING_MAGIC once initialised
+//   0x04  capacity   size of the data area in bytes, a power of two
+//   0x40  head       bytes ever written; only the producer writes this
+//   0x80  tail       bytes ever read; only the consumer writes this
+//   0xc0  data       `capacity` bytes
+//
+// head and tail are free-running sequence counters that wrap at 2^32, so
+// `head - tail` is the number of bytes waiting, and a byte's place in the
+// data area is its sequence number modulo `capacity`. Each side only ever
+// writes its own counter, which is what makes this safe without a lock:
+//
+//  - the producer fills data, fences, then publishes the new head
+//  - the consumer reads head, fences, copies data out, fences, then
+//    publishes the new tail
+//
+// The fences order the accesses as seen by the other core, but neither
+// side snoops the other's caches, so each end also cleans and invalidates
+// (see `cache`) whatever it's about to read or has just written. head and
+// tail sit on cache lines of their own, so writing one back can never
+// undo the other side's update to the other.
+//
+// On top of the byte stream, `write_record`/`read_record` frame messages
+// with a 16-bit length, written all-or-nothing.
+
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use cache::{self, SHARED_LINE};
+
+pub const RING_MAGIC: u32 = 0x474e4952; // "RING"
+pub const HEADER_SIZE: usize = 3 * SHARED_LINE;
+
+const MAGIC: usize = 0x00;
+const CAPACITY: usize = 0x04;
+const HEAD: usize = SHARED_LINE;
+const TAIL: usize = 2 * SHARED_LINE;
+
+const RECORD_HEADER: usize = 2;
+
+/// An error from setting up or using a ring
+#[derive(Debug, PartialEq)]
+pub enum RingError {
+    /// Not enough room for the header and any data
+    TooSmall,
+
+    /// `base` isn't word aligned
+    Misaligned,
+
+    /// Nobody has initialised a ring here
+    NotInitialised,
+
+    /// Not enough free space for the whole record
+    Full,
+
+    /// Record is bigger than the ring, or than 64KiB
+    TooLarge,
+
+    /// Buffer is too small for the next record; the record is left in place
+    BufferTooSmall(usize),
+
+    /// Ring contents make no sense (eg. the other side is misbehaving)
+    Corrupt,
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+// common to both ends
+struct Ring {
+    base: *mut u8,
+    capacity: u32,
+}
+
+impl Ring {
+    unsafe fn attach(base: *mut u8) -> Result<Ring, RingError> {
+        if base as usize % 4 != 0 {
+            return Err(RingError::Misaligned);
+        }
+
+        let ring = Ring { base: base, capacity: 0 };
+        ring.sync(MAGIC, 8);
+
+        if ring.load(MAGIC) != RING_MAGIC {
+            return Err(RingError::NotInitialised);
+        }
+
+        let capacity = ring.load(CAPACITY);
+        if capacity == 0 || !capacity.is_power_of_two() {
+            return Err(RingError::Corrupt);
+        }
+
+        Ok(Ring { base: base, capacity: capacity })
+    }
+
+    fn load(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.base.offset(offset as isize) as *const u32) }
+    }
+
+    fn store(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.base.offset(offset as isize) as *mut u32, value) }
+    }
+
+    fn data(&self, seq: u32) -> *mut u8 {
+        let index = (seq & (self.capacity - 1)) as usize;
+        unsafe { self.base.offset((HEADER_SIZE + index) as isize) }
+    }
+
+    // write back our changes to `len` bytes at `offset`, and see the other
+    // side's
+    fn sync(&self, offset: usize, len: usize) {
+        cache::clean_invalidate(self.base as usize + offset, len);
+    }
+
+    // the same for `len` bytes of data from sequence number `seq`, which
+    // may wrap around the end of the data area
+    fn sync_data(&self, seq: u32, len: usize) {
+        let index = (seq & (self.capacity - 1)) as usize;
+        let room = self.capacity as usize - index;
+        let first = if len < room { len } else { room };
+
+        self.sync(HEADER_SIZE + index, first);
+        self.sync(HEADER_SIZE, len - first);
+    }
+
+    fn used(&self) -> u32 {
+        self.sync(HEAD, 4);
+        self.sync(TAIL, 4);
+        self.load(HEAD).wrapping_sub(self.load(TAIL))
+    }
+
+    // copy out of the ring starting at sequence number `seq`, without
+    // moving anything
+    fn peek(&self, seq: u32, buf: &mut [u8]) {
+        self.sync_data(seq, buf.len());
+
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile(self.data(seq.wrapping_add(i as u32))) };
+        }
+    }
+}
+
+/// Set up an empty ring in the `len` bytes at `base`. Only one side should
+/// do this, before the other attaches.
+///
+/// The data area is the largest power of two that fits after the header.
+pub unsafe fn init(base: *mut u8, len: usize) -> Result<(), RingError> {
+    if base as usize % 4 != 0 {
+        return Err(RingError::Misaligned);
+    }
+
+    if len <= HEADER_SIZE {
+        return Err(RingError::TooSmall);
+    }
+
+    let room = (len - HEADER_SIZE) as u32;
+    let mut capacity = 1u32 << 31;
+    while capacity > room {
+        capacity >>= 1;
+    }
+
+    let ring = Ring { base: base, capacity: capacity };
+    ring.store(MAGIC, 0);
+    ring.store(CAPACITY, capacity);
+    ring.store(HEAD, 0);
+    ring.store(TAIL, 0);
+
+    // everything else has to be in place before the magic says so
+    fence(Ordering::SeqCst);
+    ring.store(MAGIC, RING_MAGIC);
+
+    // and nothing stale left to be written back over the other side's data
+    ring.sync(0, HEADER_SIZE + capacity as usize);
+
+    Ok(())
+}
+
+/// The writing end.
+pub struct Producer {
+    ring: Ring,
+}
+
+impl Producer {
+    /// Attach to a ring someone has already `init`ed.
+    pub unsafe fn attach(base: *mut u8) -> Result<Producer, RingError> {
+        Ok(Producer { ring: Ring::attach(base)? })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity as usize
+    }
+
+    /// Bytes that can be written without overwriting unread data.
+    pub fn free(&self) -> Result<usize, RingError> {
+        let used = self.ring.used();
+        // don't start overwriting until the consumer's done reading
+        fence(Ordering::Acquire);
+
+        // a tail ahead of the head, or more waiting than fits
+        if used > self.ring.capacity {
+            return Err(RingError::Corrupt);
+        }
+
+        Ok((self.ring.capacity - used) as usize)
+    }
+
+    /// Write as much of `data` as fits, returning how much that was.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, RingError> {
+        let free = self.free()?;
+        let len = if data.len() < free { data.len() } else { free };
+        let head = self.ring.load(HEAD);
+
+        for (i, byte) in data[..len].iter().enumerate() {
+            unsafe { ptr::write_volatile(self.ring.data(head.wrapping_add(i as u32)), *byte) };
+        }
+
+        // data has to land before the consumer can see the new head
+        self.ring.sync_data(head, len);
+        fence(Ordering::Release);
+        self.ring.store(HEAD, head.wrapping_add(len as u32));
+        self.ring.sync(HEAD, 4);
+
+        Ok(len)
+    }
+
+    /// Write `data` as one record, or not at all.
+    pub fn write_record(&mut self, data: &[u8]) -> Result<(), RingError> {
+        if data.len() > 0xffff || data.len() + RECORD_HEADER > self.capacity() {
+            return Err(RingError::TooLarge);
+        }
+
+        if data.len() + RECORD_HEADER > self.free()? {
+            return Err(RingError::Full);
+        }
+
+        let len = data.len() as u16;
+        let head = self.ring.load(HEAD);
+
+        let header = [len as u8, (len >> 8) as u8];
+        for (i, byte) in header.iter().chain(data.iter()).enumerate() {
+            unsafe { ptr::write_volatile(self.ring.data(head.wrapping_add(i as u32)), *byte) };
+        }
+
+        self.ring.sync_data(head, data.len() + RECORD_HEADER);
+        fence(Ordering::Release);
+        self.ring.store(HEAD, head.wrapping_add((data.len() + RECORD_HEADER) as u32));
+        self.ring.sync(HEAD, 4);
+
+        Ok(())
+    }
+}
+
+// each end is only ever used from one side at a time, which is the point
+unsafe impl Send for Producer { }
+
+/// The reading end.
+pub struct Consumer {
+    ring: Ring,
+}
+
+impl Consumer {
+    /// Attach to a ring someone has already `init`ed.
+    pub unsafe fn attach(base: *mut u8) -> Result<Consumer, RingError> {
+        Ok(Consumer { ring: Ring::attach(base)? })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity as usize
+    }
+
+    /// Bytes waiting to be read.
+    pub fn available(&self) -> usize {
+        let used = self.ring.used();
+        fence(Ordering::Acquire);
+        used as usize
+    }
+
+    /// Read as much as is waiting into `buf`, returning how much that was.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let available = self.available();
+        let len = if buf.len() < available { buf.len() } else { available };
+        let tail = self.ring.load(TAIL);
+
+        self.ring.peek(tail, &mut buf[..len]);
+        self.consume(tail, len);
+
+        len
+    }
+
+    /// Read the next record into `buf`, returning its length; `None` if
+    /// there isn't one waiting.
+    pub fn read_record(&mut self, buf: &mut [u8]) -> Result<Option<usize>, RingError> {
+        let available = self.available();
+        if available < RECORD_HEADER {
+            return Ok(None);
+        }
+
+        let tail = self.ring.load(TAIL);
+
+        let mut header = [0u8; RECORD_HEADER];
+        self.ring.peek(tail, &mut header);
+        let len = header[0] as usize | (header[1] as usize) << 8;
+
+        // the producer writes whole records, so a partial one is garbage
+        if len + RECORD_HEADER > available {
+            return Err(RingError::Corrupt);
+        }
+
+        if len > buf.len() {
+            return Err(RingError::BufferTooSmall(len));
+        }
+
+        self.ring.peek(tail.wrapping_add(RECORD_HEADER as u32), &mut buf[..len]);
+        self.consume(tail, len + RECORD_HEADER);
+
+        Ok(Some(len))
+    }
+
+    /// Throw away the next record without reading it.
+    pub fn skip_record(&mut self) -> Result<(), RingError> {
+        if self.available() < RECORD_HEADER {
+            return Ok(());
+        }
+
+        let tail = self.ring.load(TAIL);
+
+        let mut header = [0u8; RECORD_HEADER];
+        self.ring.peek(tail, &mut header);
+        let len = header[0] as usize | (header[1] as usize) << 8;
+
+        if len + RECORD_HEADER > self.available() {
+            return Err(RingError::Corrupt);
+        }
+
+        self.consume(tail, len + RECORD_HEADER);
+        Ok(())
+    }
+
+    // we're done with the data, so the producer can have the space back
+    fn consume(&mut self, tail: u32, len: usize) {
+        fence(Ordering::Release);
+        self.ring.store(TAIL, tail.wrapping_add(len as u32));
+        self.ring.sync(TAIL, 4);
+    }
+}
+
+unsafe impl Send for Consumer { }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::*;
+
+    // a word-aligned ring of `capacity` data bytes, with head and tail both
+    // starting at `start`
+    fn ring(capacity: usize, start: u32) -> Vec<u32> {
+        let mut mem = vec![0u32; (HEADER_SIZE + capacity) / 4];
+
+        unsafe { init(mem.as_mut_ptr() as *mut u8, HEADER_SIZE + capacity).unwrap() };
+        mem[HEAD / 4] = start;
+        mem[TAIL / 4] = start;
+
+        mem
+    }
+
+    fn base(mem: &mut Vec<u32>) -> *mut u8 {
+        mem.as_mut_ptr() as *mut u8
+    }
+
+    // record `n`: its length and contents both follow from `n`
+    fn record(n: usize) -> Vec<u8> {
+        (0..n % 23).map(|i| (n + i) as u8).collect()
+    }
+
+    #[test]
+    fn init_errors() {
+        let mut mem = vec![0u32; (HEADER_SIZE + 16) / 4];
+        let base = base(&mut mem);
+
+        unsafe {
+            assert_eq!(init(base.offset(1), 16), Err(RingError::Misaligned));
+            assert_eq!(init(base, HEADER_SIZE), Err(RingError::TooSmall));
+            assert_eq!(Producer::attach(base).err(), Some(RingError::NotInitialised));
+
+            // 15 bytes of room: the data area rounds down to 8
+            init(base, HEADER_SIZE + 15).unwrap();
+            assert_eq!(Producer::attach(base).unwrap().capacity(), 8);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut mem = ring(16, 0);
+        let mut consumer = unsafe { Consumer::attach(base(&mut mem)).unwrap() };
+        let mut buf = [0u8; 16];
+
+        assert_eq!(consumer.available(), 0);
+        assert_eq!(consumer.read(&mut buf), 0);
+        assert_eq!(consumer.read_record(&mut buf), Ok(None));
+        assert_eq!(consumer.skip_record(), Ok(()));
+    }
+
+    #[test]
+    fn full() {
+        let mut mem = ring(16, 0);
+        let mut producer = unsafe { Producer::attach(base(&mut mem)).unwrap() };
+        let mut consumer = unsafe { Consumer::attach(base(&mut mem)).unwrap() };
+
+        assert_eq!(producer.write(&[1; 20]), Ok(16));
+        assert_eq!(producer.free(), Ok(0));
+        assert_eq!(producer.write(&[2; 4]), Ok(0));
+        assert_eq!(producer.write_record(&[]), Err(RingError::Full));
+        assert_eq!(producer.write_record(&[3; 15]), Err(RingError::TooLarge));
+
+        let mut buf = [0u8; 4];
+        assert_eq!(consumer.read(&mut buf), 4);
+        assert_eq!(buf, [1; 4]);
+        assert_eq!(producer.free(), Ok(4));
+
+        // nothing goes in unless all of it does
+        assert_eq!(producer.write_record(&[4; 3]), Err(RingError::Full));
+        producer.write_record(&[5; 2]).unwrap();
+        assert_eq!(producer.free(), Ok(0));
+    }
+
+    #[test]
+    fn records() {
+        let mut mem = ring(32, 0);
+        let mut producer = unsafe { Producer::attach(base(&mut mem)).unwrap() };
+        let mut consumer = unsafe { Consumer::attach(base(&mut mem)).unwrap() };
+        let mut buf = [0u8; 8];
+
+        producer.write_record(b"hello").unwrap();
+        producer.write_record(b"").unwrap();
+        producer.write_record(b"too long").unwrap();
+
+        assert_eq!(consumer.read_record(&mut buf), Ok(Some(5)));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(consumer.read_record(&mut buf), Ok(Some(0)));
+
+        // left in place for a bigger buffer
+        assert_eq!(consumer.read_record(&mut buf[..4]), Err(RingError::BufferTooSmall(8)));
+        assert_eq!(consumer.read_record(&mut buf), Ok(Some(8)));
+        assert_eq!(&buf, b"too long");
+        assert_eq!(consumer.read_record(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut mem = ring(16, 0);
+
+        mem[CAPACITY / 4] = 12;
+        assert_eq!(unsafe { Consumer::attach(base(&mut mem)).err() }, Some(RingError::Corrupt));
+        mem[CAPACITY / 4] = 0;
+        assert_eq!(unsafe { Consumer::attach(base(&mut mem)).err() }, Some(RingError::Corrupt));
+        mem[CAPACITY / 4] = 16;
+
+        let mut producer = unsafe { Producer::attach(base(&mut mem)).unwrap() };
+        let mut consumer = unsafe { Consumer::attach(base(&mut mem)).unwrap() };
+
+        // tail ahead of head
+        mem[TAIL / 4] = 1;
+        assert_eq!(producer.free(), Err(RingError::Corrupt));
+        assert_eq!(producer.write(&[0; 4]), Err(RingError::Corrupt));
+        assert_eq!(producer.write_record(&[0; 4]), Err(RingError::Corrupt));
+
+        // a record header longer than what's been written
+        mem[TAIL / 4] = 0;
+        producer.write(&[10, 0, 1, 2]).unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(consumer.read_record(&mut buf), Err(RingError::Corrupt));
+        assert_eq!(consumer.skip_record(), Err(RingError::Corrupt));
+        assert_eq!(consumer.available(), 4);
+    }
+
+    // one thread each end, over a small ring whose head and tail are about
+    // to wrap, so records straddle the end of the data area and the
+    // counters go through zero
+    #[test]
+    fn threads() {
+        const RECORDS: usize = 10_000;
+
+        let mut mem = ring(64, 0xffff_ff00);
+        let base = base(&mut mem) as usize;
+
+        let producer = thread::spawn(move || {
+            let mut producer = unsafe { Producer::attach(base as *mut u8).unwrap() };
+
+            for n in 0..RECORDS {
+                let data = record(n);
+                loop {
+                    match producer.write_record(&data) {
+                        Ok(()) => break,
+                        Err(RingError::Full) => thread::yield_now(),
+                        Err(e) => panic!("record {}: {:?}", n, e),
+                    }
+                }
+            }
+        });
+
+        let consumer = thread::spawn(move || {
+            let mut consumer = unsafe { Consumer::attach(base as *mut u8).unwrap() };
+            let mut buf = [0u8; 32];
+            let mut n = 0;
+
+            while n < RECORDS {
+                match consumer.read_record(&mut buf) {
+                    Ok(Some(len)) => {
+                        assert_eq!(&buf[..len], &record(n)[..], "record {}", n);
+                        n += 1;
+                    },
+                    Ok(None) => thread::yield_now(),
+                    Err(e) => panic!("record {}: {:?}", n, e),
+                }
+            }
+        });
+
+        producer.join().unwrap();
+        consumer.join().unwrap();
+
+        // every record went through, and the counters wrapped
+        assert_eq!(mem[HEAD / 4], mem[TAIL / 4]);
+        assert!(mem[HEAD / 4] < 0xffff_ff00);
+    }
+}
@@ -0,0 +1,362 @@
+use i2c::{self, I2CTrait};
+use regulator::{self, LinearRange, Regulator, Error};
+
+pub mod rtc;
+pub use self::rtc::RK808Rtc;
+
+/// 7-bit I2C address of the RK808.
+pub const RK808_ADDRESS: u8 = 0x1b;
+
+// register map; see the RK808 datasheet, section 3
+const VB_MON: u8 = 0x21;
+const DCDC_EN: u8 = 0x23;
+const LDO_EN: u8 = 0x24;
+const DCDC_UV_STS: u8 = 0x27;
+const LDO_UV_STS: u8 = 0x29;
+const DCDC_PG: u8 = 0x2b;
+const LDO_PG: u8 = 0x2c;
+const BUCK1_ON_VSEL: u8 = 0x2f;
+const BUCK2_ON_VSEL: u8 = 0x33;
+const BUCK4_ON_VSEL: u8 = 0x38;
+const LDO1_ON_VSEL: u8 = 0x3b;
+const LDO2_ON_VSEL: u8 = 0x3d;
+const LDO3_ON_VSEL: u8 = 0x3f;
+const LDO4_ON_VSEL: u8 = 0x41;
+const LDO5_ON_VSEL: u8 = 0x43;
+const LDO6_ON_VSEL: u8 = 0x45;
+const LDO7_ON_VSEL: u8 = 0x47;
+const LDO8_ON_VSEL: u8 = 0x49;
+const DEVCTRL: u8 = 0x4b;
+const INT_STS1: u8 = 0x4c;
+const INT_STS_MSK1: u8 = 0x4d;
+const INT_STS2: u8 = 0x4e;
+const INT_STS_MSK2: u8 = 0x4f;
+
+// BUCK1/2 can brown out the PMIC if their output jumps too far at once,
+// so big changes are made a few selector steps at a time (as the vendor
+// kernel does)
+const BUCK1_2_MAX_STEPS: u8 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rail {
+    Buck1,
+    Buck2,
+    Buck3,
+    Buck4,
+    Ldo1,
+    Ldo2,
+    Ldo3,
+    Ldo4,
+    Ldo5,
+    Ldo6,
+    Ldo7,
+    Ldo8,
+    Switch1,
+    Switch2,
+}
+
+pub const RAILS: [Rail; 14] = [
+    Rail::Buck1, Rail::Buck2, Rail::Buck3, Rail::Buck4,
+    Rail::Ldo1, Rail::Ldo2, Rail::Ldo3, Rail::Ldo4,
+    Rail::Ldo5, Rail::Ldo6, Rail::Ldo7, Rail::Ldo8,
+    Rail::Switch1, Rail::Switch2,
+];
+
+static BUCK1_2_RANGES: [LinearRange; 1] = [
+    LinearRange { min_uv: 712_500, min_sel: 0, max_sel: 63, step_uv: 12_500 },
+];
+
+static BUCK4_RANGES: [LinearRange; 1] = [
+    LinearRange { min_uv: 1_800_000, min_sel: 0, max_sel: 15, step_uv: 100_000 },
+];
+
+static LDO_RANGES: [LinearRange; 1] = [
+    LinearRange { min_uv: 1_800_000, min_sel: 0, max_sel: 16, step_uv: 100_000 },
+];
+
+// selector 14 isn't documented, 15 jumps straight to 2.5V
+static LDO3_RANGES: [LinearRange; 2] = [
+    LinearRange { min_uv: 800_000, min_sel: 0, max_sel: 13, step_uv: 100_000 },
+    LinearRange { min_uv: 2_500_000, min_sel: 15, max_sel: 15, step_uv: 0 },
+];
+
+static LDO6_7_RANGES: [LinearRange; 1] = [
+    LinearRange { min_uv: 800_000, min_sel: 0, max_sel: 17, step_uv: 100_000 },
+];
+
+struct RailDesc {
+    name: &'static str,
+    enable_reg: u8,
+    enable_bit: u8,
+
+    /// register selecting the output voltage in the active state;
+    /// `None` when set by external feedback resistors (BUCK3, switches)
+    vsel_reg: Option<u8>,
+    vsel_mask: u8,
+    ranges: &'static [LinearRange],
+}
+
+impl RailDesc {
+    fn new(name: &'static str, enable_reg: u8, enable_bit: u8, vsel_reg: Option<u8>,
+           vsel_mask: u8, ranges: &'static [LinearRange]) -> RailDesc {
+        RailDesc {
+            name: name,
+            enable_reg: enable_reg,
+            enable_bit: enable_bit,
+            vsel_reg: vsel_reg,
+            vsel_mask: vsel_mask,
+            ranges: ranges,
+        }
+    }
+}
+
+impl Rail {
+    fn desc(&self) -> RailDesc {
+        match *self {
+            Rail::Buck1 => RailDesc::new("BUCK1", DCDC_EN, 0, Some(BUCK1_ON_VSEL), 0x3f, &BUCK1_2_RANGES),
+            Rail::Buck2 => RailDesc::new("BUCK2", DCDC_EN, 1, Some(BUCK2_ON_VSEL), 0x3f, &BUCK1_2_RANGES),
+            Rail::Buck3 => RailDesc::new("BUCK3", DCDC_EN, 2, None, 0, &[]),
+            Rail::Buck4 => RailDesc::new("BUCK4", DCDC_EN, 3, Some(BUCK4_ON_VSEL), 0x0f, &BUCK4_RANGES),
+            Rail::Ldo1 => RailDesc::new("LDO1", LDO_EN, 0, Some(LDO1_ON_VSEL), 0x1f, &LDO_RANGES),
+            Rail::Ldo2 => RailDesc::new("LDO2", LDO_EN, 1, Some(LDO2_ON_VSEL), 0x1f, &LDO_RANGES),
+            Rail::Ldo3 => RailDesc::new("LDO3", LDO_EN, 2, Some(LDO3_ON_VSEL), 0x0f, &LDO3_RANGES),
+            Rail::Ldo4 => RailDesc::new("LDO4", LDO_EN, 3, Some(LDO4_ON_VSEL), 0x1f, &LDO_RANGES),
+            Rail::Ldo5 => RailDesc::new("LDO5", LDO_EN, 4, Some(LDO5_ON_VSEL), 0x1f, &LDO_RANGES),
+            Rail::Ldo6 => RailDesc::new("LDO6", LDO_EN, 5, Some(LDO6_ON_VSEL), 0x1f, &LDO6_7_RANGES),
+            Rail::Ldo7 => RailDesc::new("LDO7", LDO_EN, 6, Some(LDO7_ON_VSEL), 0x1f, &LDO6_7_RANGES),
+            Rail::Ldo8 => RailDesc::new("LDO8", LDO_EN, 7, Some(LDO8_ON_VSEL), 0x1f, &LDO_RANGES),
+            Rail::Switch1 => RailDesc::new("SWITCH1", DCDC_EN, 5, None, 0, &[]),
+            Rail::Switch2 => RailDesc::new("SWITCH2", DCDC_EN, 6, None, 0, &[]),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.desc().name
+    }
+
+    /// Lowest and highest voltage this rail can be set to, if adjustable.
+    pub fn limits(&self) -> Option<(u32, u32)> {
+        let desc = self.desc();
+        if desc.vsel_reg.is_none() {
+            return None;
+        }
+
+        let min = desc.ranges.iter().map(|r| r.min_uv).min();
+        let max = desc.ranges.iter().map(|r| r.max_uv()).max();
+
+        match (min, max) {
+            (Some(min), Some(max)) => Some((min, max)),
+            _ => None,
+        }
+    }
+}
+
+/// Pending PMIC interrupts, as in INT_STS1 (low byte) and INT_STS2 (high).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interrupts(pub u16);
+
+impl Interrupts {
+    pub const VOUT_LOW: u16 = 1 << 0;
+    pub const VBAT_LOW: u16 = 1 << 1;
+    pub const PWRON: u16 = 1 << 2;
+    pub const PWRON_LONG_PRESS: u16 = 1 << 3;
+    pub const HOTDIE: u16 = 1 << 4;
+    pub const RTC_ALARM: u16 = 1 << 5;
+    pub const RTC_PERIOD: u16 = 1 << 6;
+    pub const PLUG_IN: u16 = 1 << 8;
+    pub const PLUG_OUT: u16 = 1 << 9;
+
+    pub fn contains(&self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
+}
+
+/// Snapshot of the PMIC's monitoring registers.
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    /// bit per BUCK, set if it's under voltage
+    pub dcdc_undervoltage: u8,
+    /// bit per LDO, set if it's under voltage
+    pub ldo_undervoltage: u8,
+    /// bit per BUCK, set once it's in regulation
+    pub dcdc_power_good: u8,
+    /// bit per LDO, set once it's in regulation
+    pub ldo_power_good: u8,
+    /// raw VB_MON: battery voltage comparator and thresholds
+    pub vb_mon: u8,
+    /// raw DEVCTRL
+    pub devctrl: u8,
+}
+
+/// An RK808 PMIC.
+pub struct RK808<T>
+where
+    T: I2CTrait,
+{
+    bus: T,
+    address: u8,
+}
+
+impl<T> RK808<T>
+where
+    T: I2CTrait,
+{
+    pub fn new(bus: T) -> RK808<T> {
+        RK808 {
+            bus: bus,
+            address: RK808_ADDRESS,
+        }
+    }
+
+    /// Give back the underlying bus.
+    pub fn free(self) -> T {
+        self.bus
+    }
+
+    /// A handle on one of the PMIC's outputs.
+    pub fn regulator(&self, rail: Rail) -> RK808Regulator<T> {
+        RK808Regulator {
+            pmic: self,
+            rail: rail,
+        }
+    }
+
+    pub fn status(&self) -> regulator::Result<Status> {
+        Ok(Status {
+            dcdc_undervoltage: self.read_reg(DCDC_UV_STS)?,
+            ldo_undervoltage: self.read_reg(LDO_UV_STS)?,
+            dcdc_power_good: self.read_reg(DCDC_PG)?,
+            ldo_power_good: self.read_reg(LDO_PG)?,
+            vb_mon: self.read_reg(VB_MON)?,
+            devctrl: self.read_reg(DEVCTRL)?,
+        })
+    }
+
+    pub fn interrupts(&self) -> regulator::Result<Interrupts> {
+        let low = self.read_reg(INT_STS1)?;
+        let high = self.read_reg(INT_STS2)?;
+
+        Ok(Interrupts(low as u16 | (high as u16) << 8))
+    }
+
+    /// Acknowledge pending interrupts (write 1 to clear).
+    pub fn clear_interrupts(&self, irqs: Interrupts) -> regulator::Result<()> {
+        self.write_reg(INT_STS1, irqs.0 as u8)?;
+        self.write_reg(INT_STS2, (irqs.0 >> 8) as u8)?;
+        Ok(())
+    }
+
+    /// Mask interrupts; a set bit stops that source from asserting the
+    /// PMIC's interrupt line.
+    pub fn set_interrupt_mask(&self, mask: Interrupts) -> regulator::Result<()> {
+        self.write_reg(INT_STS_MSK1, mask.0 as u8)?;
+        self.write_reg(INT_STS_MSK2, (mask.0 >> 8) as u8)?;
+        Ok(())
+    }
+
+    fn read_reg(&self, reg: u8) -> i2c::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.bus.read_from(self.address, Some(reg), &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_regs(&self, reg: u8, buf: &mut [u8]) -> i2c::Result<()> {
+        self.bus.read_from(self.address, Some(reg), buf)?;
+        Ok(())
+    }
+
+    fn write_reg(&self, reg: u8, value: u8) -> i2c::Result<()> {
+        self.bus.write_to(self.address, Some(reg), &[value])?;
+        Ok(())
+    }
+
+    fn write_regs(&self, reg: u8, data: &[u8]) -> i2c::Result<()> {
+        self.bus.write_to(self.address, Some(reg), data)?;
+        Ok(())
+    }
+
+    fn update_reg(&self, reg: u8, mask: u8, value: u8) -> i2c::Result<()> {
+        let old = self.read_reg(reg)?;
+        let new = (old & !mask) | (value & mask);
+
+        if new != old {
+            self.write_reg(reg, new)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One output of an `RK808`.
+pub struct RK808Regulator<'p, T>
+where
+    T: 'p + I2CTrait,
+{
+    pmic: &'p RK808<T>,
+    rail: Rail,
+}
+
+impl<'p, T> RK808Regulator<'p, T>
+where
+    T: 'p + I2CTrait,
+{
+    pub fn rail(&self) -> Rail {
+        self.rail
+    }
+
+    fn selector(&self, reg: u8, mask: u8) -> regulator::Result<u8> {
+        Ok(self.pmic.read_reg(reg)? & mask)
+    }
+}
+
+impl<'p, T> Regulator for RK808Regulator<'p, T>
+where
+    T: 'p + I2CTrait,
+{
+    fn voltage(&self) -> regulator::Result<u32> {
+        let desc = self.rail.desc();
+        let reg = desc.vsel_reg.ok_or(Error::Unsupported)?;
+
+        let sel = self.selector(reg, desc.vsel_mask)?;
+        regulator::ranges_voltage(desc.ranges, sel).ok_or(Error::OutOfRange)
+    }
+
+    fn set_voltage(&self, uv: u32) -> regulator::Result<()> {
+        let desc = self.rail.desc();
+        let reg = desc.vsel_reg.ok_or(Error::Unsupported)?;
+
+        let target = regulator::ranges_selector(desc.ranges, uv).ok_or(Error::OutOfRange)?;
+
+        match self.rail {
+            Rail::Buck1 | Rail::Buck2 => {
+                let mut sel = self.selector(reg, desc.vsel_mask)?;
+
+                while sel != target {
+                    sel = if target > sel {
+                        if target - sel > BUCK1_2_MAX_STEPS { sel + BUCK1_2_MAX_STEPS } else { target }
+                    } else {
+                        if sel - target > BUCK1_2_MAX_STEPS { sel - BUCK1_2_MAX_STEPS } else { target }
+                    };
+
+                    self.pmic.update_reg(reg, desc.vsel_mask, sel)?;
+                }
+
+                Ok(())
+            },
+
+            _ => Ok(self.pmic.update_reg(reg, desc.vsel_mask, target)?),
+        }
+    }
+
+    fn is_enabled(&self) -> regulator::Result<bool> {
+        let desc = self.rail.desc();
+        Ok(self.pmic.read_reg(desc.enable_reg)? & (1 << desc.enable_bit) != 0)
+    }
+
+    fn set_enabled(&self, enabled: bool) -> regulator::Result<()> {
+        let desc = self.rail.desc();
+        let bit = 1 << desc.enable_bit;
+
+        Ok(self.pmic.update_reg(desc.enable_reg, bit, if enabled { bit } else { 0 })?)
+    }
+}
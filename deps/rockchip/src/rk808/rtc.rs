@@ -0,0 +1,251 @@
+use i2c::I2CTrait;
+use rtc::{self, bcd_to_bin, bin_to_bcd, DateTime, Error, Rtc};
+
+use super::RK808;
+
+const SECONDS: u8 = 0x00;
+const ALARM_SECONDS: u8 = 0x08;
+const RTC_CTRL: u8 = 0x10;
+const RTC_STATUS: u8 = 0x11;
+const RTC_INT: u8 = 0x12;
+
+const RTC_CTRL_STOP: u8 = 1 << 0;
+const RTC_CTRL_GET_TIME: u8 = 1 << 6;
+
+const RTC_STATUS_ALARM: u8 = 1 << 6;
+
+const RTC_INT_ALARM: u8 = 1 << 3;
+
+// seconds, minutes, hours, days, months, years, weeks
+const NUM_TIME_REGS: usize = 7;
+// as above, minus weeks
+const NUM_ALARM_REGS: usize = 6;
+
+// years are stored as 2 BCD digits
+const YEAR_BASE: u16 = 2000;
+
+// the RK808 thinks November has 31 days. rather than keep that fiction
+// going, convert to and from real dates by counting the bogus November
+// 31sts since a reference point where we assume the RTC was correct
+// (the same one the vendor kernel uses: 2016-01-01).
+const QUIRK_REFERENCE_YEAR: i64 = 2016;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// number of Nov 31sts between the reference point and `tm`
+fn nov2dec_transitions(tm: &DateTime) -> i64 {
+    tm.year as i64 - QUIRK_REFERENCE_YEAR + if tm.month > 11 { 1 } else { 0 }
+}
+
+fn rockchip_to_gregorian(tm: &DateTime) -> DateTime {
+    // to_unix copes with Nov 31st, treating it as Dec 1st
+    DateTime::from_unix(tm.to_unix() + nov2dec_transitions(tm) * SECONDS_PER_DAY)
+}
+
+fn gregorian_to_rockchip(tm: &DateTime) -> DateTime {
+    let extra_days = nov2dec_transitions(tm);
+    let time = tm.to_unix();
+
+    let mut shifted = DateTime::from_unix(time - extra_days * SECONDS_PER_DAY);
+
+    // compensate if we went back over a Nov 31st
+    if nov2dec_transitions(&shifted) < extra_days {
+        if shifted.month == 11 {
+            // this may well give the 31st
+            shifted.day += 1;
+        } else {
+            shifted = DateTime::from_unix(time - (extra_days - 1) * SECONDS_PER_DAY);
+        }
+    }
+
+    shifted
+}
+
+// seconds, minutes, hours, day, month, year, in BCD
+fn decode(regs: &[u8]) -> rtc::Result<DateTime> {
+    let tm = DateTime {
+        second: bcd_to_bin(regs[0] & 0x7f),
+        minute: bcd_to_bin(regs[1] & 0x7f),
+        hour: bcd_to_bin(regs[2] & 0x3f),
+        day: bcd_to_bin(regs[3] & 0x3f),
+        month: bcd_to_bin(regs[4] & 0x1f),
+        year: bcd_to_bin(regs[5]) as u16 + YEAR_BASE,
+    };
+
+    // it's fine for the raw registers to say Nov 31st
+    let quirk_ok = tm.month == 11 && tm.day == 31;
+    if !tm.is_valid() && !quirk_ok {
+        return Err(Error::InvalidDateTime);
+    }
+
+    Ok(rockchip_to_gregorian(&tm))
+}
+
+fn encode(tm: &DateTime, regs: &mut [u8]) -> rtc::Result<()> {
+    if !tm.is_valid() || tm.year < YEAR_BASE || tm.year > YEAR_BASE + 99 {
+        return Err(Error::InvalidDateTime);
+    }
+
+    let rk = gregorian_to_rockchip(tm);
+
+    regs[0] = bin_to_bcd(rk.second);
+    regs[1] = bin_to_bcd(rk.minute);
+    regs[2] = bin_to_bcd(rk.hour);
+    regs[3] = bin_to_bcd(rk.day);
+    regs[4] = bin_to_bcd(rk.month);
+    regs[5] = bin_to_bcd((rk.year - YEAR_BASE) as u8);
+
+    Ok(())
+}
+
+/// The real-time clock inside an RK808.
+///
+/// Runs in 24 hour mode; all times in and out are real Gregorian dates.
+pub struct RK808Rtc<'p, T>
+where
+    T: 'p + I2CTrait,
+{
+    pmic: &'p RK808<T>,
+}
+
+impl<T> RK808<T>
+where
+    T: I2CTrait,
+{
+    pub fn rtc(&self) -> RK808Rtc<T> {
+        RK808Rtc { pmic: self }
+    }
+}
+
+impl<'p, T> RK808Rtc<'p, T>
+where
+    T: 'p + I2CTrait,
+{
+    /// Program an alarm, and whether it should raise the RTC_ALARM interrupt.
+    pub fn set_alarm(&self, time: &DateTime, enabled: bool) -> rtc::Result<()> {
+        let mut regs = [0u8; NUM_ALARM_REGS];
+        encode(time, &mut regs)?;
+
+        // don't let it fire half-written
+        self.pmic.update_reg(RTC_INT, RTC_INT_ALARM, 0)?;
+        self.pmic.write_regs(ALARM_SECONDS, &regs)?;
+
+        if enabled {
+            self.clear_alarm()?;
+            self.pmic.update_reg(RTC_INT, RTC_INT_ALARM, RTC_INT_ALARM)?;
+        }
+
+        Ok(())
+    }
+
+    /// Currently programmed alarm, and whether it's enabled.
+    pub fn alarm(&self) -> rtc::Result<(DateTime, bool)> {
+        let mut regs = [0u8; NUM_ALARM_REGS];
+        self.pmic.read_regs(ALARM_SECONDS, &mut regs)?;
+
+        let enabled = self.pmic.read_reg(RTC_INT)? & RTC_INT_ALARM != 0;
+
+        Ok((decode(&regs)?, enabled))
+    }
+
+    pub fn alarm_pending(&self) -> rtc::Result<bool> {
+        Ok(self.pmic.read_reg(RTC_STATUS)? & RTC_STATUS_ALARM != 0)
+    }
+
+    /// Acknowledge a fired alarm (write 1 to clear).
+    pub fn clear_alarm(&self) -> rtc::Result<()> {
+        self.pmic.write_reg(RTC_STATUS, RTC_STATUS_ALARM)?;
+        Ok(())
+    }
+}
+
+impl<'p, T> Rtc for RK808Rtc<'p, T>
+where
+    T: 'p + I2CTrait,
+{
+    fn now(&self) -> rtc::Result<DateTime> {
+        // a rising edge on GET_TIME latches the counters into the shadow
+        // registers we read from. it takes a 32kHz cycle (~31us) to happen,
+        // but the I2C write clearing the bit takes longer than that anyway.
+        self.pmic.update_reg(RTC_CTRL, RTC_CTRL_GET_TIME, RTC_CTRL_GET_TIME)?;
+        self.pmic.update_reg(RTC_CTRL, RTC_CTRL_GET_TIME, 0)?;
+
+        let mut regs = [0u8; NUM_TIME_REGS];
+        self.pmic.read_regs(SECONDS, &mut regs)?;
+
+        decode(&regs)
+    }
+
+    fn set_time(&self, time: &DateTime) -> rtc::Result<()> {
+        let mut regs = [0u8; NUM_TIME_REGS];
+        encode(time, &mut regs)?;
+        regs[6] = bin_to_bcd(time.weekday());
+
+        // stop the counters while we write, so nothing carries half way
+        self.pmic.update_reg(RTC_CTRL, RTC_CTRL_STOP, RTC_CTRL_STOP)?;
+        self.pmic.write_regs(SECONDS, &regs)?;
+        self.pmic.update_reg(RTC_CTRL, RTC_CTRL_STOP, 0)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { year: year, month: month, day: day, hour: hour, minute: minute, second: second }
+    }
+
+    #[test]
+    fn nov31() {
+        // none before the first one, at the end of November 2016
+        assert_eq!(nov2dec_transitions(&dt(2016, 11, 30, 0, 0, 0)), 0);
+        assert_eq!(nov2dec_transitions(&dt(2016, 12, 1, 0, 0, 0)), 1);
+        assert_eq!(nov2dec_transitions(&dt(2017, 1, 1, 0, 0, 0)), 1);
+        assert_eq!(nov2dec_transitions(&dt(2017, 12, 1, 0, 0, 0)), 2);
+
+        // the RTC's Nov 31st is really Dec 1st, and it runs a day behind
+        // from then on, through the new year
+        let pairs = [
+            (dt(2016, 11, 30, 12, 0, 0), dt(2016, 11, 30, 12, 0, 0)),
+            (dt(2016, 11, 31, 12, 0, 0), dt(2016, 12, 1, 12, 0, 0)),
+            (dt(2016, 12, 1, 12, 0, 0), dt(2016, 12, 2, 12, 0, 0)),
+            (dt(2016, 12, 31, 23, 59, 59), dt(2017, 1, 1, 23, 59, 59)),
+            (dt(2017, 1, 1, 0, 0, 0), dt(2017, 1, 2, 0, 0, 0)),
+            (dt(2017, 11, 31, 12, 0, 0), dt(2017, 12, 2, 12, 0, 0)),
+        ];
+
+        for &(rk, real) in pairs.iter() {
+            assert_eq!(rockchip_to_gregorian(&rk), real);
+            assert_eq!(gregorian_to_rockchip(&real), rk);
+        }
+    }
+
+    #[test]
+    fn registers() {
+        let mut regs = [0u8; NUM_ALARM_REGS];
+
+        // New Year's Day 2017 is Dec 31st 2016 to the RTC
+        encode(&dt(2017, 1, 1, 12, 34, 56), &mut regs).unwrap();
+        assert_eq!(regs, [0x56, 0x34, 0x12, 0x31, 0x12, 0x16]);
+        assert_eq!(decode(&regs).unwrap(), dt(2017, 1, 1, 12, 34, 56));
+
+        // and it's happy to say Nov 31st
+        encode(&dt(2016, 12, 1, 0, 0, 0), &mut regs).unwrap();
+        assert_eq!(regs, [0x00, 0x00, 0x00, 0x31, 0x11, 0x16]);
+        assert_eq!(decode(&regs).unwrap(), dt(2016, 12, 1, 0, 0, 0));
+
+        // but not any other day that doesn't exist
+        match decode(&[0x00, 0x00, 0x00, 0x31, 0x09, 0x16]) {
+            Err(Error::InvalidDateTime) => (),
+            r => panic!("{:?}", r),
+        }
+
+        match encode(&dt(1999, 12, 31, 0, 0, 0), &mut regs) {
+            Err(Error::InvalidDateTime) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+}
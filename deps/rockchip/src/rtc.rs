@@ -0,0 +1,149 @@
+use nb;
+
+use core::fmt;
+
+use i2c::I2CError;
+
+/// An error from a real-time clock
+#[derive(Debug)]
+pub enum Error {
+    /// Date/time read from, or given to, the RTC isn't a real one
+    InvalidDateTime,
+
+    /// Talking to the RTC over I2C failed
+    Bus(I2CError),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<nb::Error<I2CError>> for Error {
+    fn from(e: nb::Error<I2CError>) -> Error {
+        match e {
+            nb::Error::Other(e) => Error::Bus(e),
+            nb::Error::WouldBlock => Error::Bus(I2CError::Timeout),
+        }
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Wall-clock time, proleptic Gregorian calendar, no timezone (UTC by
+/// convention).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 - 12
+    pub month: u8,
+    /// 1 - 31
+    pub day: u8,
+    /// 0 - 23
+    pub hour: u8,
+    /// 0 - 59
+    pub minute: u8,
+    /// 0 - 59
+    pub second: u8,
+}
+
+// days since 1970-01-01 for a y/m/d; happily takes out-of-range days
+// (eg. November 31st is December 1st)
+//
+// see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+impl DateTime {
+    pub fn is_valid(&self) -> bool {
+        self.month >= 1 && self.month <= 12 &&
+            self.day >= 1 && self.day <= days_in_month(self.year, self.month) &&
+            self.hour < 24 && self.minute < 60 && self.second < 60
+    }
+
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year as i64, self.month, self.day) * SECONDS_PER_DAY +
+            self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    pub fn from_unix(secs: i64) -> DateTime {
+        let days = if secs >= 0 { secs } else { secs - (SECONDS_PER_DAY - 1) } / SECONDS_PER_DAY;
+        let rem = secs - days * SECONDS_PER_DAY;
+
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            year: year as u16,
+            month: month,
+            day: day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// Day of the week, 0 = Sunday.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        ((days % 7 + 7 + 4) % 7) as u8
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Something that keeps wall-clock time.
+pub trait Rtc {
+    fn now(&self) -> Result<DateTime>;
+    fn set_time(&self, time: &DateTime) -> Result<()>;
+}
+
+pub fn bcd_to_bin(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0xf)
+}
+
+pub fn bin_to_bcd(bin: u8) -> u8 {
+    (bin / 10) << 4 | bin % 10
+}
@@ -6,6 +6,9 @@ use core::ops::Deref;
 use core::ptr;
 use core::fmt;
 
+use clock::{Clock, ClockConsumer, ClockError, ClockHandle, ClockManager, ClockRegisters, Kind, Veto};
+use delay::{self, Timeout};
+
 #[cfg(target_arch = "aarch64")]
 use rk3399_tools::{UART0, UART1, UART2, UART3, UART4, uart0};
 
@@ -15,14 +18,16 @@ use rk3399_m0::{UART0, UART1, UART2, UART3, UART4, uart0};
 pub type Result<T> = ::core::result::Result<T, nb::Error<Error>>;
 
 pub unsafe trait Usart: Deref<Target = uart0::RegisterBlock> {
-
+    /// name of the clock the UART's baud rate generator runs from; a mux
+    /// between the integer divider, the fractional divider and 24MHz
+    const CLOCK: &'static str;
 }
 
-unsafe impl Usart for UART0 { }
-unsafe impl Usart for UART1 { }
-unsafe impl Usart for UART2 { }
-unsafe impl Usart for UART3 { }
-unsafe impl Usart for UART4 { }
+unsafe impl Usart for UART0 { const CLOCK: &'static str = "clk_uart0"; }
+unsafe impl Usart for UART1 { const CLOCK: &'static str = "clk_uart1"; }
+unsafe impl Usart for UART2 { const CLOCK: &'static str = "clk_uart2"; }
+unsafe impl Usart for UART3 { const CLOCK: &'static str = "clk_uart3"; }
+unsafe impl Usart for UART4 { const CLOCK: &'static str = "clk_uart4"; }
 
 /// An error
 #[derive(Debug)]
@@ -96,4 +101,255 @@ where
             Ok(())
         }
     }
-}
\ No newline at end of file
+}
+// divisor latch and LCR, as word offsets from RBR (DLL overlaps RBR, DLH
+// overlaps IER); none of them are generated
+const DLL: isize = 0;
+const DLH: isize = 1;
+const LCR: isize = 3;
+const LCR_DLAB: u32 = 1 << 7;
+
+const USR_BUSY: u32 = 1 << 0;
+const USR_TFE: u32 = 1 << 2;
+
+// long enough to drain a full 64-byte FIFO at 9600 baud
+const IDLE_TIMEOUT_US: u32 = 100_000;
+
+// how far off the configured baud rate we'll tolerate after a clock change;
+// the receiver samples mid-bit, so a couple of percent is safe
+const MAX_BAUD_ERROR_PERCENT: u32 = 2;
+
+impl<'a, U> Serial<'a, U>
+where
+    U: Any + Usart,
+{
+    /// A handle on the UART's clock, to enable before using it.
+    pub fn clock<'m, 'c, R>(&self, clocks: &'m ClockManager<'c, R>) -> ::core::result::Result<ClockHandle<'m, 'c, R>, ClockError>
+    where
+        R: ClockRegisters,
+    {
+        clocks.request(U::CLOCK)
+    }
+
+    /// Run the UART at `baud`, setting its clock up to hit it exactly where
+    /// possible: straight from 24MHz, then from the integer divider, then
+    /// from the fractional divider. Returns the baud rate actually set.
+    ///
+    /// Waits for the transmitter to drain first, and gives up with `Vetoed`
+    /// if it doesn't.
+    ///
+    /// The UART's own rate-change notifications try to hold on to the old
+    /// baud rate, so don't have it registered as a consumer of its clock
+    /// while calling this.
+    pub fn set_baud<'c, R>(&self, clocks: &ClockManager<'c, R>, baud: u32) -> ::core::result::Result<u32, ClockError>
+    where
+        R: ClockRegisters,
+    {
+        let uart = clocks.get(U::CLOCK)?;
+
+        self.wait_idle().map_err(|_| ClockError::Vetoed)?;
+
+        let rate = baud_clock(clocks, uart, baud)?;
+        let (div, achieved) = divisor_for(rate, baud)?;
+
+        self.set_divisor(div);
+        Ok(achieved)
+    }
+
+    fn reg(&self, offset: isize) -> *mut u32 {
+        unsafe { (&self.0.uart_rbr as *const _ as *mut u32).offset(offset) }
+    }
+
+    /// Current baud rate divisor; the UART runs at sclk / (16 * divisor).
+    pub fn divisor(&self) -> u32 {
+        unsafe {
+            let lcr = ptr::read_volatile(self.reg(LCR));
+            ptr::write_volatile(self.reg(LCR), lcr | LCR_DLAB);
+
+            let div = ptr::read_volatile(self.reg(DLL)) & 0xff |
+                      (ptr::read_volatile(self.reg(DLH)) & 0xff) << 8;
+
+            ptr::write_volatile(self.reg(LCR), lcr);
+            div
+        }
+    }
+
+    /// Set the baud rate divisor. The UART must be idle; the divisor latch
+    /// can't be written while it's busy.
+    pub fn set_divisor(&self, div: u16) {
+        unsafe {
+            let lcr = ptr::read_volatile(self.reg(LCR));
+            ptr::write_volatile(self.reg(LCR), lcr | LCR_DLAB);
+
+            ptr::write_volatile(self.reg(DLL), div as u32 & 0xff);
+            ptr::write_volatile(self.reg(DLH), div as u32 >> 8);
+
+            ptr::write_volatile(self.reg(LCR), lcr);
+        }
+    }
+
+    // wait for the transmitter to drain, so a rate change doesn't garble
+    // whatever's in flight; a UART that stays busy (held off by flow
+    // control, say) won't take a new divisor anyway
+    fn wait_idle(&self) -> ::core::result::Result<(), Veto> {
+        let timeout = Timeout::new(delay::system_timer(), IDLE_TIMEOUT_US);
+        while !timeout.expired() {
+            let usr = self.0.uart_usr.read().bits();
+            if usr & USR_TFE != 0 && usr & USR_BUSY == 0 {
+                return Ok(());
+            }
+        }
+
+        Err(Veto)
+    }
+}
+
+// set a UART's clock up for `baud`: straight from 24MHz if that divides
+// down exactly, then an exact integer division of CPLL or GPLL, then the
+// fractional divider. Returns the rate the UART's clock ends up at.
+fn baud_clock<'c, R>(clocks: &ClockManager<'c, R>, uart: Clock, baud: u32) -> ::core::result::Result<u32, ClockError>
+where
+    R: ClockRegisters,
+{
+    if baud == 0 {
+        return Err(ClockError::InvalidRate);
+    }
+
+    let sclk = baud.checked_mul(16).ok_or(ClockError::InvalidRate)?;
+
+    let (div, frac, xin) = match uart.node().kind {
+        Kind::Mux { parents, .. } if parents.len() == 3 => (parents[0], parents[1], parents[2]),
+        _ => return Err(ClockError::Unsupported),
+    };
+
+    // 24MHz divides down exactly for the classic rates and 1.5M
+    let xin_rate = clocks.rate(xin);
+    if xin_rate % sclk == 0 && xin_rate / sclk <= 0xffff {
+        clocks.set_parent(uart, xin)?;
+        return Ok(xin_rate);
+    }
+
+    if clocks.set_rate(div, sclk)? == sclk {
+        clocks.set_parent(uart, div)?;
+        return Ok(sclk);
+    }
+
+    // otherwise the fractional divider, fed as fast as it'll go so it has
+    // the most room to work with; the integer divider takes the largest
+    // rate it's asked for as "undivided, from the fastest input"
+    clocks.set_rate(div, u32::max_value())?;
+    let rate = clocks.set_rate(frac, sclk)?;
+    clocks.set_parent(uart, frac)?;
+
+    Ok(rate)
+}
+
+// divisor closest to `baud` with the UART clock at `rate`, and the baud
+// rate it gives
+fn divisor_for(rate: u32, baud: u32) -> ::core::result::Result<(u16, u32), ClockError> {
+    if baud == 0 {
+        return Err(ClockError::InvalidRate);
+    }
+
+    let div = (rate as u64 + 8 * baud as u64) / (16 * baud as u64);
+    if div == 0 || div > 0xffff {
+        return Err(ClockError::InvalidRate);
+    }
+
+    let achieved = (rate as u64 / (16 * div)) as u32;
+    let error = if achieved > baud { achieved - baud } else { baud - achieved };
+
+    if error as u64 * 100 > baud as u64 * MAX_BAUD_ERROR_PERCENT as u64 {
+        return Err(ClockError::InvalidRate);
+    }
+
+    Ok((div as u16, achieved))
+}
+
+// divisor keeping the UART at the baud rate it runs at from `old`, once
+// the clock is at `new`
+fn rescaled_divisor(old: u32, new: u32, div: u32) -> Option<u16> {
+    if div == 0 {
+        return None;
+    }
+
+    divisor_for(new, old / (16 * div)).ok().map(|(div, _)| div)
+}
+
+impl<'a, U> ClockConsumer for Serial<'a, U>
+where
+    U: Any + Usart,
+{
+    fn pre_rate_change(&self, _clk: Clock, old: u32, new: u32) -> ::core::result::Result<(), Veto> {
+        match rescaled_divisor(old, new, self.divisor()) {
+            Some(_) => self.wait_idle(),
+            None => Err(Veto),
+        }
+    }
+
+    fn post_rate_change(&self, _clk: Clock, old: u32, new: u32) {
+        if let Some(div) = rescaled_divisor(old, new, self.divisor()) {
+            self.set_divisor(div);
+        }
+    }
+
+    fn abort_rate_change(&self, _clk: Clock, _old: u32, _new: u32) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::Reg;
+    use fake::FakeRegisters;
+    use super::*;
+
+    fn manager(regs: &FakeRegisters<Reg>) -> ClockManager<'static, &FakeRegisters<Reg>> {
+        let clocks = ClockManager::new(regs);
+
+        for &(clk, rate) in [(Clock::Cpll, 1_000_000_000), (Clock::Gpll, 800_000_000)].iter() {
+            assert_eq!(clocks.pll(clk).unwrap().set_rate(rate).unwrap(), rate);
+        }
+
+        clocks
+    }
+
+    #[test]
+    fn baud_from_24m() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        assert_eq!(baud_clock(&clocks, Clock::ClkUart2, 1_500_000).unwrap(), 24_000_000);
+        assert_eq!(clocks.parent(Clock::ClkUart2), Some(Clock::Xin24m));
+        assert_eq!(divisor_for(24_000_000, 115_200).unwrap(), (13, 115_384));
+    }
+
+    #[test]
+    fn baud_from_fractional_divider() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        // 921600 * 16 divides neither 24MHz nor either PLL
+        let rate = baud_clock(&clocks, Clock::ClkUart2, 921_600).unwrap();
+
+        assert_eq!(clocks.parent(Clock::ClkUart2), Some(Clock::ClkUart2Frac));
+        assert_eq!(clocks.rate(Clock::ClkUart2Div), 1_000_000_000);
+        // as close as 16-bit terms get to 14.7456MHz from 1GHz
+        assert_eq!(rate, 14_745_599);
+        assert_eq!(divisor_for(rate, 921_600).unwrap(), (1, 921_599));
+    }
+
+    #[test]
+    fn zero_baud() {
+        let regs = FakeRegisters::new();
+        let clocks = manager(&regs);
+
+        match baud_clock(&clocks, Clock::ClkUart2, 0) {
+            Err(ClockError::InvalidRate) => (),
+            r => panic!("{:?}", r),
+        }
+        match divisor_for(24_000_000, 0) {
+            Err(ClockError::InvalidRate) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+// Secure GRF: which bus masters are secure, and which slaves only answer to
+// secure masters.
+//
+// Everything here lives in the PMUSGRF block, and is only reachable from the
+// AP in secure state. Register offsets follow ARM Trusted Firmware's
+// rk3399 secure.h:
+//
+//   0xc100 + n*4  PMU_CON n          bit 7: PMU M0 master control
+//   0xc240 + n*4  PMU_SLV_CON n      bit 1: CRYPTO1 secure
+//   0xe00c + n*4  SOC_CON 3-7        5-7 are per-master controls
+//   0x8020 + n*4  SOC_CON 8-15
+//   0xe3c0 + n*4  SLV_SECURE_CON 0-4 per-slave controls
+//
+// All of them take a write mask in the top 16 bits. Master bits are set for
+// non-secure; slave bits are set for secure.
+
+use core::fmt;
+#[cfg(target_arch = "aarch64")]
+use core::ptr;
+
+#[cfg(target_arch = "aarch64")]
+use rk3399_tools::PMUSGRF;
+
+/// Whether a master's transactions are secure, or whether a slave insists
+/// on them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Security {
+    Secure,
+    NonSecure,
+
+    /// A master with more than one control, not all of which agree
+    Mixed,
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Security::Secure => "secure",
+            Security::NonSecure => "non-secure",
+            Security::Mixed => "mixed",
+        })
+    }
+}
+
+/// A PMUSGRF register. Only ones that exist can be named: the constants
+/// below, or `soc_con` and `slv_secure_con`, which check the number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reg {
+    group: Group,
+    n: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Group {
+    PmuCon,
+    PmuSlvCon,
+    SocCon,
+    SlvSecureCon,
+}
+
+impl Reg {
+    pub const PMU_CON0: Reg = Reg { group: Group::PmuCon, n: 0 };
+    pub const PMU_SLV_CON0: Reg = Reg { group: Group::PmuSlvCon, n: 0 };
+    pub const SOC_CON5: Reg = Reg { group: Group::SocCon, n: 5 };
+    pub const SOC_CON6: Reg = Reg { group: Group::SocCon, n: 6 };
+    pub const SOC_CON7: Reg = Reg { group: Group::SocCon, n: 7 };
+    pub const SOC_CON9: Reg = Reg { group: Group::SocCon, n: 9 };
+    pub const SOC_CON10: Reg = Reg { group: Group::SocCon, n: 10 };
+
+    /// SOC_CON3 to SOC_CON15; the others aren't in this block.
+    pub fn soc_con(n: u8) -> Option<Reg> {
+        if n >= 3 && n <= 15 { Some(Reg { group: Group::SocCon, n: n }) } else { None }
+    }
+
+    /// SLV_SECURE_CON0 to SLV_SECURE_CON4.
+    pub fn slv_secure_con(n: u8) -> Option<Reg> {
+        if n <= 4 { Some(Reg { group: Group::SlvSecureCon, n: n }) } else { None }
+    }
+
+    fn offset(&self) -> isize {
+        let n = self.n as isize;
+
+        match self.group {
+            Group::PmuCon => 0xc100 + n * 4,
+            Group::PmuSlvCon => 0xc240 + n * 4,
+            Group::SocCon if n >= 8 => 0x8020 + (n - 8) * 4,
+            Group::SocCon => 0xe00c + (n - 3) * 4,
+            Group::SlvSecureCon => 0xe3c0 + n * 4,
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.group {
+            Group::PmuCon => "pmu_con",
+            Group::PmuSlvCon => "pmu_slv_con",
+            Group::SocCon => "soc_con",
+            Group::SlvSecureCon => "slv_secure_con",
+        };
+
+        write!(f, "{}{}", name, self.n)
+    }
+}
+
+/// Access to the PMUSGRF, by register. Every register here has a write
+/// mask; `write` passes it through as given, `write_field` builds it.
+pub trait SgrfRegisters {
+    fn read(&self, reg: Reg) -> u32;
+    fn write(&self, reg: Reg, value: u32);
+
+    /// Update a field, through the register's write mask.
+    fn write_field(&self, reg: Reg, shift: u8, width: u8, value: u32) {
+        let mask = ((1 << width) - 1) << shift;
+        self.write(reg, mask << 16 | (value << shift) & mask);
+    }
+}
+
+/// The real PMUSGRF.
+#[cfg(target_arch = "aarch64")]
+pub struct Mmio;
+
+#[cfg(target_arch = "aarch64")]
+impl Mmio {
+    fn address(&self, reg: Reg) -> *mut u32 {
+        unsafe { (PMUSGRF.get() as *mut u8).offset(reg.offset()) as *mut u32 }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl SgrfRegisters for Mmio {
+    // the SVD has typed accessors for these; for the rest, go by offset
+    fn read(&self, reg: Reg) -> u32 {
+        let pmusgrf = unsafe { &*PMUSGRF.get() };
+
+        match reg {
+            Reg::PMU_CON0 => pmusgrf.pmu_con0.read().bits(),
+            Reg::SOC_CON6 => pmusgrf.soc_con6.read().bits(),
+            _ => unsafe { ptr::read_volatile(self.address(reg)) },
+        }
+    }
+
+    fn write(&self, reg: Reg, value: u32) {
+        let pmusgrf = unsafe { &*PMUSGRF.get() };
+
+        match reg {
+            Reg::PMU_CON0 => pmusgrf.pmu_con0.write(|w| unsafe { w.bits(value) }),
+            Reg::SOC_CON6 => pmusgrf.soc_con6.write(|w| unsafe { w.bits(value) }),
+            _ => unsafe { ptr::write_volatile(self.address(reg), value) },
+        }
+    }
+}
+
+/// One bit deciding the security of a master or slave.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Control {
+    pub reg: Reg,
+    pub bit: u8,
+
+    /// whether setting the bit makes it secure
+    pub secure_when_set: bool,
+}
+
+macro_rules! master {
+    ($reg:expr, $bit:expr) => (Control { reg: $reg, bit: $bit, secure_when_set: false });
+}
+
+macro_rules! slave {
+    ($reg:expr, $bit:expr) => (Control { reg: $reg, bit: $bit, secure_when_set: true });
+}
+
+/// A bus master whose security we care about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Master {
+    /// Cortex-M0 in the PMU
+    PmuM0,
+
+    /// Cortex-M0 in PERILP
+    PerilpM0,
+}
+
+pub static MASTERS: [Master; 2] = [Master::PmuM0, Master::PerilpM0];
+
+static PMU_M0: [Control; 2] = [
+    master!(Reg::PMU_CON0, 7),
+
+    // the TRM's secure master table has PERILP at [12] and the PMU at [13],
+    // but ATF's m0_init clears [12] for the PMU M0, and that's what's been
+    // seen to work
+    master!(Reg::SOC_CON6, 12),
+];
+
+static PERILP_M0: [Control; 1] = [
+    master!(Reg::SOC_CON6, 13),
+];
+
+impl Master {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Master::PmuM0 => "pmu_m0",
+            Master::PerilpM0 => "perilp_m0",
+        }
+    }
+
+    /// Every bit that has a say; all of them have to agree.
+    pub fn controls(&self) -> &'static [Control] {
+        match *self {
+            Master::PmuM0 => &PMU_M0,
+            Master::PerilpM0 => &PERILP_M0,
+        }
+    }
+}
+
+/// A slave that can be made secure-only.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slave {
+    /// Second crypto engine, in the PMU
+    Crypto1,
+
+    /// One of the slaves in SLV_SECURE_CON0-4; see `Slave::other`
+    Other(SlaveBit),
+}
+
+/// Where in SLV_SECURE_CON0-4 a slave's control is; only `Slave::other`
+/// makes these, so they're always in range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlaveBit {
+    con: u8,
+    bit: u8,
+}
+
+impl Slave {
+    /// The slave at `bit` of SLV_SECURE_CON`con`, by the TRM's secure slave
+    /// table; `None` unless `con` is 0-4 and `bit` 0-15.
+    pub fn other(con: u8, bit: u8) -> Option<Slave> {
+        if con <= 4 && bit < 16 {
+            Some(Slave::Other(SlaveBit { con: con, bit: bit }))
+        } else {
+            None
+        }
+    }
+
+    pub fn control(&self) -> Control {
+        match *self {
+            Slave::Crypto1 => slave!(Reg::PMU_SLV_CON0, 1),
+            Slave::Other(SlaveBit { con, bit }) => {
+                slave!(Reg { group: Group::SlvSecureCon, n: con }, bit)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Slave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Slave::Crypto1 => f.write_str("crypto1"),
+            Slave::Other(SlaveBit { con, bit }) => write!(f, "slv_secure_con{}[{}]", con, bit),
+        }
+    }
+}
+
+/// Which masters and slaves are secure, over the PMUSGRF `R`.
+pub struct Sgrf<R>
+where
+    R: SgrfRegisters,
+{
+    regs: R,
+}
+
+impl<R> Sgrf<R>
+where
+    R: SgrfRegisters,
+{
+    pub fn new(regs: R) -> Sgrf<R> {
+        Sgrf {
+            regs: regs,
+        }
+    }
+
+    fn is_secure(&self, control: &Control) -> bool {
+        let set = self.regs.read(control.reg) & (1 << control.bit) != 0;
+        set == control.secure_when_set
+    }
+
+    fn set(&self, control: &Control, security: Security) {
+        let set = (security == Security::Secure) == control.secure_when_set;
+        let bit = 1 << control.bit;
+
+        self.regs.write(control.reg, bit << 16 | if set { bit } else { 0 });
+    }
+
+    pub fn master(&self, master: Master) -> Security {
+        let controls = master.controls();
+        let secure = controls.iter().filter(|c| self.is_secure(c)).count();
+
+        if secure == controls.len() {
+            Security::Secure
+        } else if secure == 0 {
+            Security::NonSecure
+        } else {
+            Security::Mixed
+        }
+    }
+
+    /// Panics if asked for `Security::Mixed`.
+    pub fn set_master(&self, master: Master, security: Security) {
+        assert!(security != Security::Mixed, "a master can't be set to mixed security");
+
+        for control in master.controls() {
+            self.set(control, security);
+        }
+    }
+
+    pub fn slave(&self, slave: Slave) -> Security {
+        if self.is_secure(&slave.control()) {
+            Security::Secure
+        } else {
+            Security::NonSecure
+        }
+    }
+
+    /// Panics if asked for `Security::Mixed`.
+    pub fn set_slave(&self, slave: Slave, security: Security) {
+        assert!(security != Security::Mixed, "a slave can't be set to mixed security");
+
+        self.set(&slave.control(), security);
+    }
+
+    /// Read back the whole configuration.
+    pub fn config(&self) -> Config {
+        let mut masters = [Security::Secure; 2];
+        for (state, master) in masters.iter_mut().zip(MASTERS.iter()) {
+            *state = self.master(*master);
+        }
+
+        let mut slv_secure = [0; 5];
+        for (n, word) in slv_secure.iter_mut().enumerate() {
+            let reg = Reg { group: Group::SlvSecureCon, n: n as u8 };
+            *word = self.regs.read(reg) & 0xffff;
+        }
+
+        Config {
+            masters: masters,
+            crypto1: self.slave(Slave::Crypto1),
+            master_con: [
+                self.regs.read(Reg::SOC_CON5) & 0xffff,
+                self.regs.read(Reg::SOC_CON6) & 0xffff,
+                self.regs.read(Reg::SOC_CON7) & 0xffff,
+            ],
+            slv_secure: slv_secure,
+        }
+    }
+}
+
+/// A snapshot of the security configuration, from `Sgrf::config`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// in the order of `MASTERS`
+    pub masters: [Security; 2],
+    pub crypto1: Security,
+
+    /// SOC_CON5-7, where a set bit is a non-secure master
+    pub master_con: [u32; 3],
+
+    /// SLV_SECURE_CON0-4, where a set bit is a secure slave
+    pub slv_secure: [u32; 5],
+}
+
+impl Config {
+    pub fn master(&self, master: Master) -> Security {
+        let index = MASTERS.iter().position(|m| *m == master).unwrap();
+        self.masters[index]
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (master, state) in MASTERS.iter().zip(self.masters.iter()) {
+            writeln!(f, "master {:<10} {}", master.name(), state)?;
+        }
+
+        writeln!(f, "slave  {:<10} {}", "crypto1", self.crypto1)?;
+
+        for (reg, word) in [Reg::SOC_CON5, Reg::SOC_CON6, Reg::SOC_CON7].iter().zip(self.master_con.iter()) {
+            writeln!(f, "{}: 0x{:04x} (set: non-secure master)", reg, word)?;
+        }
+
+        for (n, word) in self.slv_secure.iter().enumerate() {
+            let reg = Reg { group: Group::SlvSecureCon, n: n as u8 };
+            writeln!(f, "{}: 0x{:04x} (set: secure slave)", reg, word)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::FakeRegisters;
+    use super::*;
+
+    impl<'a> SgrfRegisters for &'a FakeRegisters<Reg> {
+        fn read(&self, reg: Reg) -> u32 {
+            self.value(reg)
+        }
+
+        fn write(&self, reg: Reg, value: u32) {
+            self.store_masked(reg, value);
+        }
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(Reg::soc_con(2), None);
+        assert_eq!(Reg::soc_con(16), None);
+        assert_eq!(Reg::soc_con(3).unwrap().offset(), 0xe00c);
+        assert_eq!(Reg::soc_con(8).unwrap().offset(), 0x8020);
+        assert_eq!(Reg::SOC_CON10.offset(), 0x8028);
+
+        assert_eq!(Reg::slv_secure_con(5), None);
+        assert_eq!(Reg::slv_secure_con(4).unwrap().offset(), 0xe3d0);
+
+        assert_eq!(Slave::other(5, 0), None);
+        assert_eq!(Slave::other(0, 16), None);
+        assert_eq!(Slave::other(4, 15).unwrap().control().reg, Reg::slv_secure_con(4).unwrap());
+    }
+
+    #[test]
+    fn set_master() {
+        let regs = FakeRegisters::new();
+        let sgrf = Sgrf::new(&regs);
+
+        // both of the PMU M0's bits, and nobody else's
+        sgrf.set_master(Master::PmuM0, Security::NonSecure);
+        assert_eq!((&regs).read(Reg::PMU_CON0), 1 << 7);
+        assert_eq!((&regs).read(Reg::SOC_CON6), 1 << 12);
+        assert_eq!(sgrf.master(Master::PmuM0), Security::NonSecure);
+        assert_eq!(sgrf.master(Master::PerilpM0), Security::Secure);
+
+        sgrf.set_master(Master::PmuM0, Security::Secure);
+        assert_eq!((&regs).read(Reg::PMU_CON0), 0);
+        assert_eq!((&regs).read(Reg::SOC_CON6), 0);
+        assert_eq!(sgrf.master(Master::PmuM0), Security::Secure);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_master_mixed() {
+        let regs = FakeRegisters::new();
+        Sgrf::new(&regs).set_master(Master::PmuM0, Security::Mixed);
+    }
+
+    #[test]
+    fn mixed() {
+        let regs = FakeRegisters::new();
+        let sgrf = Sgrf::new(&regs);
+
+        // PMU_CON0 says non-secure, SOC_CON6 still says secure
+        (&regs).write_field(Reg::PMU_CON0, 7, 1, 1);
+        assert_eq!(sgrf.master(Master::PmuM0), Security::Mixed);
+        assert_eq!(sgrf.config().master(Master::PmuM0), Security::Mixed);
+    }
+
+    #[test]
+    fn slaves() {
+        let regs = FakeRegisters::new();
+        let sgrf = Sgrf::new(&regs);
+        let other = Slave::other(2, 3).unwrap();
+
+        sgrf.set_slave(Slave::Crypto1, Security::Secure);
+        sgrf.set_slave(other, Security::Secure);
+        assert_eq!((&regs).read(Reg::PMU_SLV_CON0), 1 << 1);
+        assert_eq!((&regs).read(Reg::slv_secure_con(2).unwrap()), 1 << 3);
+        assert_eq!(sgrf.slave(other), Security::Secure);
+
+        sgrf.set_slave(other, Security::NonSecure);
+        assert_eq!(sgrf.slave(other), Security::NonSecure);
+        assert_eq!(sgrf.slave(Slave::Crypto1), Security::Secure);
+    }
+
+    #[test]
+    fn config() {
+        let regs = FakeRegisters::new();
+        let sgrf = Sgrf::new(&regs);
+
+        sgrf.set_master(Master::PerilpM0, Security::NonSecure);
+        sgrf.set_slave(Slave::other(1, 4).unwrap(), Security::Secure);
+
+        let config = sgrf.config();
+        assert_eq!(config.master(Master::PmuM0), Security::Secure);
+        assert_eq!(config.master(Master::PerilpM0), Security::NonSecure);
+        assert_eq!(config.crypto1, Security::NonSecure);
+        assert_eq!(config.master_con, [0, 1 << 13, 0]);
+        assert_eq!(config.slv_secure, [0, 1 << 4, 0, 0, 0]);
+
+        let text = format!("{}", config);
+        assert!(text.contains("master perilp_m0  non-secure\n"));
+        assert!(text.contains("soc_con6: 0x2000 "));
+        assert!(text.contains("slv_secure_con1: 0x0010 "));
+    }
+}
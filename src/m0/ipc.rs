@@ -0,0 +1,122 @@
+// Talking to lilmemcap over the mailbox.
+//
+// Requests go out on the core's channel (see `Layout::channel`) and the
+// reply comes back on the same channel. The command word carries:
+//
+//   [31]     set on replies
+//   [30]     set on replies reporting a failure; data is lilmemcap's error
+//   [29:16]  sequence number, echoed in the reply
+//   [15:0]   command
+//
+// A reply that doesn't match the request outstanding, such as a late one
+// to a request that already timed out, is dropped.
+//
+// lilmemcap has to agree with everything in here.
+
+use rockchip::delay::{self, Timeout};
+use rockchip::mailbox::{Mailbox, Message};
+
+use nb;
+
+const REPLY: u32 = 1 << 31;
+const FAILED: u32 = 1 << 30;
+const SEQ_SHIFT: u32 = 16;
+const SEQ_MASK: u32 = 0x3fff;
+const COMMAND_MASK: u32 = 0xffff;
+
+// how long to wait for lilmemcap to pick a request up, and again to answer
+const REPLY_TIMEOUT_US: u32 = 100_000;
+
+/// Commands lilmemcap understands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Answers with the data it was sent
+    Ping = 1,
+
+    /// Answers with its firmware version
+    Version = 2,
+
+    /// Start logging to the ring at the address in data; see `m0::log`
+    SetLogBuffer = 3,
+
+    /// Start a heartbeat in the status block at the address in data; see
+    /// `m0::supervisor`
+    SetStatusBlock = 4,
+}
+
+/// An error from talking to lilmemcap
+#[derive(Debug)]
+pub enum IpcError {
+    /// lilmemcap never picked up the request, or never answered
+    Timeout,
+
+    /// lilmemcap couldn't do it; carries its error code
+    Failed(u32),
+
+    /// Buffer is somewhere the M0 can't see; carries its address
+    NotVisible(u32),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Request/reply link to lilmemcap.
+pub struct M0Link {
+    mailbox: Mailbox,
+    channel: usize,
+    seq: u32,
+}
+
+impl M0Link {
+    /// Talk to the core answering on `channel`.
+    pub fn new(mailbox: Mailbox, channel: usize) -> M0Link {
+        M0Link {
+            mailbox: mailbox,
+            channel: channel,
+            seq: 0,
+        }
+    }
+
+    /// Send `command` with `data`, and wait for lilmemcap's answer.
+    pub fn call(&mut self, command: Command, data: u32) -> Result<u32, IpcError> {
+        self.seq = (self.seq + 1) & SEQ_MASK;
+
+        let cmd = self.seq << SEQ_SHIFT | (command as u32 & COMMAND_MASK);
+        self.send(Message { cmd: cmd, data: data })?;
+
+        let reply = self.wait_reply(cmd)?;
+        if reply.cmd & FAILED != 0 {
+            return Err(IpcError::Failed(reply.data));
+        }
+
+        Ok(reply.data)
+    }
+
+    fn send(&self, msg: Message) -> Result<(), IpcError> {
+        let timeout = Timeout::new(delay::system_timer(), REPLY_TIMEOUT_US);
+        while !timeout.expired() {
+            match self.mailbox.send(self.channel, msg) {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::WouldBlock) => continue,
+                Err(nb::Error::Other(_)) => break,
+            }
+        }
+
+        Err(IpcError::Timeout)
+    }
+
+    // wait for the reply to `cmd`, dropping any others
+    fn wait_reply(&self, cmd: u32) -> Result<Message, IpcError> {
+        let timeout = Timeout::new(delay::system_timer(), REPLY_TIMEOUT_US);
+        while !timeout.expired() {
+            match self.mailbox.receive(self.channel) {
+                Ok(msg) if msg.cmd & !FAILED == cmd | REPLY => return Ok(msg),
+                Ok(_) => continue,
+                Err(nb::Error::WouldBlock) => continue,
+                Err(nb::Error::Other(_)) => break,
+            }
+        }
+
+        Err(IpcError::Timeout)
+    }
+}
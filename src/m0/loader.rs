@@ -0,0 +1,124 @@
+// Getting an M0 image into memory the core can see.
+//
+// The M0 boots from address 0 of its own address space, which the PMUSGRF
+// remaps onto main memory: pmu_remap_flash_rom_mid and _high take bits
+// [27:12] and [31:28] of the image's physical address, so images have to
+// start on a 4KiB boundary. The M0 doesn't snoop our caches either, so the
+// copy has to be cleaned out to DDR before it's let go.
+//
+// The remap is also the only way the M0 sees DDR at all: its address 0 is
+// the image's start, and the window runs to the end of its code region
+// (0x1fffffff, ARMv6-M's fixed memory map). Anything else we share with it
+// has to be above the image, and is handed over as an offset from it; see
+// `m0_address`.
+
+use core::ptr;
+use core::slice;
+
+use m0image;
+use rockchip::cache;
+
+use super::Layout;
+
+/// Images have to start on one of these.
+pub const REMAP_GRANULE: u32 = 4096;
+
+/// How much of DDR the M0 sees, from the image's start.
+pub const REMAP_WINDOW: u32 = 0x2000_0000;
+
+/// An error from loading an M0 image
+#[derive(Debug)]
+pub enum LoadError {
+    /// Nothing to load
+    Empty,
+
+    /// Load address isn't on a 4KiB boundary, so can't be remapped
+    Misaligned,
+
+    /// Image would run past the top of the 32-bit address space
+    TooLarge,
+
+    /// Image would run into the core's log buffer
+    Overlap,
+
+    /// Image header or checksum is wrong
+    BadImage(m0image::Error),
+
+    /// Image checked out, but didn't survive the copy into place
+    CopyCorrupt,
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Check an m0image container, and load its payload at the core's
+/// `Layout::load_address`, below its log buffer. The remap puts the vector
+/// table at the M0's address 0 wherever the image goes, so the header's
+/// load address is only where m0pack was told to put it, and is ignored.
+///
+/// Returns the address to hand to `M0::setup`; nothing should be started
+/// on an image this rejects.
+pub fn load(image: &[u8], layout: &Layout) -> Result<u32, LoadError> {
+    let (header, payload) = m0image::verify(image).map_err(LoadError::BadImage)?;
+    let dest = layout.load_address;
+
+    if (dest as u64) + (payload.len() as u64) > layout.log_address as u64 {
+        return Err(LoadError::Overlap);
+    }
+
+    let entry = dest.wrapping_add(header.vector_offset);
+    if entry % REMAP_GRANULE != 0 {
+        return Err(LoadError::Misaligned);
+    }
+
+    load_raw(payload, dest)?;
+
+    // check it again where the M0 will actually see it, having dropped
+    // our cached copy so the reads come from DDR
+    cache::clean_invalidate(dest as usize, payload.len());
+
+    let loaded = unsafe {
+        slice::from_raw_parts(dest as usize as *const u8, payload.len())
+    };
+
+    if m0image::crc32(loaded) != header.crc32 {
+        return Err(LoadError::CopyCorrupt);
+    }
+
+    Ok(entry)
+}
+
+/// Copy a bare binary to `dest` and make sure it's reached memory, ready
+/// for `M0::setup` to point the core at it. Returns `dest` for passing on.
+///
+/// `dest` has to be memory nothing else is using; nothing here can check.
+pub fn load_raw(image: &[u8], dest: u32) -> Result<u32, LoadError> {
+    if image.is_empty() {
+        return Err(LoadError::Empty);
+    }
+
+    if dest % REMAP_GRANULE != 0 {
+        return Err(LoadError::Misaligned);
+    }
+
+    if (dest as u64) + (image.len() as u64) > 1 << 32 {
+        return Err(LoadError::TooLarge);
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(image.as_ptr(), dest as usize as *mut u8, image.len());
+    }
+
+    cache::clean(dest as usize, image.len());
+
+    Ok(dest)
+}
+
+/// Where an M0 started at `start` sees physical address `addr`; `None` if
+/// it's outside the remap window.
+pub fn m0_address(addr: u32, start: u32) -> Option<u32> {
+    match addr.checked_sub(start) {
+        Some(offset) if offset < REMAP_WINDOW => Some(offset),
+        _ => None,
+    }
+}
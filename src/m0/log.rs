@@ -0,0 +1,34 @@
+// Printing what lilmemcap logs.
+
+use rockchip::m0log::{self, LogReader};
+use rockchip::ring::RingError;
+
+use super::Layout;
+
+/// How big each core's log is, at its `Layout::log_address`: DDR clear of
+/// feo and above its image, where lilmemcap can see it, which it's told
+/// about by `m0::announce`. It's cached on our side; `rockchip::ring` does
+/// the maintenance.
+pub const M0_LOG_SIZE: usize = 4096;
+
+/// Set up an empty log for the core laid out as `layout`, and attach to it.
+pub fn init(layout: &Layout) -> Result<LogReader, RingError> {
+    let base = layout.log_address as usize as *mut u8;
+
+    unsafe {
+        m0log::init(base, M0_LOG_SIZE)?;
+        LogReader::attach(base)
+    }
+}
+
+/// Print everything waiting in the log to the console.
+pub fn drain(log: &mut LogReader) {
+    if let Err(e) = log.drain(|line| println!("[m0] {}", line)) {
+        println!("[m0] log is corrupt: {:?}", e);
+    }
+
+    let dropped = log.dropped();
+    if dropped > 0 {
+        println!("[m0] ... {} lines dropped", dropped);
+    }
+}
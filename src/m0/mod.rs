@@ -0,0 +1,253 @@
+extern crate core;
+extern crate rk3399_tools;
+
+use rockchip::clock::{Clock, ClockError, ClockManager, ClockRegisters};
+use rockchip::delay::{self, Delay};
+use rockchip::sgrf::{self, Master, Security, Sgrf};
+
+use hal::blocking::delay::DelayUs;
+
+pub mod loader;
+pub use self::loader::{load, load_raw, m0_address, LoadError, REMAP_GRANULE};
+
+pub mod ipc;
+pub use self::ipc::{Command, IpcError, M0Link};
+
+pub mod log;
+
+pub mod supervisor;
+pub use self::supervisor::{announce, Health, Supervisor, SupervisorError};
+
+/// Where one core's image, log and status block go, and which mailbox
+/// channel it answers on. Each core gets its own, so more than one could
+/// run at once; its log and status block have to be above its image and
+/// inside the remap window, see `m0_address`.
+///
+/// Only the PMU M0 is driven for now. The PERILP one's resets and boot
+/// remap aren't in ATF or Linux, and until their bits are checked against
+/// the TRM it's safer not to poke at them.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    /// Where the image is copied to, in place of its header's address
+    pub load_address: u32,
+    pub log_address: u32,
+    pub status_address: u32,
+    pub channel: usize,
+}
+
+/// The PMU M0's layout: the image at 0x250000, past the end of feo, then
+/// the log and status block above it.
+pub static PMU_M0_LAYOUT: Layout = Layout {
+    load_address: 0x250000,
+    log_address: 0x2f0000,
+    status_address: 0x2f1000,
+    channel: 0,
+};
+
+/// The Cortex-M0 in the PMU, which ATF uses for DDR frequency changes. Its
+/// boot remap is in the PMUSGRF, and its clocks and resets in the PMUCRU.
+pub struct PmuM0<'a> {
+    pmusgrf: &'a rk3399_tools::PMUSGRF,
+    pmucru: &'a rk3399_tools::PMUCRU,
+
+    // whether we hold references on PMU_M0_CLOCKS
+    clocks_on: bool,
+}
+
+impl<'a> PmuM0<'a> {
+    pub fn new(pmusgrf: &'a rk3399_tools::PMUSGRF, pmucru: &'a rk3399_tools::PMUCRU) -> PmuM0<'a> {
+        PmuM0 {
+            pmusgrf: pmusgrf,
+            pmucru: pmucru,
+            clocks_on: false,
+        }
+    }
+}
+
+// take a reference on each of `names`, unless `on` says we already have;
+// if one can't be had, the ones already taken are given back
+fn enable_clocks<R: ClockRegisters>(on: &mut bool, names: &[&str],
+    clocks: &ClockManager<R>) -> Result<(), ClockError> {
+    if !*on {
+        for (n, name) in names.iter().enumerate() {
+            if let Err(e) = clocks.get(name).and_then(|clk| clocks.enable(clk)) {
+                for name in names[..n].iter() {
+                    let _ = clocks.get(name).and_then(|clk| clocks.disable(clk));
+                }
+                return Err(e);
+            }
+        }
+        *on = true;
+    }
+
+    Ok(())
+}
+
+fn disable_clocks<R: ClockRegisters>(on: &mut bool, names: &[&str],
+    clocks: &ClockManager<R>) -> Result<(), ClockError> {
+    if *on {
+        for name in names.iter() {
+            clocks.disable(clocks.get(name)?)?;
+        }
+        *on = false;
+    }
+
+    Ok(())
+}
+
+/// What the M0 is up to, as far as the CRU can tell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum M0State {
+    /// clocks gated
+    Off,
+
+    /// clocked, but held in reset
+    Reset,
+
+    /// clocked and out of reset
+    Running,
+}
+
+// between releasing the bus and the core from reset
+const RESET_DELAY_US: u32 = 5;
+
+// softrst_con0 bits
+const HRESETN_CM0S_PMU: u32 = 1 << 2;
+const PORESETN_CM0S_PMU: u32 = 1 << 5;
+
+// everything the core needs running; the CRU gates them all from the
+// fclk_cm0s_src_pmu gate, which in turn runs from PPLL or 24MHz
+const PMU_M0_CLOCKS: [&'static str; 4] = [
+    "fclk_cm0s_pmu",
+    "sclk_cm0s_pmu",
+    "hclk_cm0s_pmu",
+    "dclk_cm0s_pmu",
+];
+
+pub trait M0 {
+    /// Where this core's image and buffers live.
+    fn layout(&self) -> &'static Layout;
+
+    fn setup(&mut self, start: u32);
+
+    fn on<R: ClockRegisters>(&mut self, clocks: &ClockManager<R>) -> Result<(), ClockError>;
+
+    /// Hold the core in reset and gate its clocks.
+    fn halt<R: ClockRegisters>(&mut self, clocks: &ClockManager<R>) -> Result<(), ClockError>;
+
+    /// Pulse the core's resets, so it starts again from its reset vector.
+    fn reset(&mut self);
+
+    /// Halt, point the core at a new image and start it again.
+    fn restart<R: ClockRegisters>(&mut self, clocks: &ClockManager<R>,
+        start: u32) -> Result<(), ClockError>;
+
+    fn state<R: ClockRegisters>(&self, clocks: &ClockManager<R>) -> M0State;
+}
+
+fn assert_reset(pmucru: &rk3399_tools::PMUCRU) {
+    pmucru.pmucru_softrst_con0.write(|w| unsafe { w.
+        hresetn_cm0s_pmu_req().set_bit().
+        poresetn_cm0s_pmu_req().set_bit().
+        write_mask().bits(1 << 2 | 1 << 5)
+    });
+}
+
+fn deassert_reset(pmucru: &rk3399_tools::PMUCRU) {
+    // pull hresetn_cm0s_pmu high
+    pmucru.pmucru_softrst_con0.write(|w| unsafe { w.
+        hresetn_cm0s_pmu_req().clear_bit().
+        write_mask().bits(1 << 2)
+    });
+
+    Delay::new(delay::system_timer()).delay_us(RESET_DELAY_US);
+
+    // now pull poresetn_cm0s_pmu high
+    pmucru.pmucru_softrst_con0.write(|w| unsafe { w.
+        poresetn_cm0s_pmu_req().clear_bit().
+        write_mask().bits(1 << 5)
+    });
+}
+
+// WMSK_BIT(x)       => BIT(x + 16)          => 1 << (x + 16)
+// BIT_WITH_WMASK(x) => BIT(x) | WMSK_BIT(x) => (1 << x) | (1 << (x + 16))
+// BITS_WITH_WMASK(x, y, z) -> 
+impl<'a> M0 for PmuM0<'a> {
+    fn layout(&self) -> &'static Layout {
+        &PMU_M0_LAYOUT
+    }
+
+	fn setup(&mut self, start: u32) {
+
+        // put PMU M0 into secure mode
+        Sgrf::new(sgrf::Mmio).set_master(Master::PmuM0, Security::Secure);
+
+        // middle 16 bits
+        self.pmusgrf.pmu_con3.write(|w| unsafe { w.
+            pmu_remap_flash_rom_mid().bits((start >> 12) as u16).
+            write_mask().bits(0xffff)
+        });
+
+        // high 4 bits
+        self.pmusgrf.pmu_con7.write(|w| unsafe { w.
+            pmu_remap_flash_rom_high().bits((start >> 28) as u8).
+            write_mask().bits(0xf)
+        });
+
+        // writes 0x2 to this?
+        // m0_init also disables clk_center1 but probably a bug
+        // but surely we just want to set first bit to 1?
+        self.pmucru.pmucru_gatedis_con0.modify(|_, w| w.
+            clk_pmum0_gating_dis().clear_bit().
+            clk_center1_gating_dis().set_bit()  // FIXME: do we need this?
+        );
+
+        // FIXME: do we actually need this? find out what it does!
+        // write_volatile::<u32>(PMUCRU_CLKGATE_CON(28), 1 << (16 + 5));
+
+    }
+
+    fn on<R: ClockRegisters>(&mut self, clocks: &ClockManager<R>) -> Result<(), ClockError> {
+        // enable clocks
+        enable_clocks(&mut self.clocks_on, &PMU_M0_CLOCKS, clocks)?;
+
+        deassert_reset(self.pmucru);
+
+        Ok(())
+    }
+
+    fn halt<R: ClockRegisters>(&mut self, clocks: &ClockManager<R>) -> Result<(), ClockError> {
+        // reset first, so the core isn't left stopped mid-bus-transaction
+        assert_reset(self.pmucru);
+
+        disable_clocks(&mut self.clocks_on, &PMU_M0_CLOCKS, clocks)
+    }
+
+    fn reset(&mut self) {
+        assert_reset(self.pmucru);
+        deassert_reset(self.pmucru);
+    }
+
+    fn restart<R: ClockRegisters>(&mut self, clocks: &ClockManager<R>,
+        start: u32) -> Result<(), ClockError> {
+        self.halt(clocks)?;
+        self.setup(start);
+        self.on(clocks)
+    }
+
+    fn state<R: ClockRegisters>(&self, clocks: &ClockManager<R>) -> M0State {
+        let gated = [Clock::FclkCm0sPmu, Clock::HclkCm0sPmu].iter()
+            .any(|clk| clocks.is_gated(*clk));
+
+        if gated {
+            return M0State::Off;
+        }
+
+        let resets = self.pmucru.pmucru_softrst_con0.read().bits();
+        if resets & (HRESETN_CM0S_PMU | PORESETN_CM0S_PMU) != 0 {
+            M0State::Reset
+        } else {
+            M0State::Running
+        }
+    }
+}
\ No newline at end of file
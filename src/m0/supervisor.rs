@@ -0,0 +1,163 @@
+// Watching over lilmemcap.
+//
+// lilmemcap bumps a heartbeat in the status block (see rockchip::m0status)
+// from its main loop. If it stops for too many checks in a row, the M0 is
+// assumed hung or crashed: whatever crash record it left is picked up, and
+// the core is halted, its image reloaded and started again.
+
+use rockchip::clock::{ClockError, ClockManager, ClockRegisters};
+use rockchip::m0status::{CrashRecord, Status};
+
+use super::{load, m0_address, IpcError, Layout, LoadError, M0, M0Link};
+use super::ipc::Command;
+
+/// An error from restarting the M0
+#[derive(Debug)]
+pub enum SupervisorError {
+    /// Image didn't reload
+    Load(LoadError),
+
+    /// Couldn't stop or start the core's clocks
+    Clock(ClockError),
+
+    /// Restarted it, but couldn't tell it where its buffers are, for a
+    /// reason that waiting won't fix
+    Announce(IpcError),
+
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// How the M0 looked at the last check.
+#[derive(Debug)]
+pub enum Health {
+    /// Heartbeat has moved since the last check
+    Alive,
+
+    /// Heartbeat hasn't moved for this many checks
+    Stale(u32),
+
+    /// Gave up waiting and restarted it; carries the crash record if it
+    /// left one
+    Restarted(Option<CrashRecord>),
+}
+
+/// Tell lilmemcap, started at `start`, where `layout` puts its log and
+/// status block; needed after every start. They go as addresses in its own
+/// view of memory.
+pub fn announce(link: &mut M0Link, layout: &Layout, start: u32) -> Result<(), IpcError> {
+    for &(command, addr) in [(Command::SetLogBuffer, layout.log_address),
+            (Command::SetStatusBlock, layout.status_address)].iter() {
+        let m0_addr = m0_address(addr, start).ok_or(IpcError::NotVisible(addr))?;
+        link.call(command, m0_addr)?;
+    }
+
+    Ok(())
+}
+
+pub struct Supervisor<'a> {
+    layout: &'a Layout,
+    status: Status,
+    image: &'a [u8],
+    last: Option<u32>,
+    stale: u32,
+    max_stale: u32,
+
+    // where the image went, if it's been restarted and hasn't heard from
+    // `announce` yet
+    unannounced: Option<u32>,
+}
+
+impl<'a> Supervisor<'a> {
+    /// Watch an M0 laid out as `layout` and running `image`, restarting it
+    /// once the heartbeat has been stale for `max_stale` checks. Clears the
+    /// status block, so call it before starting the M0.
+    pub fn new(layout: &'a Layout, image: &'a [u8], max_stale: u32) -> Supervisor<'a> {
+        let status = unsafe { Status::at(layout.status_address as usize as *mut u8) };
+        status.clear();
+
+        Supervisor {
+            layout: layout,
+            status: status,
+            image: image,
+            last: None,
+            stale: 0,
+            max_stale: max_stale,
+            unannounced: None,
+        }
+    }
+
+    /// Call every so often; the interval, times `max_stale`, is how long
+    /// lilmemcap can go quiet before it's restarted.
+    pub fn check<M, R>(&mut self, m0: &mut M, clocks: &ClockManager<R>,
+        link: &mut M0Link) -> Result<Health, SupervisorError>
+    where
+        M: M0,
+        R: ClockRegisters,
+    {
+        self.announce(link)?;
+
+        // not being up yet counts as stale too, so a hang during boot is
+        // caught the same way
+        let beat = if self.status.is_up() { Some(self.status.heartbeat()) } else { None };
+
+        if beat.is_some() && beat != self.last {
+            self.last = beat;
+            self.stale = 0;
+            return Ok(Health::Alive);
+        }
+
+        self.stale += 1;
+        if self.stale < self.max_stale {
+            return Ok(Health::Stale(self.stale));
+        }
+
+        let crash = self.status.crash();
+        self.unannounced = Some(self.restart(m0, clocks)?);
+
+        // it'll want to know where everything is again
+        self.announce(link)?;
+
+        Ok(Health::Restarted(crash))
+    }
+
+    // tell a restarted M0 where its buffers are. If it isn't answering yet,
+    // it's tried again on every check until it does; until then it has
+    // nowhere to beat, so it'll be restarted again if it never does
+    fn announce(&mut self, link: &mut M0Link) -> Result<(), SupervisorError> {
+        let start = match self.unannounced {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+
+        match announce(link, self.layout, start) {
+            Ok(()) => {
+                self.unannounced = None;
+                Ok(())
+            },
+            Err(IpcError::Timeout) => Ok(()),
+            Err(e) => Err(SupervisorError::Announce(e)),
+        }
+    }
+
+    // returns where the image went
+    fn restart<M, R>(&mut self, m0: &mut M,
+        clocks: &ClockManager<R>) -> Result<u32, SupervisorError>
+    where
+        M: M0,
+        R: ClockRegisters,
+    {
+        m0.halt(clocks).map_err(SupervisorError::Clock)?;
+
+        // it may have scribbled over itself on the way down
+        let start = load(self.image, self.layout).map_err(SupervisorError::Load)?;
+
+        self.status.clear();
+        self.last = None;
+        self.stale = 0;
+
+        m0.restart(clocks, start).map_err(SupervisorError::Clock)?;
+
+        Ok(start)
+    }
+}
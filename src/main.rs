@@ -18,29 +18,53 @@ mod serial;
 mod lang_items;
 
 mod m0;
-use m0::{PerilpM0, M0};
+use m0::{PmuM0, M0, M0State, M0Link, Command};
+
+#[cfg(feature = "m0-supervisor")]
+use m0::{Health, Supervisor};
+use rockchip::delay::{self, Delay};
+use hal::blocking::delay::DelayMs;
 
 extern crate rk3399_tools;
+extern crate rockchip;
+extern crate m0image;
+extern crate nb;
+extern crate embedded_hal as hal;
+
+use rockchip::clock::{ClockManager, Mmio};
+use rockchip::mailbox::Mailbox;
+use rockchip::pinctrl::{self, Pinctrl};
+use rockchip::sgrf::{self, Sgrf};
+
+// packed by m0pack to load at 0x250000, where PMU_M0_LAYOUT puts it: past
+// the end of feo, and on a 4KiB boundary so the M0 can be remapped onto it
+static LILMEMCAP: &'static [u8] = include_bytes!("../target/lilmemcap.img");
 
-const M0_START_ADDRESS:u32 = 0x250000;
+// a second between draining the M0's log (and checking its heartbeat), and
+// five stale checks before the M0 is restarted
+const M0_CHECK_INTERVAL_MS: u32 = 1000;
+#[cfg(feature = "m0-supervisor")]
+const M0_MAX_STALE_CHECKS: u32 = 5;
 
 fn main() {
 	println!("Hello from feo!");
 
-	let pmugrf = unsafe { &*rk3399_tools::PMUGRF.get() };
 	let pmucru = unsafe { &*rk3399_tools::PMUCRU.get() };
 	let pmusgrf = unsafe { &*rk3399_tools::PMUSGRF.get() };
 
+	let clocks = ClockManager::new(Mmio);
+
+	// see what uboot left us with
+	print!("{}", clocks.summary());
+	clocks.check(|issue| println!("clock: {}", issue));
+
+	let pins = Pinctrl::new(pinctrl::Mmio);
+	pinctrl::check(|issue| println!("pinctrl: {:?}", issue));
+
 	// setup iomux to select PMU JTAG
-	pmugrf.pmugrf_gpio1b_iomux.modify(|_, w| unsafe {
-		w.
-		write_enable().bits(
-			3 << 4 |
-			3 << 2
-		).
-		gpio1b1_sel().bits(1). 	// pmum0jtag_tck
-		gpio1b2_sel().bits(1)	// pmum0jtag_tms
-	});
+	if let Err(e) = pins.claim_group("pmu_m0_jtag", "m0") {
+		println!("Couldn't claim M0 JTAG pins: {:?}", e);
+	}
 
 	// and enable SWD for the core
 	pmusgrf.pmu_con0.modify(|_, w| unsafe { w.
@@ -52,11 +76,72 @@ fn main() {
 	// into unsecure mode, but we'll see how we go...
 
 	// start the M0
-	let mut littleguy = PerilpM0 { };
+	let mut littleguy = PmuM0::new(pmusgrf, pmucru);
 	
+	// clears the status block, so has to come before the M0 starts
+	#[cfg(feature = "m0-supervisor")]
+	let mut supervisor = Supervisor::new(littleguy.layout(), LILMEMCAP, M0_MAX_STALE_CHECKS);
+
 	// println!("Booting M0 at 0x{:x}...", M0_START_ADDRESS);
-	littleguy.setup (pmusgrf, pmucru, M0_START_ADDRESS);
-	littleguy.on (pmucru);
+	match m0::load(LILMEMCAP, littleguy.layout()) {
+		Ok(start) => {
+			littleguy.setup(start);
+			print!("Security configuration:\n{}", Sgrf::new(sgrf::Mmio).config());
+
+			if let Err(e) = littleguy.on(&clocks) {
+				println!("Couldn't start M0: {:?}", e);
+			}
+
+			if littleguy.state(&clocks) != M0State::Running {
+				println!("M0 didn't come up");
+			}
+
+			let mut link = M0Link::new(Mailbox::new(), littleguy.layout().channel);
+			match link.call(Command::Version, 0) {
+				Ok(version) => println!("lilmemcap version {}", version),
+				Err(e) => println!("M0 isn't answering: {:?}", e),
+			}
+
+			let mut log = match m0::log::init(littleguy.layout()) {
+				Ok(log) => Some(log),
+				Err(e) => {
+					println!("Couldn't set up M0 log: {:?}", e);
+					None
+				},
+			};
+
+			if let Err(e) = m0::announce(&mut link, littleguy.layout(), start) {
+				println!("Couldn't tell M0 where its buffers are: {:?}", e);
+			}
+
+			let mut delay = Delay::new(delay::system_timer());
+
+			// the M0 keeps logging for as long as it runs, so keep printing
+			loop {
+				if let Some(ref mut log) = log {
+					m0::log::drain(log);
+				}
+
+				delay.delay_ms(M0_CHECK_INTERVAL_MS);
+
+				#[cfg(feature = "m0-supervisor")]
+				match supervisor.check(&mut littleguy, &clocks, &mut link) {
+					Ok(Health::Restarted(crash)) => {
+						println!("M0 stopped responding; restarted it");
+						if let Some(crash) = crash {
+							println!("M0 crashed: {}", crash);
+						}
+					},
+					Ok(_) => (),
+					Err(e) => {
+						println!("Couldn't restart M0: {:?}", e);
+						break;
+					},
+				}
+			}
+		},
+		Err(e) => println!("Couldn't load M0 image: {:?}", e),
+	}
 
 	unsafe { asm!("wfi"); };
 }